@@ -0,0 +1,145 @@
+use std::time::{Duration, Instant};
+use orderbook::{parse_order, DeserializedCommand, FlowConfig, InboundMessage, OrderFlow, Orderbook};
+
+// Run with `cargo bench`. Each scenario fills the book from one flow, then
+// times every operation of a second one; latencies are per call, with orders
+// and cancels reported separately.
+
+const WARM_UP: usize = 50_000;
+const MEASURED: usize = 200_000;
+
+struct Scenario {
+    name: &'static str,
+    resting: FlowConfig,
+    incoming: FlowConfig
+}
+
+fn scenarios() -> Vec<Scenario> {
+    let passive = FlowConfig {mean_offset: 50.0, price_deviation: 20.0, cancel_ratio: 0.0, ..FlowConfig::default()};
+    vec![
+        Scenario {
+            name: "deep book",
+            resting: FlowConfig {mean_offset: 500.0, price_deviation: 200.0, ..passive.clone()},
+            incoming: FlowConfig {seed: 2, ..FlowConfig::default()}
+        },
+        Scenario {
+            name: "small peak icebergs",
+            resting: FlowConfig {iceberg_ratio: 0.9, peak_size: 3, max_quantity: 1_000, ..passive.clone()},
+            incoming: FlowConfig {seed: 2, mean_offset: -20.0, iceberg_ratio: 0.9, peak_size: 3, max_quantity: 1_000, cancel_ratio: 0.0, ..FlowConfig::default()}
+        },
+        Scenario {
+            name: "heavy cancels",
+            resting: passive.clone(),
+            incoming: FlowConfig {seed: 2, cancel_ratio: 0.6, ..FlowConfig::default()}
+        },
+        Scenario {
+            name: "single level",
+            resting: FlowConfig {mean_offset: 0.0, price_deviation: 0.0, ..passive},
+            incoming: FlowConfig {seed: 2, mean_offset: 0.0, price_deviation: 0.0, cancel_ratio: 0.0, ..FlowConfig::default()}
+        }
+    ]
+}
+
+fn apply(orderbook: &mut Orderbook, message: InboundMessage) {
+    match message {
+        InboundMessage::Order(deserialized_order) => {
+            orderbook.process_order(&mut parse_order(deserialized_order));
+        }
+        InboundMessage::Command(DeserializedCommand::Cancel {id}) => {
+            orderbook.cancel_order(id);
+        }
+        InboundMessage::Command(_) => ()
+    }
+}
+
+// Incoming ids start after the resting ones so they never collide.
+fn offset_ids(message: &mut InboundMessage) {
+    match message {
+        InboundMessage::Order(deserialized_order) => deserialized_order.order_core_mut().id += WARM_UP as u64,
+        InboundMessage::Command(DeserializedCommand::Cancel {id}) => *id += WARM_UP as u64,
+        InboundMessage::Command(_) => ()
+    }
+}
+
+fn report(name: &str, operation: &str, mut latencies: Vec<Duration>) {
+    if latencies.is_empty() {
+        return;
+    }
+    latencies.sort();
+    let total: Duration = latencies.iter().sum();
+    let percentile = |fraction: f64| latencies[((latencies.len() - 1) as f64 * fraction) as usize].as_nanos();
+    println!(
+        "{:<20} {:<14} {:>10.0} ops/s  p50 {:>7} ns  p99 {:>7} ns  p99.9 {:>8} ns  max {:>9} ns",
+        name,
+        operation,
+        latencies.len() as f64 / total.as_secs_f64(),
+        percentile(0.5),
+        percentile(0.99),
+        percentile(0.999),
+        latencies.last().unwrap().as_nanos()
+    );
+}
+
+fn bench_scenario(scenario: &Scenario) {
+    let mut orderbook = Orderbook::new();
+    for event in OrderFlow::new(scenario.resting.clone()).take(WARM_UP) {
+        apply(&mut orderbook, event.message);
+    }
+
+    let messages: Vec<InboundMessage> = OrderFlow::new(scenario.incoming.clone())
+        .take(MEASURED)
+        .map(|mut event| {
+            offset_ids(&mut event.message);
+            event.message
+        })
+        .collect();
+    let mut order_latencies = Vec::with_capacity(MEASURED);
+    let mut cancel_latencies = Vec::new();
+    for message in messages {
+        let is_order = matches!(message, InboundMessage::Order(_));
+        let start = Instant::now();
+        apply(&mut orderbook, message);
+        let latency = start.elapsed();
+        if is_order {
+            order_latencies.push(latency);
+        } else {
+            cancel_latencies.push(latency);
+        }
+    }
+    report(scenario.name, "process_order", order_latencies);
+    report(scenario.name, "cancel_order", cancel_latencies);
+
+    let latencies = (0..100)
+        .map(|_| {
+            let start = Instant::now();
+            std::hint::black_box(orderbook.get_orders());
+            start.elapsed()
+        })
+        .collect();
+    report(scenario.name, "get_orders", latencies);
+}
+
+fn bench_parsing() {
+    let lines: Vec<String> = OrderFlow::new(FlowConfig::default())
+        .take(MEASURED)
+        .map(|event| serde_json::to_string(&event.message).unwrap())
+        .collect();
+    let latencies = lines
+        .iter()
+        .map(|line| {
+            let start = Instant::now();
+            if let InboundMessage::Order(deserialized_order) = serde_json::from_str(line).unwrap() {
+                std::hint::black_box(parse_order(deserialized_order));
+            }
+            start.elapsed()
+        })
+        .collect();
+    report("json", "parse", latencies);
+}
+
+fn main() {
+    for scenario in scenarios().iter() {
+        bench_scenario(scenario);
+    }
+    bench_parsing();
+}
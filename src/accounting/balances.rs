@@ -0,0 +1,327 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use serde::{Deserialize, Serialize};
+
+use crate::matching_engine::order::{FillEvent, Order, OrderSide};
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Balance {
+    pub available: u64,
+    pub reserved: u64
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalanceReport {
+    pub account: String,
+    pub asset: String,
+    #[serde(flatten)]
+    pub balance: Balance
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BalanceError {
+    InsufficientFunds {
+        account: String,
+        asset: String,
+        required: u64,
+        available: u64
+    },
+    DuplicateReservation(u64),
+    AmountOverflow {
+        price: u64,
+        quantity: u64
+    },
+    SupplyOverflow {
+        asset: String,
+        amount: u64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Reservation {
+    account: String,
+    order_side: OrderSide,
+    limit_price: u64,
+    remaining_quantity: u64
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalanceSnapshot {
+    balances: Vec<((String, String), Balance)>,
+    reservations: HashMap<u64, Reservation>
+}
+
+// Funds only enter through deposits and fills move them between accounts, so
+// keeping each asset's total within a u64 keeps every balance within one too.
+pub struct BalanceLedger {
+    base_asset: String,
+    quote_asset: String,
+    balances: BTreeMap<(String, String), Balance>,
+    supply: HashMap<String, u64>,
+    reservations: HashMap<u64, Reservation>
+}
+
+impl fmt::Display for BalanceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BalanceError::InsufficientFunds {account, asset, required, available} =>
+                write!(f, "insufficient {} for account {}: required {}, available {}", asset, account, required, available),
+            BalanceError::DuplicateReservation(order_id) =>
+                write!(f, "order {} already holds a reservation", order_id),
+            BalanceError::AmountOverflow {price, quantity} =>
+                write!(f, "amount of {} at {} is out of range", quantity, price),
+            BalanceError::SupplyOverflow {asset, amount} =>
+                write!(f, "deposit of {} would take the total {} out of range", amount, asset)
+        }
+    }
+}
+
+impl BalanceLedger {
+    pub fn new(base_asset: &str, quote_asset: &str) -> Self {
+        BalanceLedger {
+            base_asset: base_asset.to_string(),
+            quote_asset: quote_asset.to_string(),
+            balances: BTreeMap::new(),
+            supply: HashMap::new(),
+            reservations: HashMap::new()
+        }
+    }
+
+    // The asset totals are not stored, fills only move funds between accounts
+    // so they are the sums of the restored balances.
+    pub fn restore(base_asset: &str, quote_asset: &str, snapshot: BalanceSnapshot) -> Self {
+        let mut supply = HashMap::new();
+        for ((_, asset), balance) in snapshot.balances.iter() {
+            let total: &mut u64 = supply.entry(asset.clone()).or_default();
+            *total = total.saturating_add(balance.available).saturating_add(balance.reserved);
+        }
+        BalanceLedger {
+            base_asset: base_asset.to_string(),
+            quote_asset: quote_asset.to_string(),
+            balances: snapshot.balances.into_iter().collect(),
+            supply,
+            reservations: snapshot.reservations
+        }
+    }
+
+    pub fn snapshot(&self) -> BalanceSnapshot {
+        BalanceSnapshot {
+            balances: self.balances.iter().map(|(owner, balance)| (owner.clone(), *balance)).collect(),
+            reservations: self.reservations.clone()
+        }
+    }
+
+    pub fn check_deposit(&self, asset: &str, amount: u64) -> Result<u64, BalanceError> {
+        self.supply
+            .get(asset)
+            .copied()
+            .unwrap_or(0)
+            .checked_add(amount)
+            .ok_or_else(|| BalanceError::SupplyOverflow {asset: asset.to_string(), amount})
+    }
+
+    pub fn deposit(&mut self, account: &str, asset: &str, amount: u64) -> Result<(), BalanceError> {
+        let supply = self.check_deposit(asset, amount)?;
+        self.supply.insert(asset.to_string(), supply);
+        self.balance_mut(account, asset).available += amount;
+        Ok(())
+    }
+
+    pub fn balance(&self, account: &str, asset: &str) -> Balance {
+        self.balances
+            .get(&(account.to_string(), asset.to_string()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn balances(&self) -> Vec<BalanceReport> {
+        self.balances
+            .iter()
+            .map(|((account, asset), balance)| BalanceReport {
+                account: account.clone(),
+                asset: asset.clone(),
+                balance: *balance
+            })
+            .collect()
+    }
+
+    // Checks that `reserve` would accept the order once the reservation of
+    // `replaced_order_id`, if there is one, has been released.
+    pub fn check_reserve(&self, account: &str, order: &Order, replaced_order_id: Option<u64>) -> Result<(), BalanceError> {
+        let order_id = order.order_key.id;
+        if self.reservations.contains_key(&order_id) && replaced_order_id != Some(order_id) {
+            return Err(BalanceError::DuplicateReservation(order_id));
+        }
+
+        let (asset, required) = self.reservation_amount(&reservation(account, order))?;
+        let released = match replaced_order_id.and_then(|replaced_order_id| self.reservations.get(&replaced_order_id)) {
+            Some(replaced) if replaced.account == account => match self.reservation_amount(replaced) {
+                Ok((released_asset, amount)) if released_asset == asset => amount,
+                _ => 0
+            },
+            _ => 0
+        };
+        // Both amounts are part of the same balance, so their sum stays
+        // within the asset supply.
+        let available = self.balance(account, &asset).available + released;
+        if available < required {
+            return Err(BalanceError::InsufficientFunds {
+                account: account.to_string(),
+                asset,
+                required,
+                available
+            });
+        }
+        Ok(())
+    }
+
+    // Locks the funds the whole order may consume, including the hidden part
+    // of an iceberg. Buys reserve quote at the limit price, sells reserve base.
+    pub fn reserve(&mut self, account: &str, order: &Order) -> Result<(), BalanceError> {
+        self.check_reserve(account, order, None)?;
+        let reservation = reservation(account, order);
+        let (asset, required) = self.reservation_amount(&reservation)?;
+        let balance = self.balance_mut(account, &asset);
+        balance.available -= required;
+        balance.reserved += required;
+        self.reservations.insert(order.order_key.id, reservation);
+        Ok(())
+    }
+
+    pub fn release(&mut self, order_id: u64) {
+        // The remaining amount only shrinks after `reserve` checked it.
+        if let Some(reservation) = self.reservations.remove(&order_id) {
+            let (asset, amount) = self.reservation_amount(&reservation).unwrap_or_default();
+            let balance = self.balance_mut(&reservation.account, &asset);
+            balance.reserved -= amount;
+            balance.available += amount;
+        }
+    }
+
+    pub fn on_fill(&mut self, fill_event: &FillEvent) {
+        self.settle(fill_event.buy_order_id, fill_event);
+        self.settle(fill_event.sell_order_id, fill_event);
+    }
+
+    fn settle(&mut self, order_id: u64, fill_event: &FillEvent) {
+        let reservation = match self.reservations.get_mut(&order_id) {
+            None => return,
+            Some(reservation) => reservation
+        };
+        reservation.remaining_quantity -= fill_event.quantity;
+        let reservation = reservation.clone();
+        if reservation.remaining_quantity == 0 {
+            self.reservations.remove(&order_id);
+        }
+
+        let (base_asset, quote_asset) = (self.base_asset.clone(), self.quote_asset.clone());
+        // Every fill is part of a buy order whose notional at its limit price
+        // `reserve` checked, so neither product saturates, and the credits
+        // below stay within the asset supply.
+        let notional = fill_event.price.saturating_mul(fill_event.quantity);
+        match reservation.order_side {
+            OrderSide::Buy => {
+                let reserved = reservation.limit_price.saturating_mul(fill_event.quantity);
+                let quote = self.balance_mut(&reservation.account, &quote_asset);
+                quote.reserved -= reserved;
+                quote.available += reserved - notional;
+                self.balance_mut(&reservation.account, &base_asset).available += fill_event.quantity;
+            }
+            OrderSide::Sell => {
+                self.balance_mut(&reservation.account, &base_asset).reserved -= fill_event.quantity;
+                self.balance_mut(&reservation.account, &quote_asset).available += notional;
+            }
+        }
+    }
+
+    fn reservation_amount(&self, reservation: &Reservation) -> Result<(String, u64), BalanceError> {
+        match reservation.order_side {
+            OrderSide::Buy => match reservation.limit_price.checked_mul(reservation.remaining_quantity) {
+                None => Err(BalanceError::AmountOverflow {price: reservation.limit_price, quantity: reservation.remaining_quantity}),
+                Some(amount) => Ok((self.quote_asset.clone(), amount))
+            },
+            OrderSide::Sell => Ok((self.base_asset.clone(), reservation.remaining_quantity))
+        }
+    }
+
+    fn balance_mut(&mut self, account: &str, asset: &str) -> &mut Balance {
+        self.balances
+            .entry((account.to_string(), asset.to_string()))
+            .or_default()
+    }
+}
+
+fn reservation(account: &str, order: &Order) -> Reservation {
+    Reservation {
+        account: account.to_string(),
+        order_side: order.order_key.order_side,
+        limit_price: order.order_key.price,
+        remaining_quantity: order.quantity + order.iceberg.map_or(0, |iceberg| iceberg.hidden_quantity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::test_support::limit;
+
+    #[test]
+    fn reserve_including_hidden_quantity() {
+        let mut ledger = BalanceLedger::new("BTC", "USD");
+        ledger.deposit("alice", "USD", 50_000).unwrap();
+
+        let iceberg = Order {
+            iceberg: Some(IcebergOrder {peak_size: 100, hidden_quantity: 400}),
+            ..limit(1, OrderSide::Buy, 100, 100)
+        };
+        assert_eq!(ledger.reserve("alice", &iceberg), Ok(()));
+        assert_eq!(ledger.balance("alice", "USD"), Balance {available: 0, reserved: 50_000});
+
+        assert_eq!(ledger.reserve("alice", &limit(2, OrderSide::Buy, 1, 1)), Err(BalanceError::InsufficientFunds {
+            account: "alice".to_string(),
+            asset: "USD".to_string(),
+            required: 1,
+            available: 0
+        }));
+
+        ledger.release(1);
+        assert_eq!(ledger.balance("alice", "USD"), Balance {available: 50_000, reserved: 0});
+
+        assert_eq!(
+            ledger.reserve("alice", &limit(3, OrderSide::Buy, u64::MAX, 2)),
+            Err(BalanceError::AmountOverflow {price: u64::MAX, quantity: 2})
+        );
+        assert_eq!(ledger.balance("alice", "USD"), Balance {available: 50_000, reserved: 0});
+
+        assert_eq!(
+            ledger.deposit("bob", "USD", u64::MAX),
+            Err(BalanceError::SupplyOverflow {asset: "USD".to_string(), amount: u64::MAX})
+        );
+        assert_eq!(ledger.balance("bob", "USD"), Balance::default());
+        assert_eq!(ledger.deposit("bob", "BTC", u64::MAX), Ok(()));
+    }
+
+    #[test]
+    fn settle_fills_with_price_improvement() {
+        let mut orderbook = Orderbook::new();
+        let mut ledger = BalanceLedger::new("BTC", "USD");
+        ledger.deposit("seller", "BTC", 10).unwrap();
+        ledger.deposit("buyer", "USD", 1_100).unwrap();
+
+        let mut sell_order = limit(1, OrderSide::Sell, 100, 10);
+        ledger.reserve("seller", &sell_order).unwrap();
+        orderbook.process_order(&mut sell_order);
+
+        let mut buy_order = limit(2, OrderSide::Buy, 110, 10);
+        ledger.reserve("buyer", &buy_order).unwrap();
+        orderbook.process_order(&mut buy_order).iter().for_each(|event| ledger.on_fill(event));
+
+        assert_eq!(ledger.balance("buyer", "USD"), Balance {available: 100, reserved: 0});
+        assert_eq!(ledger.balance("buyer", "BTC"), Balance {available: 10, reserved: 0});
+        assert_eq!(ledger.balance("seller", "USD"), Balance {available: 1_000, reserved: 0});
+        assert_eq!(ledger.balance("seller", "BTC"), Balance {available: 0, reserved: 0});
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::matching_engine::order::{FillEvent, OrderSide};
+
+const BASIS_POINTS: i128 = 10_000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeeTier {
+    pub min_monthly_volume: u64,
+    pub maker_rate_bps: i64,
+    pub taker_rate_bps: i64
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeSchedule {
+    tiers: Vec<FeeTier>
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeeAnnotatedFill {
+    #[serde(flatten)]
+    pub fill_event: FillEvent,
+    pub taker_side: OrderSide,
+    pub buy_fee: i64,
+    pub sell_fee: i64
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeeSnapshot {
+    owners: HashMap<u64, String>,
+    monthly_volumes: HashMap<String, u64>
+}
+
+pub struct FeeEngine {
+    schedule: FeeSchedule,
+    owners: HashMap<u64, String>,
+    monthly_volumes: HashMap<String, u64>
+}
+
+impl FeeSchedule {
+    pub fn flat(maker_rate_bps: i64, taker_rate_bps: i64) -> Self {
+        FeeSchedule::tiered(vec![FeeTier {min_monthly_volume: 0, maker_rate_bps, taker_rate_bps}])
+    }
+
+    pub fn tiered(mut tiers: Vec<FeeTier>) -> Self {
+        tiers.sort_by_key(|tier| tier.min_monthly_volume);
+        FeeSchedule {tiers}
+    }
+
+    pub fn tiers(&self) -> &[FeeTier] {
+        &self.tiers
+    }
+
+    pub fn tier(&self, monthly_volume: u64) -> FeeTier {
+        self.tiers
+            .iter()
+            .rev()
+            .find(|tier| tier.min_monthly_volume <= monthly_volume)
+            .copied()
+            .unwrap_or(FeeTier {min_monthly_volume: 0, maker_rate_bps: 0, taker_rate_bps: 0})
+    }
+}
+
+impl Default for FeeSchedule {
+    fn default() -> Self {
+        FeeSchedule::flat(0, 0)
+    }
+}
+
+// Negative rates are rebates. Amounts are rounded up, so charges round in the
+// exchange's favour and rebates round towards zero. Fees beyond the i64 range
+// saturate.
+pub fn fee_amount(price: u64, quantity: u64, rate_bps: i64) -> i64 {
+    let amount = (price as i128).checked_mul(quantity as i128).and_then(|notional| notional.checked_mul(rate_bps as i128));
+    let fee = match amount {
+        None if rate_bps > 0 => i128::MAX,
+        None => i128::MIN,
+        Some(amount) if amount > 0 => (amount - 1) / BASIS_POINTS + 1,
+        Some(amount) => amount / BASIS_POINTS
+    };
+    fee.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+}
+
+impl FeeEngine {
+    pub fn new(schedule: FeeSchedule) -> Self {
+        FeeEngine {
+            schedule,
+            owners: HashMap::new(),
+            monthly_volumes: HashMap::new()
+        }
+    }
+
+    pub fn restore(schedule: FeeSchedule, snapshot: FeeSnapshot) -> Self {
+        FeeEngine {
+            schedule,
+            owners: snapshot.owners,
+            monthly_volumes: snapshot.monthly_volumes
+        }
+    }
+
+    pub fn snapshot(&self) -> FeeSnapshot {
+        FeeSnapshot {
+            owners: self.owners.clone(),
+            monthly_volumes: self.monthly_volumes.clone()
+        }
+    }
+
+    pub fn register_order(&mut self, order_id: u64, account: &str) {
+        self.owners.insert(order_id, account.to_string());
+    }
+
+    pub fn forget_order(&mut self, order_id: u64) {
+        self.owners.remove(&order_id);
+    }
+
+    pub fn monthly_volume(&self, account: &str) -> u64 {
+        self.monthly_volumes.get(account).copied().unwrap_or(0)
+    }
+
+    pub fn reset_monthly_volumes(&mut self) {
+        self.monthly_volumes.clear();
+    }
+
+    pub fn annotate(&mut self, fill_event: &FillEvent, taker_side: OrderSide) -> FeeAnnotatedFill {
+        let buy_fee = self.charge(fill_event.buy_order_id, fill_event, taker_side == OrderSide::Buy);
+        let sell_fee = self.charge(fill_event.sell_order_id, fill_event, taker_side == OrderSide::Sell);
+        FeeAnnotatedFill {fill_event: *fill_event, taker_side, buy_fee, sell_fee}
+    }
+
+    fn charge(&mut self, order_id: u64, fill_event: &FillEvent, is_taker: bool) -> i64 {
+        let account = self.owners.get(&order_id).cloned().unwrap_or_default();
+        let monthly_volume = self.monthly_volumes.entry(account).or_insert(0);
+        let tier = self.schedule.tier(*monthly_volume);
+        *monthly_volume += fill_event.quantity;
+
+        let rate_bps = if is_taker { tier.taker_rate_bps } else { tier.maker_rate_bps };
+        fee_amount(fill_event.price, fill_event.quantity, rate_bps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn fee_rounding() {
+        assert_eq!(fee_amount(100, 15, 10), 2);
+        assert_eq!(fee_amount(100, 100, 10), 10);
+        assert_eq!(fee_amount(100, 15, -10), -1);
+        assert_eq!(fee_amount(100, 15, 0), 0);
+        assert_eq!(fee_amount(u64::MAX, u64::MAX, 10), i64::MAX);
+        assert_eq!(fee_amount(u64::MAX, u64::MAX, -10), i64::MIN);
+    }
+
+    #[test]
+    fn maker_rebate_and_taker_fee() {
+        let mut fee_engine = FeeEngine::new(FeeSchedule::flat(-2, 5));
+        fee_engine.register_order(1, "maker");
+        fee_engine.register_order(2, "taker");
+
+        let fill_event = FillEvent {buy_order_id: 1, sell_order_id: 2, price: 1000, quantity: 100};
+        let annotated = fee_engine.annotate(&fill_event, OrderSide::Sell);
+        assert_eq!(annotated, FeeAnnotatedFill {fill_event, taker_side: OrderSide::Sell, buy_fee: -20, sell_fee: 50});
+        assert_eq!(fee_engine.monthly_volume("maker"), 100);
+        assert_eq!(fee_engine.monthly_volume("taker"), 100);
+    }
+
+    #[test]
+    fn tiers_by_monthly_volume() {
+        let mut fee_engine = FeeEngine::new(FeeSchedule::tiered(vec![
+            FeeTier {min_monthly_volume: 1000, maker_rate_bps: 0, taker_rate_bps: 3},
+            FeeTier {min_monthly_volume: 0, maker_rate_bps: 1, taker_rate_bps: 5}
+        ]));
+        fee_engine.register_order(1, "a");
+        fee_engine.register_order(2, "b");
+
+        let fill_event = FillEvent {buy_order_id: 1, sell_order_id: 2, price: 100, quantity: 1000};
+        assert_eq!(fee_engine.annotate(&fill_event, OrderSide::Buy).buy_fee, 50);
+        assert_eq!(fee_engine.annotate(&fill_event, OrderSide::Buy).buy_fee, 30);
+
+        fee_engine.reset_monthly_volumes();
+        assert_eq!(fee_engine.annotate(&fill_event, OrderSide::Buy).buy_fee, 50);
+    }
+}
@@ -0,0 +1,3 @@
+pub mod positions;
+pub mod fees;
+pub mod balances;
@@ -0,0 +1,299 @@
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+use crate::matching_engine::order::FillEvent;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MarkMethod {
+    Mid,
+    LastTrade
+}
+
+// Amounts are kept in i128. A fill that would take them out of range leaves
+// the position as it was and marks it as overflowed, so its figures are known
+// to be incomplete.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Position {
+    pub net_quantity: i128,
+    pub cost_basis: i128,
+    pub realized_pnl: i128,
+    pub overflowed: bool
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PositionReport {
+    pub account: String,
+    pub instrument: String,
+    pub net_quantity: i128,
+    pub average_entry_price: Option<f64>,
+    pub realized_pnl: i128,
+    pub unrealized_pnl: Option<i128>,
+    pub mark_price: Option<u64>,
+    pub overflowed: bool
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Marks {
+    last_trade: Option<u64>,
+    mid: Option<u64>
+}
+
+// Positions are keyed by (account, instrument), which JSON cannot use as an
+// object key, so they are saved as a list of pairs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PositionSnapshot {
+    owners: HashMap<u64, (String, String)>,
+    positions: Vec<((String, String), Position)>,
+    marks: HashMap<String, Marks>
+}
+
+pub struct PositionKeeper {
+    mark_method: MarkMethod,
+    owners: HashMap<u64, (String, String)>,
+    positions: BTreeMap<(String, String), Position>,
+    marks: HashMap<String, Marks>
+}
+
+impl FromStr for MarkMethod {
+    type Err = String;
+
+    fn from_str(mark_method: &str) -> Result<Self, Self::Err> {
+        match mark_method {
+            "mid" => Ok(MarkMethod::Mid),
+            "last" => Ok(MarkMethod::LastTrade),
+            _ => Err(format!("unknown mark method {}", mark_method))
+        }
+    }
+}
+
+impl Position {
+    pub fn apply_fill(&mut self, signed_quantity: i128, price: u64) {
+        match self.filled(signed_quantity, price as i128) {
+            Some(position) => *self = position,
+            None => self.overflowed = true
+        }
+    }
+
+    fn filled(&self, quantity: i128, price: i128) -> Option<Position> {
+        let (mut net_quantity, mut cost_basis, mut realized_pnl) = (self.net_quantity, self.cost_basis, self.realized_pnl);
+        if net_quantity == 0 || net_quantity.signum() == quantity.signum() {
+            net_quantity = net_quantity.checked_add(quantity)?;
+            cost_basis = cost_basis.checked_add(quantity.checked_mul(price)?)?;
+        } else {
+            let closing_quantity = std::cmp::min(quantity.abs(), net_quantity.abs());
+            // The cost per unit and the remainder are scaled separately, which
+            // gives the same result as `cost_basis * closing / net` without
+            // multiplying the whole cost basis.
+            let net = net_quantity.abs();
+            let closed_cost = (cost_basis / net).checked_mul(closing_quantity)?
+                .checked_add((cost_basis % net).checked_mul(closing_quantity)? / net)?;
+            let closing_value = (net_quantity.signum() * closing_quantity).checked_mul(price)?;
+            realized_pnl = realized_pnl.checked_add(closing_value.checked_sub(closed_cost)?)?;
+            cost_basis -= closed_cost;
+            net_quantity += quantity.signum() * closing_quantity;
+
+            let opening_quantity = quantity.signum() * (quantity.abs() - closing_quantity);
+            net_quantity = net_quantity.checked_add(opening_quantity)?;
+            cost_basis = cost_basis.checked_add(opening_quantity.checked_mul(price)?)?;
+        }
+        Some(Position {net_quantity, cost_basis, realized_pnl, overflowed: self.overflowed})
+    }
+
+    pub fn average_entry_price(&self) -> Option<f64> {
+        match self.net_quantity {
+            0 => None,
+            net_quantity => Some(self.cost_basis as f64 / net_quantity as f64)
+        }
+    }
+
+    pub fn unrealized_pnl(&self, mark_price: u64) -> Option<i128> {
+        self.net_quantity.checked_mul(mark_price as i128)?.checked_sub(self.cost_basis)
+    }
+}
+
+impl PositionKeeper {
+    pub fn new(mark_method: MarkMethod) -> Self {
+        PositionKeeper {
+            mark_method,
+            owners: HashMap::new(),
+            positions: BTreeMap::new(),
+            marks: HashMap::new()
+        }
+    }
+
+    pub fn restore(mark_method: MarkMethod, snapshot: PositionSnapshot) -> Self {
+        PositionKeeper {
+            mark_method,
+            owners: snapshot.owners,
+            positions: snapshot.positions.into_iter().collect(),
+            marks: snapshot.marks
+        }
+    }
+
+    pub fn snapshot(&self) -> PositionSnapshot {
+        PositionSnapshot {
+            owners: self.owners.clone(),
+            positions: self.positions.iter().map(|(owner, position)| (owner.clone(), *position)).collect(),
+            marks: self.marks.clone()
+        }
+    }
+
+    pub fn register_order(&mut self, order_id: u64, account: &str, instrument: &str) {
+        self.owners.insert(order_id, (account.to_string(), instrument.to_string()));
+    }
+
+    pub fn account(&self, order_id: u64) -> Option<&str> {
+        self.owners.get(&order_id).map(|(account, _)| account.as_str())
+    }
+
+    // Owners of finished orders are forgotten, their positions are kept.
+    pub fn forget_order(&mut self, order_id: u64) {
+        self.owners.remove(&order_id);
+    }
+
+    pub fn on_fill(&mut self, fill_event: &FillEvent) {
+        let quantity = fill_event.quantity as i128;
+        let sides = [(fill_event.buy_order_id, quantity), (fill_event.sell_order_id, -quantity)];
+        for (order_id, signed_quantity) in sides.iter() {
+            if let Some(owner) = self.owners.get(order_id) {
+                self.marks.entry(owner.1.clone()).or_default().last_trade = Some(fill_event.price);
+                self.positions
+                    .entry(owner.clone())
+                    .or_default()
+                    .apply_fill(*signed_quantity, fill_event.price);
+            }
+        }
+    }
+
+    pub fn update_quotes(&mut self, instrument: &str, best_buy_price: Option<u64>, best_sell_price: Option<u64>) {
+        let mid = match (best_buy_price, best_sell_price) {
+            (Some(bid), Some(ask)) => Some(bid + ask.saturating_sub(bid) / 2),
+            _ => None
+        };
+        self.marks.entry(instrument.to_string()).or_default().mid = mid;
+    }
+
+    pub fn mark_price(&self, instrument: &str) -> Option<u64> {
+        let marks = self.marks.get(instrument)?;
+        match self.mark_method {
+            MarkMethod::Mid => marks.mid.or(marks.last_trade),
+            MarkMethod::LastTrade => marks.last_trade
+        }
+    }
+
+    pub fn position(&self, account: &str, instrument: &str) -> Option<PositionReport> {
+        self.positions
+            .get(&(account.to_string(), instrument.to_string()))
+            .map(|position| self.report(account, instrument, position))
+    }
+
+    pub fn positions(&self) -> Vec<PositionReport> {
+        self.positions
+            .iter()
+            .map(|((account, instrument), position)| self.report(account, instrument, position))
+            .collect()
+    }
+
+    fn report(&self, account: &str, instrument: &str, position: &Position) -> PositionReport {
+        let mark_price = self.mark_price(instrument);
+        PositionReport {
+            account: account.to_string(),
+            instrument: instrument.to_string(),
+            net_quantity: position.net_quantity,
+            average_entry_price: position.average_entry_price(),
+            realized_pnl: position.realized_pnl,
+            unrealized_pnl: mark_price.map_or(Some(0), |price| position.unrealized_pnl(price)),
+            mark_price,
+            overflowed: position.overflowed
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn fill(buy_order_id: u64, sell_order_id: u64, price: u64, quantity: u64) -> FillEvent {
+        FillEvent {buy_order_id, sell_order_id, price, quantity}
+    }
+
+    #[test]
+    fn realized_pnl_on_reduce_and_flip() {
+        let mut keeper = PositionKeeper::new(MarkMethod::LastTrade);
+        keeper.register_order(1, "alice", "X");
+        keeper.register_order(2, "bob", "X");
+        keeper.register_order(3, "alice", "X");
+        keeper.register_order(4, "bob", "X");
+
+        keeper.on_fill(&fill(1, 2, 100, 10));
+        keeper.on_fill(&fill(4, 3, 110, 15));
+
+        let alice = keeper.position("alice", "X").unwrap();
+        assert_eq!(alice.net_quantity, -5);
+        assert_eq!(alice.realized_pnl, 100);
+        assert_eq!(alice.average_entry_price, Some(110.0));
+        assert_eq!(alice.unrealized_pnl, Some(0));
+
+        let bob = keeper.position("bob", "X").unwrap();
+        assert_eq!(bob.net_quantity, 5);
+        assert_eq!(bob.realized_pnl, -100);
+    }
+
+    #[test]
+    fn unrealized_pnl_against_mid() {
+        let mut keeper = PositionKeeper::new(MarkMethod::Mid);
+        keeper.register_order(1, "alice", "X");
+
+        keeper.on_fill(&fill(1, 2, 100, 10));
+        assert_eq!(keeper.position("alice", "X").unwrap().unrealized_pnl, Some(0));
+
+        keeper.update_quotes("X", Some(104), Some(108));
+        let alice = keeper.position("alice", "X").unwrap();
+        assert_eq!(alice.mark_price, Some(106));
+        assert_eq!(alice.unrealized_pnl, Some(60));
+        assert_eq!(keeper.positions().len(), 1);
+    }
+
+    #[test]
+    fn fills_from_orderbook() {
+        let mut orderbook = Orderbook::new();
+        let mut keeper = PositionKeeper::new(MarkMethod::LastTrade);
+
+        let mut orders = [
+            Order {order_key: OrderKey {id: 1, timestamp: 0, price: 100, order_side: OrderSide::Buy}, quantity: 20, iceberg: None},
+            Order {order_key: OrderKey {id: 2, timestamp: 0, price: 99, order_side: OrderSide::Sell}, quantity: 30, iceberg: None}
+        ];
+        for order in orders.iter_mut() {
+            keeper.register_order(order.order_key.id, &format!("account-{}", order.order_key.id), "X");
+            orderbook.process_order(order).iter().for_each(|event| keeper.on_fill(event));
+        }
+
+        assert_eq!(keeper.position("account-1", "X").unwrap().net_quantity, 20);
+        assert_eq!(keeper.position("account-2", "X").unwrap().net_quantity, -20);
+        assert_eq!(keeper.mark_price("X"), Some(100));
+    }
+
+    #[test]
+    fn flag_overflowing_positions() {
+        let mut position = Position::default();
+        position.apply_fill(u64::MAX as i128, 1 << 62);
+        assert_eq!(position.cost_basis, u64::MAX as i128 * (1 << 62));
+        position.apply_fill(-(u64::MAX as i128), (1 << 62) - 1);
+        assert_eq!((position.net_quantity, position.realized_pnl, position.overflowed), (0, -(u64::MAX as i128), false));
+
+        position.apply_fill(u64::MAX as i128, u64::MAX);
+        assert_eq!(position, Position {realized_pnl: -(u64::MAX as i128), overflowed: true, ..Position::default()});
+        position.apply_fill(1, 100);
+        assert_eq!((position.net_quantity, position.cost_basis, position.overflowed), (1, 100, true));
+        assert_eq!(Position {net_quantity: i128::MAX / 2, ..position}.unrealized_pnl(4), None);
+
+        let mut keeper = PositionKeeper::new(MarkMethod::Mid);
+        keeper.update_quotes("X", Some(u64::MAX - 2), Some(u64::MAX));
+        assert_eq!(keeper.mark_price("X"), Some(u64::MAX - 1));
+    }
+}
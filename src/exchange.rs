@@ -0,0 +1,610 @@
+use std::collections::VecDeque;
+use std::io;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+
+use crate::accounting::balances::{BalanceLedger, BalanceReport};
+use crate::accounting::fees::{FeeAnnotatedFill, FeeEngine, FeeSchedule};
+use crate::accounting::positions::{MarkMethod, PositionKeeper, PositionReport};
+use crate::market_data::metrics::{BookMetrics, MetricsConfig};
+use crate::market_data::statistics::SessionStatistics;
+use crate::matching_engine::order::{FillEvent, Order, OrderSide};
+use crate::matching_engine::orderbook::{OrderStatus, Orderbook, OrderbookContent};
+use crate::matching_engine::parse::{parse_order, DeserializedCommand, DeserializedOrder, InboundMessage, OrderCore};
+use crate::persistence::journal::{Journal, JournalError};
+use crate::persistence::snapshot::{load_snapshot, save_snapshot, RecoveryError, SnapshotFile};
+
+// Trades kept for `trades_since`, older ones are dropped.
+const TRADE_HISTORY: usize = 10_000;
+
+#[derive(Clone)]
+pub struct ExchangeConfig {
+    pub instrument: String,
+    pub mark_method: MarkMethod,
+    pub fee_schedule: FeeSchedule,
+    pub spot_assets: Option<(String, String)>,
+    pub metrics: Option<MetricsConfig>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderRejected {
+    pub rejected_order_id: u64,
+    pub reason: String
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderNotFound {
+    pub unknown_order_id: u64
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderCancelled {
+    pub cancelled_order_id: u64,
+    pub quantity: u64
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderReplaced {
+    pub replaced_order_id: u64,
+    pub order_id: u64,
+    pub price: u64,
+    pub quantity: u64
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TradeRecord {
+    pub sequence: u64,
+    #[serde(flatten)]
+    pub fill_event: FillEvent,
+    pub taker_side: OrderSide
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ErrorReport {
+    pub error: String
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum ExchangeOutput {
+    Book(OrderbookContent),
+    Fill(FeeAnnotatedFill),
+    Rejected(OrderRejected),
+    Cancelled(OrderCancelled),
+    Replaced(OrderReplaced),
+    Status(OrderStatus),
+    NotFound(OrderNotFound),
+    Positions(Vec<PositionReport>),
+    Balances(Vec<BalanceReport>),
+    Statistics(SessionStatistics),
+    Metrics(BookMetrics),
+    Error(ErrorReport)
+}
+
+pub struct Exchange {
+    config: ExchangeConfig,
+    orderbook: Orderbook,
+    position_keeper: PositionKeeper,
+    fee_engine: FeeEngine,
+    balance_ledger: Option<BalanceLedger>,
+    journal: Option<Journal>,
+    trades: VecDeque<TradeRecord>,
+    next_trade_sequence: u64,
+    statistics: SessionStatistics
+}
+
+impl Default for ExchangeConfig {
+    fn default() -> Self {
+        ExchangeConfig {
+            instrument: "default".to_string(),
+            mark_method: MarkMethod::Mid,
+            fee_schedule: FeeSchedule::default(),
+            spot_assets: None,
+            metrics: None
+        }
+    }
+}
+
+impl Exchange {
+    pub fn new(config: ExchangeConfig) -> Self {
+        let balance_ledger = config.spot_assets
+            .as_ref()
+            .map(|(base_asset, quote_asset)| BalanceLedger::new(base_asset, quote_asset));
+        Exchange {
+            orderbook: Orderbook::new(),
+            position_keeper: PositionKeeper::new(config.mark_method),
+            fee_engine: FeeEngine::new(config.fee_schedule.clone()),
+            balance_ledger,
+            journal: None,
+            trades: VecDeque::new(),
+            next_trade_sequence: 1,
+            statistics: SessionStatistics::new(),
+            config
+        }
+    }
+
+    // Rebuilds the exchange by replaying every journaled command, then keeps
+    // journaling new commands to the same file.
+    pub fn recover<P: AsRef<Path>>(config: ExchangeConfig, path: P) -> Result<Self, JournalError> {
+        let journal = Journal::open(&path)?;
+        let mut exchange = Exchange::new(config);
+        for record in Journal::read(&path)? {
+            exchange.handle(record.message);
+        }
+        exchange.journal = Some(journal);
+        Ok(exchange)
+    }
+
+    // Restores the book, positions, fee volumes, balances, statistics and
+    // trades from a snapshot and replays only the journal records written
+    // after it.
+    pub fn recover_with_snapshot<P: AsRef<Path>, Q: AsRef<Path>>(config: ExchangeConfig, snapshot_path: P, journal_path: Q) -> Result<Self, RecoveryError> {
+        let snapshot_file = load_snapshot(snapshot_path)?;
+        let balance_ledger = match (&config.spot_assets, snapshot_file.balances) {
+            (None, None) => None,
+            (Some((base_asset, quote_asset)), Some(balances)) => Some(BalanceLedger::restore(base_asset, quote_asset, balances)),
+            (Some(_), None) => return Err(RecoveryError::Unsupported("the snapshot has no spot balances".to_string())),
+            (None, Some(_)) => return Err(RecoveryError::Unsupported("the snapshot has spot balances but the exchange has no spot assets".to_string()))
+        };
+        let journal = Journal::open(&journal_path)?;
+        let mut exchange = Exchange::new(config);
+        exchange.orderbook = Orderbook::restore(snapshot_file.orderbook)?;
+        exchange.position_keeper = PositionKeeper::restore(exchange.config.mark_method, snapshot_file.positions);
+        exchange.fee_engine = FeeEngine::restore(exchange.config.fee_schedule.clone(), snapshot_file.fees);
+        exchange.balance_ledger = balance_ledger;
+        exchange.statistics = snapshot_file.statistics;
+        exchange.trades = snapshot_file.trades.into_iter().collect();
+        exchange.next_trade_sequence = snapshot_file.next_trade_sequence;
+        for record in Journal::read(&journal_path)? {
+            if record.sequence > snapshot_file.journal_sequence {
+                exchange.handle(record.message);
+            }
+        }
+        exchange.update_quotes();
+        exchange.journal = Some(journal);
+        Ok(exchange)
+    }
+
+    pub fn save_snapshot<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let journal_sequence = self.journal.as_ref().map_or(0, |journal| journal.next_sequence() - 1);
+        save_snapshot(path, &SnapshotFile {
+            journal_sequence,
+            orderbook: self.orderbook.snapshot(),
+            positions: self.position_keeper.snapshot(),
+            fees: self.fee_engine.snapshot(),
+            balances: self.balance_ledger.as_ref().map(BalanceLedger::snapshot),
+            statistics: self.statistics,
+            trades: self.trades.iter().copied().collect(),
+            next_trade_sequence: self.next_trade_sequence
+        })
+    }
+
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    pub fn instrument(&self) -> &str {
+        &self.config.instrument
+    }
+
+    pub fn orderbook(&self) -> &Orderbook {
+        &self.orderbook
+    }
+
+    pub fn position_keeper(&self) -> &PositionKeeper {
+        &self.position_keeper
+    }
+
+    pub fn balance_ledger(&self) -> Option<&BalanceLedger> {
+        self.balance_ledger.as_ref()
+    }
+
+    // Trades numbered from 1 in execution order, after `sequence`.
+    pub fn trades_since(&self, sequence: u64) -> Vec<TradeRecord> {
+        self.trades.iter().filter(|trade| trade.sequence > sequence).copied().collect()
+    }
+
+    // Statistics since the exchange started, including the journal replayed
+    // by `recover` and the trades restored from a snapshot.
+    pub fn statistics(&self) -> SessionStatistics {
+        SessionStatistics {
+            bids: self.orderbook.resting_quantity(OrderSide::Buy),
+            asks: self.orderbook.resting_quantity(OrderSide::Sell),
+            ..self.statistics
+        }
+    }
+
+    // Commands are validated before they are journaled, so the journal only
+    // holds commands that changed the exchange and recovery never replays a
+    // rejected one. With metrics enabled, every message that is not a query
+    // ends with the book metrics after it was processed.
+    pub fn handle(&mut self, message: InboundMessage) -> Vec<ExchangeOutput> {
+        let is_query = message.is_query();
+        let mut outputs = match self.validate(&message) {
+            Err(rejection) => rejection,
+            Ok(()) => {
+                if let (false, Some(journal)) = (is_query, self.journal.as_mut()) {
+                    if let Err(journal_error) = journal.append(&message) {
+                        return vec![error(&journal_error.to_string())];
+                    }
+                }
+                self.apply(message)
+            }
+        };
+        if let (false, Some(metrics_config)) = (is_query, self.config.metrics) {
+            outputs.push(ExchangeOutput::Metrics(BookMetrics::from_orderbook(&self.orderbook, metrics_config)));
+        }
+        outputs
+    }
+
+    fn validate(&self, message: &InboundMessage) -> Result<(), Vec<ExchangeOutput>> {
+        match message {
+            InboundMessage::Order(deserialized_order) => {
+                let order_core = deserialized_order.order_core();
+                let id = order_core.id;
+                if order_core.quantity == 0 {
+                    return Err(vec![rejected(id, "quantity must be positive".to_string())]);
+                }
+                if let DeserializedOrder::Iceberg {peak: 0, ..} = deserialized_order {
+                    return Err(vec![rejected(id, "iceberg peak must be positive".to_string())]);
+                }
+                if self.orderbook.has_order(id) {
+                    return Err(vec![rejected(id, "duplicate order id".to_string())]);
+                }
+                if let Some(ref balance_ledger) = self.balance_ledger {
+                    let order = parse_order(deserialized_order.clone());
+                    balance_ledger.check_reserve(&order_core.account, &order, None).map_err(|balance_error| vec![rejected(id, balance_error.to_string())])?;
+                }
+                Ok(())
+            }
+            InboundMessage::Command(DeserializedCommand::Cancel {id}) => match self.orderbook.has_order(*id) {
+                false => Err(vec![rejected(*id, "unknown order id".to_string())]),
+                true => Ok(())
+            },
+            InboundMessage::Command(DeserializedCommand::Replace {id, new_id, price, quantity}) => {
+                let (id, new_id) = (*id, new_id.unwrap_or(*id));
+                if *quantity == 0 {
+                    return Err(vec![rejected(id, "replacement quantity must be positive".to_string())]);
+                }
+                if new_id != id && self.orderbook.has_order(new_id) {
+                    return Err(vec![rejected(id, "duplicate order id".to_string())]);
+                }
+                let order = self.orderbook.resting_order(id).ok_or_else(|| vec![rejected(id, "unknown order id".to_string())])?;
+                if let Some(ref balance_ledger) = self.balance_ledger {
+                    let (account, replacement) = self.replacement(&order, new_id, *price, *quantity);
+                    balance_ledger.check_reserve(&account, &replacement, Some(id)).map_err(|balance_error| vec![rejected(id, balance_error.to_string())])?;
+                }
+                Ok(())
+            }
+            InboundMessage::Command(DeserializedCommand::Deposit {asset, amount, ..}) => match self.balance_ledger {
+                None => Err(vec![error("balances are not enabled")]),
+                Some(ref balance_ledger) => balance_ledger.check_deposit(asset, *amount).map(|_| ()).map_err(|balance_error| vec![error(&balance_error.to_string())])
+            },
+            InboundMessage::Command(_) => Ok(())
+        }
+    }
+
+    // Applies a message that passed `validate`.
+    fn apply(&mut self, message: InboundMessage) -> Vec<ExchangeOutput> {
+        match message {
+            InboundMessage::Order(deserialized_order) => self.submit_order(deserialized_order),
+            InboundMessage::Command(DeserializedCommand::Cancel {id}) => self.cancel_order(id),
+            InboundMessage::Command(DeserializedCommand::Replace {id, new_id, price, quantity}) =>
+                self.replace_order(id, new_id.unwrap_or(id), price, quantity),
+            InboundMessage::Command(DeserializedCommand::Deposit {account, asset, amount}) => {
+                match self.balance_ledger {
+                    None => vec![],
+                    Some(ref mut balance_ledger) => {
+                        // `validate` checked the supply.
+                        let _ = balance_ledger.deposit(&account, &asset, amount);
+                        vec![ExchangeOutput::Balances(balance_ledger.balances())]
+                    }
+                }
+            }
+            InboundMessage::Command(DeserializedCommand::Status {id}) => match self.orderbook.get_order(id) {
+                None => vec![ExchangeOutput::NotFound(OrderNotFound {unknown_order_id: id})],
+                Some(order_status) => vec![ExchangeOutput::Status(order_status)]
+            },
+            InboundMessage::Command(DeserializedCommand::Positions) =>
+                vec![ExchangeOutput::Positions(self.position_keeper.positions())],
+            InboundMessage::Command(DeserializedCommand::Balances) => match self.balance_ledger {
+                None => vec![error("balances are not enabled")],
+                Some(ref balance_ledger) => vec![ExchangeOutput::Balances(balance_ledger.balances())]
+            },
+            InboundMessage::Command(DeserializedCommand::Stats) => vec![ExchangeOutput::Statistics(self.statistics())],
+            InboundMessage::Command(DeserializedCommand::Heartbeat) => vec![]
+        }
+    }
+
+    fn submit_order(&mut self, deserialized_order: DeserializedOrder) -> Vec<ExchangeOutput> {
+        let account = deserialized_order.order_core().account.clone();
+        let order = parse_order(deserialized_order);
+        if let Some(ref mut balance_ledger) = self.balance_ledger {
+            // `validate` checked the funds.
+            let _ = balance_ledger.reserve(&account, &order);
+        }
+        self.execute_order(&account, order)
+    }
+
+    // Matches an order that already passed validation and holds its funds.
+    fn execute_order(&mut self, account: &str, mut order: Order) -> Vec<ExchangeOutput> {
+        let id = order.order_key.id;
+        self.position_keeper.register_order(id, account, &self.config.instrument);
+        self.fee_engine.register_order(id, account);
+
+        let events = self.orderbook.process_order(&mut order);
+        for event in events.iter() {
+            self.position_keeper.on_fill(event);
+            if let Some(ref mut balance_ledger) = self.balance_ledger {
+                balance_ledger.on_fill(event);
+            }
+        }
+        self.update_quotes();
+
+        let mut outputs = vec![ExchangeOutput::Book(self.orderbook.get_orders())];
+        for event in events.iter() {
+            outputs.push(ExchangeOutput::Fill(self.fee_engine.annotate(event, order.order_key.order_side)));
+            self.record_trade(event, order.order_key.order_side);
+        }
+        for event in events.iter() {
+            for order_id in [event.buy_order_id, event.sell_order_id].iter() {
+                if !self.orderbook.has_order(*order_id) {
+                    self.forget_order(*order_id);
+                }
+            }
+        }
+        outputs
+    }
+
+    fn forget_order(&mut self, id: u64) {
+        self.position_keeper.forget_order(id);
+        self.fee_engine.forget_order(id);
+    }
+
+    fn cancel_order(&mut self, id: u64) -> Vec<ExchangeOutput> {
+        match self.orderbook.cancel_order(id) {
+            None => vec![],
+            Some(order) => {
+                if let Some(ref mut balance_ledger) = self.balance_ledger {
+                    balance_ledger.release(id);
+                }
+                self.forget_order(id);
+                self.update_quotes();
+                let quantity = order.quantity + order.iceberg.map_or(0, |iceberg| iceberg.hidden_quantity);
+                vec![
+                    ExchangeOutput::Book(self.orderbook.get_orders()),
+                    ExchangeOutput::Cancelled(OrderCancelled {cancelled_order_id: id, quantity})
+                ]
+            }
+        }
+    }
+
+    // Replacing is a cancel followed by a new order on the same side for the
+    // same account, so the replacement loses its time priority. `validate`
+    // checked everything that could reject the replacement, so a rejected one
+    // leaves the original order resting as it was.
+    fn replace_order(&mut self, id: u64, new_id: u64, price: u64, quantity: u64) -> Vec<ExchangeOutput> {
+        let order = match self.orderbook.resting_order(id) {
+            None => return vec![],
+            Some(order) => order
+        };
+        let (account, replacement) = self.replacement(&order, new_id, price, quantity);
+        if let Some(ref mut balance_ledger) = self.balance_ledger {
+            // `validate` checked the funds left once the original is released.
+            balance_ledger.release(id);
+            let _ = balance_ledger.reserve(&account, &replacement);
+        }
+
+        self.orderbook.cancel_order(id);
+        self.forget_order(id);
+        let mut outputs = vec![ExchangeOutput::Replaced(OrderReplaced {replaced_order_id: id, order_id: new_id, price, quantity})];
+        outputs.extend(self.execute_order(&account, replacement));
+        outputs
+    }
+
+    fn replacement(&self, order: &Order, new_id: u64, price: u64, quantity: u64) -> (String, Order) {
+        let account = self.position_keeper.account(order.order_key.id).unwrap_or_default().to_string();
+        let order_core = OrderCore {direction: order.order_key.order_side, id: new_id, price, quantity, account: account.clone()};
+        let replacement = parse_order(match order.iceberg {
+            None => DeserializedOrder::Limit {order_core},
+            Some(iceberg) => DeserializedOrder::Iceberg {order_core, peak: iceberg.peak_size.min(quantity)}
+        });
+        (account, replacement)
+    }
+
+    fn record_trade(&mut self, fill_event: &FillEvent, taker_side: OrderSide) {
+        if self.trades.len() == TRADE_HISTORY {
+            self.trades.pop_front();
+        }
+        self.statistics.on_fill(fill_event);
+        self.trades.push_back(TradeRecord {sequence: self.next_trade_sequence, fill_event: *fill_event, taker_side});
+        self.next_trade_sequence += 1;
+    }
+
+    fn update_quotes(&mut self) {
+        self.position_keeper.update_quotes(
+            &self.config.instrument,
+            self.orderbook.best_buy_price(),
+            self.orderbook.best_sell_price()
+        );
+    }
+}
+
+fn rejected(rejected_order_id: u64, reason: String) -> ExchangeOutput {
+    ExchangeOutput::Rejected(OrderRejected {rejected_order_id, reason})
+}
+
+fn error(error: &str) -> ExchangeOutput {
+    ExchangeOutput::Error(ErrorReport {error: error.to_string()})
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::test_support::message;
+
+    fn spot_exchange() -> Exchange {
+        Exchange::new(ExchangeConfig {
+            spot_assets: Some(("BTC".to_string(), "USD".to_string())),
+            ..ExchangeConfig::default()
+        })
+    }
+
+    #[test]
+    fn rejects_orders_without_funds() {
+        let mut exchange = spot_exchange();
+        let outputs = exchange.handle(message(r#"{"type": "Limit", "order": {"direction": "Buy", "id": 1, "price": 100, "quantity": 5, "account": "a"}}"#));
+        match outputs.as_slice() {
+            [ExchangeOutput::Rejected(rejection)] => assert_eq!(rejection.rejected_order_id, 1),
+            other => panic!("unexpected outputs {:?}", other)
+        }
+        assert!(!exchange.orderbook().has_order(1));
+    }
+
+    #[test]
+    fn rejects_empty_orders_before_reserving() {
+        let mut exchange = spot_exchange();
+        exchange.handle(message(r#"{"type": "Deposit", "account": "a", "asset": "USD", "amount": 500}"#));
+        for (json, reason) in [
+            (r#"{"type": "Iceberg", "order": {"direction": "Buy", "id": 1, "price": 100, "quantity": 5, "peak": 0, "account": "a"}}"#, "iceberg peak must be positive"),
+            (r#"{"type": "Limit", "order": {"direction": "Buy", "id": 2, "price": 100, "quantity": 0, "account": "a"}}"#, "quantity must be positive")
+        ].iter() {
+            match exchange.handle(message(json)).as_slice() {
+                [ExchangeOutput::Rejected(rejection)] => assert_eq!(rejection.reason, *reason),
+                other => panic!("unexpected outputs {:?}", other)
+            }
+        }
+        assert_eq!(exchange.balance_ledger().unwrap().balance("a", "USD"), Balance {available: 500, reserved: 0});
+        assert_eq!(exchange.orderbook().get_orders().buy_orders, vec![]);
+    }
+
+    #[test]
+    fn rejects_deposits_beyond_supply() {
+        let mut exchange = spot_exchange();
+        exchange.handle(message(r#"{"type": "Deposit", "account": "a", "asset": "USD", "amount": 500}"#));
+        let deposit = format!(r#"{{"type": "Deposit", "account": "b", "asset": "USD", "amount": {}}}"#, u64::MAX);
+        match exchange.handle(message(&deposit)).as_slice() {
+            [ExchangeOutput::Error(error)] => assert_eq!(error.error, format!("deposit of {} would take the total USD out of range", u64::MAX)),
+            other => panic!("unexpected outputs {:?}", other)
+        }
+        assert_eq!(exchange.balance_ledger().unwrap().balance("b", "USD"), Balance::default());
+    }
+
+    #[test]
+    fn cancel_releases_reservation() {
+        let mut exchange = spot_exchange();
+        exchange.handle(message(r#"{"type": "Deposit", "account": "a", "asset": "USD", "amount": 500}"#));
+        exchange.handle(message(r#"{"type": "Limit", "order": {"direction": "Buy", "id": 1, "price": 100, "quantity": 5, "account": "a"}}"#));
+        assert_eq!(exchange.balance_ledger().unwrap().balance("a", "USD"), Balance {available: 0, reserved: 500});
+
+        let outputs = exchange.handle(message(r#"{"type": "Cancel", "id": 1}"#));
+        match outputs.as_slice() {
+            [ExchangeOutput::Book(_), ExchangeOutput::Cancelled(cancelled)] => assert_eq!(cancelled.quantity, 5),
+            other => panic!("unexpected outputs {:?}", other)
+        }
+        assert_eq!(exchange.balance_ledger().unwrap().balance("a", "USD"), Balance {available: 500, reserved: 0});
+        assert_eq!(exchange.orderbook().get_orders().buy_orders, vec![]);
+    }
+
+    #[test]
+    fn status_of_finished_and_unknown_orders() {
+        let mut exchange = Exchange::new(ExchangeConfig::default());
+        exchange.handle(message(r#"{"type": "Limit", "order": {"direction": "Buy", "id": 1, "price": 100, "quantity": 5}}"#));
+        exchange.handle(message(r#"{"type": "Limit", "order": {"direction": "Sell", "id": 2, "price": 100, "quantity": 5}}"#));
+
+        match exchange.handle(message(r#"{"type": "Status", "id": 1}"#)).as_slice() {
+            [ExchangeOutput::Status(order_status)] => assert_eq!((order_status.state, order_status.filled_quantity), (ExecutionState::Filled, 5)),
+            other => panic!("unexpected outputs {:?}", other)
+        }
+        match exchange.handle(message(r#"{"type": "Status", "id": 3}"#)).as_slice() {
+            [ExchangeOutput::NotFound(not_found)] => assert_eq!(not_found, &OrderNotFound {unknown_order_id: 3}),
+            other => panic!("unexpected outputs {:?}", other)
+        }
+    }
+
+    #[test]
+    fn replace_keeps_side_account_and_peak() {
+        let mut exchange = Exchange::new(ExchangeConfig::default());
+        exchange.handle(message(r#"{"type": "Iceberg", "order": {"direction": "Sell", "id": 1, "price": 105, "quantity": 50, "peak": 10, "account": "a"}}"#));
+        exchange.handle(message(r#"{"type": "Limit", "order": {"direction": "Buy", "id": 2, "price": 100, "quantity": 15, "account": "b"}}"#));
+
+        let outputs = exchange.handle(message(r#"{"type": "Replace", "id": 1, "newId": 3, "price": 100, "quantity": 30}"#));
+        match outputs.as_slice() {
+            [ExchangeOutput::Replaced(replaced), ExchangeOutput::Book(_), ExchangeOutput::Fill(first), ExchangeOutput::Fill(second)] => {
+                assert_eq!(replaced, &OrderReplaced {replaced_order_id: 1, order_id: 3, price: 100, quantity: 30});
+                assert_eq!(first.fill_event, FillEvent {buy_order_id: 2, sell_order_id: 3, price: 100, quantity: 10});
+                assert_eq!(second.fill_event.quantity, 5);
+            }
+            other => panic!("unexpected outputs {:?}", other)
+        }
+        assert!(!exchange.orderbook().has_order(1));
+        assert_eq!(exchange.position_keeper().position("a", "default").unwrap().net_quantity, -15);
+        let owners: Vec<Option<&str>> = [1, 2, 3].iter().map(|id| exchange.position_keeper().account(*id)).collect();
+        assert_eq!(owners, vec![None, None, Some("a")]);
+        assert_eq!(exchange.trades_since(1), vec![TradeRecord {sequence: 2, fill_event: FillEvent {buy_order_id: 2, sell_order_id: 3, price: 100, quantity: 5}, taker_side: OrderSide::Sell}]);
+
+        match exchange.handle(message(r#"{"type": "Replace", "id": 1, "price": 100, "quantity": 30}"#)).as_slice() {
+            [ExchangeOutput::Rejected(rejection)] => assert_eq!(rejection.reason, "unknown order id"),
+            other => panic!("unexpected outputs {:?}", other)
+        }
+    }
+
+    #[test]
+    fn rejected_replace_keeps_original_order() {
+        let mut exchange = spot_exchange();
+        exchange.handle(message(r#"{"type": "Deposit", "account": "a", "asset": "USD", "amount": 700}"#));
+        exchange.handle(message(r#"{"type": "Limit", "order": {"direction": "Buy", "id": 1, "price": 100, "quantity": 5, "account": "a"}}"#));
+        exchange.handle(message(r#"{"type": "Limit", "order": {"direction": "Buy", "id": 2, "price": 100, "quantity": 2, "account": "a"}}"#));
+        let queue_position = exchange.orderbook().queue_position(1);
+
+        match exchange.handle(message(r#"{"type": "Replace", "id": 1, "newId": 3, "price": 200, "quantity": 5}"#)).as_slice() {
+            [ExchangeOutput::Rejected(rejection)] => assert_eq!(rejection.rejected_order_id, 1),
+            other => panic!("unexpected outputs {:?}", other)
+        }
+        assert_eq!(exchange.orderbook().queue_position(1), queue_position);
+        assert!(!exchange.orderbook().has_order(3));
+        assert_eq!(exchange.balance_ledger().unwrap().balance("a", "USD"), Balance {available: 0, reserved: 700});
+
+        match exchange.handle(message(r#"{"type": "Replace", "id": 1, "price": 50, "quantity": 10}"#)).as_slice() {
+            [ExchangeOutput::Replaced(_), ExchangeOutput::Book(_)] => (),
+            other => panic!("unexpected outputs {:?}", other)
+        }
+        assert_eq!(exchange.balance_ledger().unwrap().balance("a", "USD"), Balance {available: 0, reserved: 700});
+    }
+
+    #[test]
+    fn session_statistics() {
+        let mut exchange = Exchange::new(ExchangeConfig::default());
+        for json in [
+            r#"{"type": "Iceberg", "order": {"direction": "Sell", "id": 1, "price": 101, "quantity": 50, "peak": 10}}"#,
+            r#"{"type": "Limit", "order": {"direction": "Sell", "id": 2, "price": 100, "quantity": 5}}"#,
+            r#"{"type": "Limit", "order": {"direction": "Buy", "id": 3, "price": 101, "quantity": 12}}"#,
+            r#"{"type": "Limit", "order": {"direction": "Buy", "id": 4, "price": 99, "quantity": 20}}"#
+        ].iter() {
+            exchange.handle(message(json));
+        }
+
+        let expected = SessionStatistics {
+            last_price: Some(101),
+            last_quantity: Some(7),
+            high: Some(101),
+            low: Some(100),
+            volume: 12,
+            notional: 1207,
+            vwap: Some(1207.0 / 12.0),
+            trade_count: 2,
+            bids: RestingQuantity {visible: 20, hidden: 0},
+            asks: RestingQuantity {visible: 3, hidden: 40}
+        };
+        assert_eq!(exchange.statistics(), expected);
+        match exchange.handle(message(r#"{"type": "Stats"}"#)).as_slice() {
+            [ExchangeOutput::Statistics(statistics)] => assert_eq!(statistics, &expected),
+            other => panic!("unexpected outputs {:?}", other)
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use crate::matching_engine::order::{FillEvent, OrderSide};
+use crate::matching_engine::orderbook::Orderbook;
+use crate::matching_engine::parse::{parse_order, DeserializedOrder, InboundMessage, OrderCore};
+
+// Bodies of the targets in fuzz/, kept in the library so the corpus can be
+// replayed by `cargo test` without a fuzzer. Only built for tests and with the
+// `fuzzing` feature, which fuzz/ turns on. Both panic on any broken invariant.
+
+const OPERATION_LENGTH: usize = 7;
+const SELL: u8 = 1;
+const ICEBERG: u8 = 2;
+const CANCEL: u8 = 4;
+
+fn check(orderbook: &Orderbook) {
+    if let (Some(best_buy), Some(best_sell)) = (orderbook.best_buy_price(), orderbook.best_sell_price()) {
+        assert!(best_buy < best_sell, "crossed book {} >= {}", best_buy, best_sell);
+    }
+    let content = orderbook.get_orders();
+    for order in content.buy_orders.iter().chain(content.sell_orders.iter()) {
+        assert!(order.quantity > 0, "order {} rests without visible quantity", order.order_key.id);
+    }
+}
+
+// Orders with a duplicate id and icebergs with a zero peak and hidden quantity
+// left must leave the book untouched. Every other order has to fill or rest
+// in full.
+fn process(orderbook: &mut Orderbook, deserialized_order: DeserializedOrder) -> Vec<FillEvent> {
+    let order_core = deserialized_order.order_core();
+    let (id, quantity) = (order_core.id, order_core.quantity);
+    let ignored = orderbook.has_order(id) || (matches!(deserialized_order, DeserializedOrder::Iceberg {peak: 0, ..}) && quantity > 0);
+    let before = orderbook.snapshot();
+    let fill_events = orderbook.process_order(&mut parse_order(deserialized_order));
+    if ignored {
+        assert!(fill_events.is_empty() && orderbook.snapshot() == before, "order {} changed the book", id);
+        return fill_events;
+    }
+
+    let filled: u64 = fill_events
+        .iter()
+        .filter(|fill_event| fill_event.buy_order_id == id || fill_event.sell_order_id == id)
+        .map(|fill_event| fill_event.quantity)
+        .sum();
+    let resting = orderbook.resting_order(id)
+        .map_or(0, |order| order.quantity + order.iceberg.map_or(0, |iceberg| iceberg.hidden_quantity));
+    assert_eq!(filled + resting, quantity, "order {} neither filled nor rested in full", id);
+    fill_events
+}
+
+// Any bytes: JSON orders are parsed and matched, other inbound messages only
+// parsed.
+pub fn fuzz_parse_order(data: &[u8]) {
+    if let Ok(InboundMessage::Order(deserialized_order)) = serde_json::from_slice::<InboundMessage>(data) {
+        let mut orderbook = Orderbook::new();
+        process(&mut orderbook, deserialized_order);
+        check(&orderbook);
+    }
+}
+
+// Every 7 bytes are one operation: flags (sell, iceberg, cancel), id, price,
+// then little-endian 16 bit quantity and peak. Ids and prices are a single
+// byte so that duplicates and crossing prices come up often.
+pub fn fuzz_orderbook(data: &[u8]) {
+    let mut orderbook = Orderbook::new();
+    for operation in data.chunks_exact(OPERATION_LENGTH) {
+        let (flags, id, price) = (operation[0], operation[1] as u64, operation[2] as u64);
+        if flags & CANCEL != 0 {
+            orderbook.cancel_order(id);
+            check(&orderbook);
+            continue;
+        }
+
+        let order_core = OrderCore {
+            direction: if flags & SELL != 0 { OrderSide::Sell } else { OrderSide::Buy },
+            id,
+            price,
+            quantity: u16::from_le_bytes([operation[3], operation[4]]) as u64,
+            account: String::new()
+        };
+        let deserialized_order = match flags & ICEBERG {
+            0 => DeserializedOrder::Limit {order_core},
+            _ => DeserializedOrder::Iceberg {order_core, peak: u16::from_le_bytes([operation[5], operation[6]]) as u64}
+        };
+        let (taker_price, side) = (price, deserialized_order.order_core().direction);
+        for fill_event in process(&mut orderbook, deserialized_order) {
+            assert!(fill_event.quantity > 0, "empty fill {:?}", fill_event);
+            match side {
+                OrderSide::Buy => assert!(fill_event.price <= taker_price, "buy filled above its limit {:?}", fill_event),
+                OrderSide::Sell => assert!(fill_event.price >= taker_price, "sell filled below its limit {:?}", fill_event)
+            }
+        }
+        check(&orderbook);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use super::{fuzz_orderbook, fuzz_parse_order};
+
+    fn replay_corpus(target: &str, run: fn(&[u8])) {
+        let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz").join("corpus").join(target);
+        let mut inputs = 0;
+        for entry in fs::read_dir(&directory).unwrap() {
+            let path = entry.unwrap().path();
+            run(&fs::read(&path).unwrap());
+            inputs += 1;
+        }
+        assert!(inputs > 0, "empty corpus {}", directory.display());
+    }
+
+    #[test]
+    fn parse_order_corpus() {
+        replay_corpus("parse_order", fuzz_parse_order);
+    }
+
+    #[test]
+    fn orderbook_corpus() {
+        replay_corpus("orderbook", fuzz_orderbook);
+    }
+
+    #[test]
+    fn duplicate_ids_and_large_peaks() {
+        // Buy 1 rests, buy 1 again at 99, then a crossing sell, an iceberg
+        // whose peak is above its quantity and one with a zero peak.
+        fuzz_orderbook(&[
+            0, 1, 100, 10, 0, 0, 0,
+            0, 1, 99, 5, 0, 0, 0,
+            1, 2, 99, 20, 0, 0, 0,
+            3, 3, 98, 5, 0, 255, 255,
+            2, 4, 100, 5, 0, 0, 0
+        ]);
+        fuzz_parse_order(br#"{"type": "Iceberg", "order": {"direction": "Buy", "id": 1, "price": 100, "quantity": 5, "peak": 10}}"#);
+    }
+}
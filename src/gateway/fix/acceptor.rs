@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+use std::io::{self, BufReader, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::exchange::Exchange;
+use crate::gateway::http::{spawn_http, HttpQuery};
+use crate::gateway::session::{Delivery, SessionId, SessionRouter};
+use super::message::{read_message, FixMessage};
+use super::session::FixSession;
+
+const TICK: Duration = Duration::from_millis(100);
+
+// Messages waiting for a counterparty that does not keep up. A session whose
+// queue is full is disconnected rather than stalling the engine thread.
+const WRITE_QUEUE: usize = 1024;
+
+enum FixEvent {
+    Connected(SessionId, Writer),
+    Message(SessionId, FixMessage),
+    Disconnected(SessionId),
+    Query(HttpQuery)
+}
+
+pub struct FixAcceptor {
+    local_addr: SocketAddr
+}
+
+// Each connection is written by its own thread, so the engine thread only
+// ever queues encoded messages.
+struct Writer {
+    queue: SyncSender<Vec<u8>>,
+    stream: TcpStream
+}
+
+struct Connection {
+    session: FixSession,
+    writer: Writer
+}
+
+impl Writer {
+    fn open(stream: &TcpStream) -> io::Result<Writer> {
+        let mut writer = stream.try_clone()?;
+        let (queue, messages) = mpsc::sync_channel::<Vec<u8>>(WRITE_QUEUE);
+        thread::spawn(move || {
+            for message in messages {
+                if writer.write_all(&message).is_err() {
+                    break;
+                }
+            }
+            // The queue closes when the engine drops the session, so a
+            // logout queued before that is still written.
+            let _ = writer.shutdown(Shutdown::Both);
+        });
+        Ok(Writer {queue, stream: stream.try_clone()?})
+    }
+}
+
+impl FixAcceptor {
+    // Accepts FIX sessions on a background thread, the exchange runs on its
+    // own engine thread and every connection gets a writer thread, as for the
+    // JSON order entry server.
+    pub fn spawn(listener: TcpListener, exchange: Exchange, comp_id: &str, http: Option<TcpListener>) -> io::Result<FixAcceptor> {
+        let local_addr = listener.local_addr()?;
+        let (events, receiver) = mpsc::channel();
+        start_http(http, &events);
+        let comp_id = comp_id.to_string();
+        thread::spawn(move || run_engine(exchange, receiver, &comp_id));
+        thread::spawn(move || accept_sessions(listener, events));
+        Ok(FixAcceptor {local_addr})
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+// Blocks the calling thread, which becomes the engine thread.
+pub fn serve_fix(listener: TcpListener, exchange: Exchange, comp_id: &str, http: Option<TcpListener>) {
+    let (events, receiver) = mpsc::channel();
+    start_http(http, &events);
+    thread::spawn(move || accept_sessions(listener, events));
+    run_engine(exchange, receiver, comp_id);
+}
+
+fn start_http(http: Option<TcpListener>, events: &Sender<FixEvent>) {
+    if let Some(http) = http {
+        let events = events.clone();
+        spawn_http(http, move |query| events.send(FixEvent::Query(query)).is_ok());
+    }
+}
+
+fn accept_sessions(listener: TcpListener, events: Sender<FixEvent>) {
+    for (session_id, stream) in (1..).zip(listener.incoming()) {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue
+        };
+        let writer = match Writer::open(&stream) {
+            Ok(writer) => writer,
+            Err(_) => continue
+        };
+        if events.send(FixEvent::Connected(session_id, writer)).is_err() {
+            break;
+        }
+        let events = events.clone();
+        thread::spawn(move || read_session(session_id, stream, events));
+    }
+}
+
+// Garbled messages are dropped without consuming a sequence number, as the
+// FIX session protocol requires.
+fn read_session(session_id: SessionId, stream: TcpStream, events: Sender<FixEvent>) {
+    let mut reader = BufReader::new(stream);
+    while let Ok(Some(raw_message)) = read_message(&mut reader) {
+        if let Ok(message) = FixMessage::decode(&raw_message) {
+            if events.send(FixEvent::Message(session_id, message)).is_err() {
+                return;
+            }
+        }
+    }
+    let _ = events.send(FixEvent::Disconnected(session_id));
+}
+
+fn run_engine(mut exchange: Exchange, events: Receiver<FixEvent>, comp_id: &str) {
+    let mut router = SessionRouter::new();
+    let mut connections: HashMap<SessionId, Connection> = HashMap::new();
+
+    loop {
+        let mut closed = Vec::new();
+        match events.recv_timeout(TICK) {
+            Err(RecvTimeoutError::Disconnected) => break,
+            Err(RecvTimeoutError::Timeout) => (),
+            Ok(FixEvent::Connected(session_id, writer)) => {
+                connections.insert(session_id, Connection {session: FixSession::new(comp_id, Instant::now()), writer});
+            }
+            Ok(FixEvent::Disconnected(session_id)) => closed.push(session_id),
+            Ok(FixEvent::Query(query)) => query.answer(&exchange),
+            Ok(FixEvent::Message(session_id, message)) => {
+                if let Some(connection) = connections.get_mut(&session_id) {
+                    let actions = connection.session.receive(&message, Instant::now());
+                    if !send(connection, actions.replies) || actions.disconnect {
+                        closed.push(session_id);
+                    }
+                    if let Some(request) = actions.request {
+                        let deliveries = router.handle(&mut exchange, session_id, request);
+                        deliver(&mut connections, deliveries, &mut closed);
+                    }
+                }
+            }
+        }
+
+        let now = Instant::now();
+        for (session_id, connection) in connections.iter_mut() {
+            let actions = connection.session.tick(now);
+            if !send(connection, actions.replies) || actions.disconnect {
+                closed.push(*session_id);
+            }
+        }
+
+        while let Some(session_id) = closed.pop() {
+            connections.remove(&session_id);
+            let deliveries = router.disconnect(&mut exchange, session_id);
+            deliver(&mut connections, deliveries, &mut closed);
+        }
+    }
+}
+
+// Trades are public market data, order entry sessions only get their own
+// execution reports.
+fn deliver(connections: &mut HashMap<SessionId, Connection>, deliveries: Vec<Delivery>, closed: &mut Vec<SessionId>) {
+    for delivery in deliveries {
+        if let Delivery::Private(session_id, report) = delivery {
+            if let Some(connection) = connections.get_mut(&session_id) {
+                let messages = connection.session.report(&report);
+                if !send(connection, messages) {
+                    closed.push(session_id);
+                }
+            }
+        }
+    }
+}
+
+// Shutting a lagging connection down right away ends its writer and reader
+// without waiting for the queue to drain.
+fn send(connection: &mut Connection, messages: Vec<FixMessage>) -> bool {
+    let sent = messages.iter().all(|message| connection.writer.queue.try_send(message.encode()).is_ok());
+    if !sent {
+        let _ = connection.writer.stream.shutdown(Shutdown::Both);
+    }
+    sent
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+    use crate::*;
+    use gateway::fix::message::{msg_type, read_message, tag};
+    use gateway::fix::session::FixSession;
+    use super::{send, Connection, Writer, WRITE_QUEUE};
+
+    struct Client {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+        sequence: u64
+    }
+
+    impl Client {
+        fn logon(acceptor: &FixAcceptor, comp_id: &str) -> Client {
+            let stream = TcpStream::connect(acceptor.local_addr()).unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut client = Client {writer: stream.try_clone().unwrap(), reader: BufReader::new(stream), sequence: 0};
+            client.send(FixMessage::new("A")
+                .with(tag::SENDER_COMP_ID, comp_id)
+                .with(tag::ENCRYPT_METHOD, 0)
+                .with(tag::HEART_BT_INT, 30));
+            assert_eq!(client.receive().msg_type(), msg_type::LOGON);
+            client
+        }
+
+        fn send(&mut self, message: FixMessage) {
+            self.sequence += 1;
+            let message = message
+                .with(tag::TARGET_COMP_ID, "EXCHANGE")
+                .with(tag::MSG_SEQ_NUM, self.sequence)
+                .with(tag::SENDING_TIME, "20240101-00:00:00.000");
+            self.writer.write_all(&message.encode()).unwrap();
+        }
+
+        fn receive(&mut self) -> FixMessage {
+            FixMessage::decode(&read_message(&mut self.reader).unwrap().unwrap()).unwrap()
+        }
+    }
+
+    fn new_order(cl_ord_id: u64, side: &str, price: u64, quantity: u64) -> FixMessage {
+        FixMessage::new("D")
+            .with(tag::CL_ORD_ID, cl_ord_id)
+            .with(tag::SYMBOL, "XYZ")
+            .with(tag::SIDE, side)
+            .with(tag::ORD_TYPE, 2)
+            .with(tag::PRICE, price)
+            .with(tag::ORDER_QTY, quantity)
+    }
+
+    #[test]
+    fn loopback_fix_sessions() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let acceptor = FixAcceptor::spawn(listener, Exchange::new(ExchangeConfig::default()), "EXCHANGE", None).unwrap();
+        let mut buyer = Client::logon(&acceptor, "BUYER");
+        let mut seller = Client::logon(&acceptor, "SELLER");
+
+        buyer.send(new_order(1, "1", 100, 10));
+        let new = buyer.receive();
+        assert_eq!((new.get(tag::EXEC_TYPE), new.get(tag::TARGET_COMP_ID), new.get(tag::MSG_SEQ_NUM)), (Some("0"), Some("BUYER"), Some("2")));
+
+        seller.send(new_order(2, "2", 99, 4));
+        assert_eq!(seller.receive().get(tag::EXEC_TYPE), Some("0"));
+        let seller_fill = seller.receive();
+        assert_eq!((seller_fill.get(tag::ORD_STATUS), seller_fill.get(tag::LAST_PX)), (Some("2"), Some("100")));
+        let buyer_fill = buyer.receive();
+        assert_eq!((buyer_fill.get(tag::ORD_STATUS), buyer_fill.get(tag::LEAVES_QTY)), (Some("1"), Some("6")));
+
+        buyer.send(FixMessage::new("G")
+            .with(tag::CL_ORD_ID, 3)
+            .with(tag::ORIG_CL_ORD_ID, 1)
+            .with(tag::SIDE, 1)
+            .with(tag::ORD_TYPE, 2)
+            .with(tag::PRICE, 101)
+            .with(tag::ORDER_QTY, 8));
+        let replaced = buyer.receive();
+        assert_eq!((replaced.get(tag::EXEC_TYPE), replaced.get(tag::CL_ORD_ID), replaced.get(tag::LEAVES_QTY)), (Some("5"), Some("3"), Some("4")));
+
+        buyer.send(FixMessage::new("F").with(tag::CL_ORD_ID, "cancel-3").with(tag::ORIG_CL_ORD_ID, 3).with(tag::SIDE, 1));
+        let cancelled = buyer.receive();
+        assert_eq!((cancelled.get(tag::EXEC_TYPE), cancelled.get(tag::CL_ORD_ID), cancelled.get(tag::CUM_QTY)), (Some("4"), Some("cancel-3"), Some("4")));
+
+        buyer.send(FixMessage::new("5"));
+        assert_eq!(buyer.receive().msg_type(), msg_type::LOGOUT);
+    }
+
+    #[test]
+    fn lagging_session_is_shut_down() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        // Nothing drains this queue, like a writer stuck on a counterparty
+        // that stopped reading.
+        let (queue, messages) = mpsc::sync_channel(WRITE_QUEUE);
+        let mut connection = Connection {session: FixSession::new("EXCHANGE", Instant::now()), writer: Writer {queue, stream}};
+        let heartbeats = |count| (0..count).map(|_| FixMessage::new(msg_type::HEARTBEAT)).collect::<Vec<_>>();
+
+        assert!(send(&mut connection, heartbeats(WRITE_QUEUE)));
+        assert!(!send(&mut connection, heartbeats(1)));
+        assert_eq!(messages.try_iter().count(), WRITE_QUEUE);
+        assert_eq!(client.read(&mut [0; 1]).unwrap(), 0);
+    }
+}
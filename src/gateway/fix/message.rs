@@ -0,0 +1,315 @@
+use std::fmt;
+use std::io::{self, BufRead, Read};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const SOH: u8 = 0x01;
+pub const BEGIN_STRING: &str = "FIX.4.4";
+const MAX_MESSAGE_LENGTH: usize = 65_536;
+
+pub mod tag {
+    pub const ACCOUNT: u32 = 1;
+    pub const AVG_PX: u32 = 6;
+    pub const BEGIN_SEQ_NO: u32 = 7;
+    pub const BEGIN_STRING: u32 = 8;
+    pub const BODY_LENGTH: u32 = 9;
+    pub const CHECK_SUM: u32 = 10;
+    pub const CL_ORD_ID: u32 = 11;
+    pub const CUM_QTY: u32 = 14;
+    pub const END_SEQ_NO: u32 = 16;
+    pub const EXEC_ID: u32 = 17;
+    pub const LAST_PX: u32 = 31;
+    pub const LAST_QTY: u32 = 32;
+    pub const MSG_SEQ_NUM: u32 = 34;
+    pub const MSG_TYPE: u32 = 35;
+    pub const NEW_SEQ_NO: u32 = 36;
+    pub const ORDER_ID: u32 = 37;
+    pub const ORDER_QTY: u32 = 38;
+    pub const ORD_STATUS: u32 = 39;
+    pub const ORD_TYPE: u32 = 40;
+    pub const ORIG_CL_ORD_ID: u32 = 41;
+    pub const POSS_DUP_FLAG: u32 = 43;
+    pub const PRICE: u32 = 44;
+    pub const REF_SEQ_NUM: u32 = 45;
+    pub const SENDER_COMP_ID: u32 = 49;
+    pub const SENDING_TIME: u32 = 52;
+    pub const SIDE: u32 = 54;
+    pub const SYMBOL: u32 = 55;
+    pub const TARGET_COMP_ID: u32 = 56;
+    pub const TEXT: u32 = 58;
+    pub const ENCRYPT_METHOD: u32 = 98;
+    pub const CXL_REJ_REASON: u32 = 102;
+    pub const HEART_BT_INT: u32 = 108;
+    pub const MAX_FLOOR: u32 = 111;
+    pub const TEST_REQ_ID: u32 = 112;
+    pub const GAP_FILL_FLAG: u32 = 123;
+    pub const EXEC_TYPE: u32 = 150;
+    pub const LEAVES_QTY: u32 = 151;
+    pub const CXL_REJ_RESPONSE_TO: u32 = 434;
+}
+
+pub mod msg_type {
+    pub const HEARTBEAT: &str = "0";
+    pub const TEST_REQUEST: &str = "1";
+    pub const RESEND_REQUEST: &str = "2";
+    pub const REJECT: &str = "3";
+    pub const SEQUENCE_RESET: &str = "4";
+    pub const LOGOUT: &str = "5";
+    pub const EXECUTION_REPORT: &str = "8";
+    pub const ORDER_CANCEL_REJECT: &str = "9";
+    pub const LOGON: &str = "A";
+    pub const NEW_ORDER_SINGLE: &str = "D";
+    pub const ORDER_CANCEL_REQUEST: &str = "F";
+    pub const ORDER_CANCEL_REPLACE_REQUEST: &str = "G";
+}
+
+// Standard header fields are always encoded first, in this order, whatever
+// order they were set in.
+const HEADER_TAGS: [u32; 6] = [tag::MSG_TYPE, tag::SENDER_COMP_ID, tag::TARGET_COMP_ID, tag::MSG_SEQ_NUM, tag::POSS_DUP_FLAG, tag::SENDING_TIME];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FixError {
+    Garbled(String),
+    MissingField(u32),
+    InvalidField(u32, String)
+}
+
+impl fmt::Display for FixError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FixError::Garbled(reason) => write!(f, "garbled message: {}", reason),
+            FixError::MissingField(tag) => write!(f, "required tag {} missing", tag),
+            FixError::InvalidField(tag, value) => write!(f, "invalid value {} for tag {}", value, tag)
+        }
+    }
+}
+
+// A message without BeginString, BodyLength and CheckSum, which are computed
+// when encoding and verified when decoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixMessage {
+    fields: Vec<(u32, String)>
+}
+
+impl FixMessage {
+    pub fn new(msg_type: &str) -> Self {
+        FixMessage {fields: vec![(tag::MSG_TYPE, msg_type.to_string())]}
+    }
+
+    pub fn with<V: ToString>(mut self, tag: u32, value: V) -> Self {
+        self.set(tag, value);
+        self
+    }
+
+    pub fn set<V: ToString>(&mut self, tag: u32, value: V) {
+        let value = value.to_string();
+        match self.fields.iter_mut().find(|(field_tag, _)| *field_tag == tag) {
+            Some(field) => field.1 = value,
+            None => self.fields.push((tag, value))
+        }
+    }
+
+    pub fn msg_type(&self) -> &str {
+        self.get(tag::MSG_TYPE).unwrap_or_default()
+    }
+
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field_tag, _)| *field_tag == tag)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn required(&self, tag: u32) -> Result<&str, FixError> {
+        self.get(tag).ok_or(FixError::MissingField(tag))
+    }
+
+    pub fn number<T: FromStr>(&self, tag: u32) -> Result<T, FixError> {
+        let value = self.required(tag)?;
+        value.parse().map_err(|_| FixError::InvalidField(tag, value.to_string()))
+    }
+
+    pub fn optional_number<T: FromStr>(&self, tag: u32) -> Result<Option<T>, FixError> {
+        match self.get(tag) {
+            None => Ok(None),
+            Some(_) => self.number(tag).map(Some)
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        let header = HEADER_TAGS.iter().filter_map(|tag| self.fields.iter().find(|(field_tag, _)| field_tag == tag));
+        let rest = self.fields.iter().filter(|(tag, _)| !HEADER_TAGS.contains(tag));
+        for (tag, value) in header.chain(rest) {
+            push_field(&mut body, *tag, value);
+        }
+
+        let mut message = Vec::with_capacity(body.len() + 32);
+        push_field(&mut message, tag::BEGIN_STRING, BEGIN_STRING);
+        push_field(&mut message, tag::BODY_LENGTH, &body.len().to_string());
+        message.extend_from_slice(&body);
+        let check_sum = checksum(&message);
+        push_field(&mut message, tag::CHECK_SUM, &format!("{:03}", check_sum));
+        message
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<FixMessage, FixError> {
+        if bytes.last() != Some(&SOH) {
+            return Err(FixError::Garbled("message does not end with SOH".to_string()));
+        }
+        let mut fields = Vec::new();
+        let mut offset = 0;
+        let mut body_start = 0;
+        let mut trailer_start = 0;
+        for raw_field in bytes[..bytes.len() - 1].split(|byte| *byte == SOH) {
+            let field = std::str::from_utf8(raw_field).map_err(|_| FixError::Garbled("field is not UTF-8".to_string()))?;
+            let (tag, value) = field
+                .split_once('=')
+                .and_then(|(tag, value)| tag.parse::<u32>().ok().map(|tag| (tag, value)))
+                .ok_or_else(|| FixError::Garbled(format!("malformed field {}", field)))?;
+            match (fields.len(), tag) {
+                (0, tag::BEGIN_STRING) | (1, tag::BODY_LENGTH) | (2, tag::MSG_TYPE) => (),
+                (0, _) | (1, _) | (2, _) => return Err(FixError::Garbled("message must start with tags 8, 9 and 35".to_string())),
+                (_, tag::CHECK_SUM) => trailer_start = offset,
+                _ => ()
+            }
+            fields.push((tag, value.to_string()));
+            offset += raw_field.len() + 1;
+            if fields.len() == 2 {
+                body_start = offset;
+            }
+        }
+
+        match fields.last() {
+            Some((tag::CHECK_SUM, _)) => (),
+            _ => return Err(FixError::Garbled("message must end with tag 10".to_string()))
+        }
+        if fields[0].1 != BEGIN_STRING {
+            return Err(FixError::Garbled(format!("unsupported BeginString {}", fields[0].1)));
+        }
+        if fields[1].1.parse::<usize>().ok() != Some(trailer_start - body_start) {
+            return Err(FixError::Garbled(format!("BodyLength {} does not match {}", fields[1].1, trailer_start - body_start)));
+        }
+        let expected_check_sum = format!("{:03}", checksum(&bytes[..trailer_start]));
+        if fields[fields.len() - 1].1 != expected_check_sum {
+            return Err(FixError::Garbled(format!("CheckSum {} does not match {}", fields[fields.len() - 1].1, expected_check_sum)));
+        }
+
+        fields.pop();
+        fields.drain(..2);
+        Ok(FixMessage {fields})
+    }
+}
+
+impl fmt::Display for FixMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let encoded = self.encode();
+        let printable: String = encoded.iter().map(|byte| if *byte == SOH { '|' } else { *byte as char }).collect();
+        write!(f, "{}", printable)
+    }
+}
+
+fn push_field(buffer: &mut Vec<u8>, tag: u32, value: &str) {
+    buffer.extend_from_slice(tag.to_string().as_bytes());
+    buffer.push(b'=');
+    buffer.extend_from_slice(value.as_bytes());
+    buffer.push(SOH);
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+// Reads one raw message, up to and including the CheckSum field. Returns
+// `None` once the stream ends and fails on a message longer than
+// `MAX_MESSAGE_LENGTH` bytes.
+pub fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut message = Vec::new();
+    loop {
+        let field_start = message.len();
+        let limit = (MAX_MESSAGE_LENGTH - message.len()) as u64;
+        if reader.take(limit).read_until(SOH, &mut message)? == 0 {
+            if message.len() == MAX_MESSAGE_LENGTH {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "message too long"));
+            }
+            return Ok(None);
+        }
+        if message[field_start..].starts_with(b"10=") {
+            return Ok(Some(message));
+        }
+    }
+}
+
+// UTCTimestamp with milliseconds, e.g. 20240131-09:30:00.000.
+pub fn utc_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (days, seconds_of_day) = ((seconds / 86_400) as i64, seconds % 86_400);
+
+    // Civil date from days since 1970-01-01, after Howard Hinnant.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}-{:02}:{:02}:{:02}.{:03}",
+        year, month, day,
+        seconds_of_day / 3600, seconds_of_day / 60 % 60, seconds_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+    use crate::*;
+    use gateway::fix::message::{read_message, tag, utc_timestamp, MAX_MESSAGE_LENGTH};
+
+    #[test]
+    fn encode_and_decode() {
+        let message = FixMessage::new("D")
+            .with(tag::CL_ORD_ID, 7)
+            .with(tag::MSG_SEQ_NUM, 2)
+            .with(tag::SENDER_COMP_ID, "CLIENT")
+            .with(tag::PRICE, 100);
+        let encoded = message.encode();
+        assert_eq!(message.to_string(), "8=FIX.4.4|9=32|35=D|49=CLIENT|34=2|11=7|44=100|10=054|");
+
+        let decoded = FixMessage::decode(&encoded).unwrap();
+        assert_eq!(decoded.msg_type(), "D");
+        assert_eq!(decoded.number::<u64>(tag::CL_ORD_ID), Ok(7));
+        assert_eq!(decoded.number::<u64>(tag::ORDER_QTY), Err(FixError::MissingField(tag::ORDER_QTY)));
+
+        let stream = [encoded.clone(), encoded.clone()].concat();
+        let mut reader = stream.as_slice();
+        assert_eq!(read_message(&mut reader).unwrap(), Some(encoded.clone()));
+        assert_eq!(read_message(&mut reader).unwrap(), Some(encoded));
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+
+        let endless = [b"8=FIX.4.4\x01".to_vec(), vec![b'1'; MAX_MESSAGE_LENGTH]].concat();
+        let error = read_message(&mut endless.as_slice()).unwrap_err();
+        assert_eq!((error.kind(), error.to_string()), (std::io::ErrorKind::InvalidData, "message too long".to_string()));
+    }
+
+    #[test]
+    fn reject_garbled_messages() {
+        let mut encoded = FixMessage::new("0").encode();
+        let length = encoded.len();
+        encoded[length - 2] = b'0';
+        assert!(matches!(FixMessage::decode(&encoded), Err(FixError::Garbled(_))));
+        assert!(matches!(FixMessage::decode(b"8=FIX.4.4\x019=5\x0135=0\x0110=000\x01"), Err(FixError::Garbled(_))));
+        assert!(matches!(FixMessage::decode(b"35=0\x0110=000\x01"), Err(FixError::Garbled(_))));
+    }
+
+    #[test]
+    fn format_utc_timestamps() {
+        assert_eq!(utc_timestamp(UNIX_EPOCH), "19700101-00:00:00.000");
+        assert_eq!(utc_timestamp(UNIX_EPOCH + Duration::from_millis(1_709_210_096_789)), "20240229-12:34:56.789");
+    }
+}
@@ -0,0 +1,3 @@
+pub mod acceptor;
+pub mod message;
+pub mod session;
@@ -0,0 +1,666 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::gateway::session::SessionReport;
+use crate::matching_engine::order::OrderSide;
+use crate::matching_engine::parse::{DeserializedCommand, DeserializedOrder, InboundMessage, OrderCore};
+use super::message::{msg_type, tag, utc_timestamp, FixError, FixMessage};
+
+const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Default)]
+pub struct FixActions {
+    pub replies: Vec<FixMessage>,
+    pub request: Option<InboundMessage>,
+    pub disconnect: bool
+}
+
+struct FixOrder {
+    symbol: String,
+    side: OrderSide,
+    price: u64,
+    order_qty: u64,
+    cum_qty: u64,
+    notional: u128,
+    max_floor: Option<u64>
+}
+
+enum PendingRequest {
+    New,
+    Cancel {cl_ord_id: String, orig_cl_ord_id: u64},
+    Replace {cl_ord_id: String, orig_cl_ord_id: u64}
+}
+
+// Session level state of one FIX 4.4 connection: logon, sequence numbers and
+// heartbeats, plus the per-order state needed to fill in execution reports.
+// ClOrdIDs must be numeric, they become the engine's order ids.
+pub struct FixSession {
+    comp_id: String,
+    counterparty: Option<String>,
+    heartbeat_interval: Duration,
+    next_incoming: u64,
+    next_outgoing: u64,
+    now: Instant,
+    last_received: Instant,
+    last_sent: Instant,
+    test_request_sent: bool,
+    orders: HashMap<u64, FixOrder>,
+    pending: Option<PendingRequest>,
+    next_exec_id: u64
+}
+
+impl FixOrder {
+    fn leaves_qty(&self) -> u64 {
+        self.order_qty - self.cum_qty
+    }
+
+    fn ord_status(&self) -> &'static str {
+        if self.cum_qty == 0 {
+            "0"
+        } else if self.cum_qty < self.order_qty {
+            "1"
+        } else {
+            "2"
+        }
+    }
+
+    fn avg_px(&self) -> f64 {
+        if self.cum_qty == 0 { 0.0 } else { self.notional as f64 / self.cum_qty as f64 }
+    }
+}
+
+fn side_code(side: OrderSide) -> &'static str {
+    match side {
+        OrderSide::Buy => "1",
+        OrderSide::Sell => "2"
+    }
+}
+
+fn parse_side(message: &FixMessage) -> Result<OrderSide, FixError> {
+    match message.required(tag::SIDE)? {
+        "1" => Ok(OrderSide::Buy),
+        "2" => Ok(OrderSide::Sell),
+        other => Err(FixError::InvalidField(tag::SIDE, other.to_string()))
+    }
+}
+
+fn require_limit(message: &FixMessage) -> Result<(), FixError> {
+    match message.required(tag::ORD_TYPE)? {
+        "2" => Ok(()),
+        other => Err(FixError::InvalidField(tag::ORD_TYPE, other.to_string()))
+    }
+}
+
+fn parse_new_order(message: &FixMessage) -> Result<(u64, FixOrder), FixError> {
+    let id = message.number::<u64>(tag::CL_ORD_ID)?;
+    require_limit(message)?;
+    let order = FixOrder {
+        symbol: message.required(tag::SYMBOL)?.to_string(),
+        side: parse_side(message)?,
+        price: message.number(tag::PRICE)?,
+        order_qty: message.number(tag::ORDER_QTY)?,
+        cum_qty: 0,
+        notional: 0,
+        max_floor: message.optional_number(tag::MAX_FLOOR)?
+    };
+    if order.order_qty == 0 {
+        return Err(FixError::InvalidField(tag::ORDER_QTY, "0".to_string()));
+    }
+    if order.max_floor == Some(0) {
+        return Err(FixError::InvalidField(tag::MAX_FLOOR, "0".to_string()));
+    }
+    Ok((id, order))
+}
+
+fn parse_replace(message: &FixMessage) -> Result<(u64, OrderSide, u64, u64), FixError> {
+    let new_id = message.number::<u64>(tag::CL_ORD_ID)?;
+    require_limit(message)?;
+    Ok((new_id, parse_side(message)?, message.number(tag::PRICE)?, message.number(tag::ORDER_QTY)?))
+}
+
+fn execution_report(exec_id: u64, order_id: u64, order: &FixOrder, exec_type: &str, ord_status: &str) -> FixMessage {
+    let mut execution_report = FixMessage::new(msg_type::EXECUTION_REPORT)
+        .with(tag::ORDER_ID, order_id)
+        .with(tag::CL_ORD_ID, order_id)
+        .with(tag::EXEC_ID, exec_id)
+        .with(tag::EXEC_TYPE, exec_type)
+        .with(tag::ORD_STATUS, ord_status)
+        .with(tag::SYMBOL, &order.symbol)
+        .with(tag::SIDE, side_code(order.side))
+        .with(tag::ORDER_QTY, order.order_qty)
+        .with(tag::PRICE, order.price)
+        .with(tag::LEAVES_QTY, order.leaves_qty())
+        .with(tag::CUM_QTY, order.cum_qty)
+        .with(tag::AVG_PX, order.avg_px());
+    if let Some(max_floor) = order.max_floor {
+        execution_report.set(tag::MAX_FLOOR, max_floor);
+    }
+    execution_report
+}
+
+impl FixSession {
+    pub fn new(comp_id: &str, now: Instant) -> Self {
+        FixSession {
+            comp_id: comp_id.to_string(),
+            counterparty: None,
+            heartbeat_interval: DEFAULT_HEARTBEAT_INTERVAL,
+            next_incoming: 1,
+            next_outgoing: 1,
+            now,
+            last_received: now,
+            last_sent: now,
+            test_request_sent: false,
+            orders: HashMap::new(),
+            pending: None,
+            next_exec_id: 1
+        }
+    }
+
+    pub fn is_logged_on(&self) -> bool {
+        self.counterparty.is_some()
+    }
+
+    pub fn receive(&mut self, message: &FixMessage, now: Instant) -> FixActions {
+        self.now = now;
+        self.last_received = now;
+        self.test_request_sent = false;
+        self.pending = None;
+        let mut actions = FixActions::default();
+
+        let sequence = match message.number::<u64>(tag::MSG_SEQ_NUM) {
+            Ok(sequence) => sequence,
+            Err(error) => return self.logout(error.to_string())
+        };
+        if !self.is_logged_on() && message.msg_type() != msg_type::LOGON {
+            return self.logout("first message must be Logon".to_string());
+        }
+        if message.msg_type() == msg_type::SEQUENCE_RESET {
+            if let Ok(new_sequence) = message.number::<u64>(tag::NEW_SEQ_NO) {
+                self.next_incoming = self.next_incoming.max(new_sequence);
+            }
+            return actions;
+        }
+        if sequence < self.next_incoming {
+            if message.get(tag::POSS_DUP_FLAG) == Some("Y") {
+                return actions;
+            }
+            return self.logout(format!("MsgSeqNum too low, expecting {} but received {}", self.next_incoming, sequence));
+        }
+        if sequence > self.next_incoming {
+            let resend_request = FixMessage::new(msg_type::RESEND_REQUEST)
+                .with(tag::BEGIN_SEQ_NO, self.next_incoming)
+                .with(tag::END_SEQ_NO, 0);
+            if message.msg_type() != msg_type::LOGON {
+                actions.replies.push(self.stamp(resend_request));
+                return actions;
+            }
+            actions = self.logon(message);
+            if !actions.disconnect {
+                actions.replies.push(self.stamp(resend_request));
+            }
+            return actions;
+        }
+        self.next_incoming += 1;
+
+        match message.msg_type() {
+            msg_type::LOGON => return self.logon(message),
+            msg_type::HEARTBEAT => (),
+            msg_type::TEST_REQUEST => {
+                let mut heartbeat = FixMessage::new(msg_type::HEARTBEAT);
+                if let Some(test_req_id) = message.get(tag::TEST_REQ_ID) {
+                    heartbeat.set(tag::TEST_REQ_ID, test_req_id);
+                }
+                actions.replies.push(self.stamp(heartbeat));
+            }
+            // Sent messages are not stored, so a resend request is answered
+            // with a gap fill up to the next sequence number.
+            msg_type::RESEND_REQUEST => {
+                let begin_sequence = message.number::<u64>(tag::BEGIN_SEQ_NO).unwrap_or(1);
+                let mut gap_fill = self.stamp(FixMessage::new(msg_type::SEQUENCE_RESET)
+                    .with(tag::GAP_FILL_FLAG, "Y")
+                    .with(tag::NEW_SEQ_NO, self.next_outgoing));
+                self.next_outgoing -= 1;
+                gap_fill.set(tag::MSG_SEQ_NUM, begin_sequence);
+                gap_fill.set(tag::POSS_DUP_FLAG, "Y");
+                actions.replies.push(gap_fill);
+            }
+            msg_type::LOGOUT => {
+                actions.replies.push(self.stamp(FixMessage::new(msg_type::LOGOUT)));
+                actions.disconnect = true;
+            }
+            msg_type::NEW_ORDER_SINGLE => match self.new_order(message) {
+                Ok(request) => actions.request = Some(request),
+                Err(reply) => actions.replies.push(reply)
+            },
+            msg_type::ORDER_CANCEL_REQUEST => match self.cancel_request(message) {
+                Ok(request) => actions.request = Some(request),
+                Err(reply) => actions.replies.push(reply)
+            },
+            msg_type::ORDER_CANCEL_REPLACE_REQUEST => match self.replace_request(message) {
+                Ok(request) => actions.request = Some(request),
+                Err(reply) => actions.replies.push(reply)
+            },
+            other => {
+                let reject = self.session_reject(sequence, format!("unsupported MsgType {}", other));
+                actions.replies.push(reject);
+            }
+        }
+        actions
+    }
+
+    // Sends heartbeats when the session has been quiet, probes a silent
+    // counterparty with a test request and gives up after two intervals.
+    pub fn tick(&mut self, now: Instant) -> FixActions {
+        self.now = now;
+        let mut actions = FixActions::default();
+        let silence = now.saturating_duration_since(self.last_received);
+        if silence > self.heartbeat_interval * 2 {
+            return self.logout("heartbeat timeout".to_string());
+        }
+        if !self.is_logged_on() {
+            return actions;
+        }
+        if silence > self.heartbeat_interval + self.heartbeat_interval / 5 && !self.test_request_sent {
+            self.test_request_sent = true;
+            let test_request = FixMessage::new(msg_type::TEST_REQUEST).with(tag::TEST_REQ_ID, self.next_outgoing);
+            actions.replies.push(self.stamp(test_request));
+        } else if now.saturating_duration_since(self.last_sent) >= self.heartbeat_interval {
+            actions.replies.push(self.stamp(FixMessage::new(msg_type::HEARTBEAT)));
+        }
+        actions
+    }
+
+    // Turns a report routed to this session into execution reports.
+    pub fn report(&mut self, report: &SessionReport) -> Vec<FixMessage> {
+        match report {
+            SessionReport::Accepted(accepted) => {
+                let order_id = accepted.accepted_order_id;
+                if !self.orders.contains_key(&order_id) {
+                    return vec![];
+                }
+                let exec_id = self.exec_id();
+                let order = &self.orders[&order_id];
+                let execution_report = execution_report(exec_id, order_id, order, "0", order.ord_status());
+                vec![self.stamp(execution_report)]
+            }
+            SessionReport::Fill(annotated_fill) => {
+                let fill_event = annotated_fill.fill_event;
+                let mut execution_reports = Vec::new();
+                for order_id in [fill_event.buy_order_id, fill_event.sell_order_id].iter() {
+                    let order = match self.orders.get_mut(order_id) {
+                        None => continue,
+                        Some(order) => order
+                    };
+                    order.cum_qty += fill_event.quantity;
+                    order.notional += fill_event.price as u128 * fill_event.quantity as u128;
+                    let exec_id = self.exec_id();
+                    let order = &self.orders[order_id];
+                    let execution_report = execution_report(exec_id, *order_id, order, "F", order.ord_status())
+                        .with(tag::LAST_QTY, fill_event.quantity)
+                        .with(tag::LAST_PX, fill_event.price);
+                    if order.leaves_qty() == 0 {
+                        self.orders.remove(order_id);
+                    }
+                    execution_reports.push(self.stamp(execution_report));
+                }
+                execution_reports
+            }
+            SessionReport::Cancelled(cancelled) => {
+                let order_id = cancelled.cancelled_order_id;
+                let order = match self.orders.remove(&order_id) {
+                    None => return vec![],
+                    Some(order) => order
+                };
+                let cl_ord_id = match self.pending {
+                    Some(PendingRequest::Cancel {ref cl_ord_id, orig_cl_ord_id}) if orig_cl_ord_id == order_id => cl_ord_id.clone(),
+                    _ => order_id.to_string()
+                };
+                let execution_report = execution_report(self.exec_id(), order_id, &order, "4", "4")
+                    .with(tag::CL_ORD_ID, cl_ord_id)
+                    .with(tag::ORIG_CL_ORD_ID, order_id)
+                    .with(tag::LEAVES_QTY, 0);
+                vec![self.stamp(execution_report)]
+            }
+            SessionReport::Replaced(replaced) => {
+                let mut order = match self.orders.remove(&replaced.replaced_order_id) {
+                    None => return vec![],
+                    Some(order) => order
+                };
+                order.price = replaced.price;
+                order.order_qty = order.cum_qty + replaced.quantity;
+                let execution_report = execution_report(self.exec_id(), replaced.order_id, &order, "5", order.ord_status())
+                    .with(tag::ORIG_CL_ORD_ID, replaced.replaced_order_id);
+                self.orders.insert(replaced.order_id, order);
+                vec![self.stamp(execution_report)]
+            }
+            SessionReport::Rejected(rejected) => {
+                let order_id = rejected.rejected_order_id;
+                let cancel_reject = match self.pending {
+                    Some(PendingRequest::Cancel {ref cl_ord_id, orig_cl_ord_id}) if orig_cl_ord_id == order_id => Some((cl_ord_id.clone(), "1")),
+                    Some(PendingRequest::Replace {ref cl_ord_id, orig_cl_ord_id}) if orig_cl_ord_id == order_id => Some((cl_ord_id.clone(), "2")),
+                    _ => None
+                };
+                let reply = match cancel_reject {
+                    Some((cl_ord_id, response_to)) => self.cancel_reject(&cl_ord_id, order_id, response_to, &rejected.reason),
+                    None => match self.orders.remove(&order_id) {
+                        None => return vec![],
+                        Some(order) => execution_report(self.exec_id(), order_id, &order, "8", "8")
+                            .with(tag::LEAVES_QTY, 0)
+                            .with(tag::TEXT, &rejected.reason)
+                    }
+                };
+                vec![self.stamp(reply)]
+            }
+            SessionReport::Error(error) => {
+                let reject = self.session_reject(self.next_incoming - 1, error.error.clone());
+                vec![reject]
+            }
+            SessionReport::Status(_) | SessionReport::NotFound(_) | SessionReport::Positions(_) | SessionReport::Balances(_) | SessionReport::Statistics(_)
+            | SessionReport::Metrics(_) | SessionReport::Trade(_) => vec![]
+        }
+    }
+
+    fn logon(&mut self, message: &FixMessage) -> FixActions {
+        let mut actions = FixActions::default();
+        if self.is_logged_on() {
+            return actions;
+        }
+        if message.get(tag::TARGET_COMP_ID) != Some(self.comp_id.as_str()) {
+            return self.logout(format!("TargetCompID must be {}", self.comp_id));
+        }
+        let counterparty = match message.required(tag::SENDER_COMP_ID) {
+            Ok(counterparty) => counterparty.to_string(),
+            Err(error) => return self.logout(error.to_string())
+        };
+        let heartbeat_seconds = match message.number::<u64>(tag::HEART_BT_INT) {
+            Ok(heartbeat_seconds) if heartbeat_seconds > 0 => heartbeat_seconds,
+            _ => return self.logout("HeartBtInt must be a positive number of seconds".to_string())
+        };
+
+        self.counterparty = Some(counterparty);
+        self.heartbeat_interval = Duration::from_secs(heartbeat_seconds);
+        let logon = FixMessage::new(msg_type::LOGON)
+            .with(tag::ENCRYPT_METHOD, 0)
+            .with(tag::HEART_BT_INT, heartbeat_seconds);
+        actions.replies.push(self.stamp(logon));
+        actions
+    }
+
+    fn logout(&mut self, text: String) -> FixActions {
+        let logout = FixMessage::new(msg_type::LOGOUT).with(tag::TEXT, text);
+        FixActions {replies: vec![self.stamp(logout)], request: None, disconnect: true}
+    }
+
+    fn new_order(&mut self, message: &FixMessage) -> Result<InboundMessage, FixMessage> {
+        let cl_ord_id = match message.required(tag::CL_ORD_ID) {
+            Ok(cl_ord_id) => cl_ord_id,
+            Err(error) => return Err(self.session_reject(self.next_incoming - 1, error.to_string()))
+        };
+        let parsed = parse_new_order(message);
+        let (id, order) = match parsed {
+            Ok(parsed) => parsed,
+            Err(error) => return Err(self.order_reject(cl_ord_id, message, error.to_string()))
+        };
+        if self.orders.contains_key(&id) {
+            return Err(self.order_reject(cl_ord_id, message, "duplicate ClOrdID".to_string()));
+        }
+
+        let order_core = OrderCore {
+            direction: order.side,
+            id,
+            price: order.price,
+            quantity: order.order_qty,
+            account: message.get(tag::ACCOUNT).unwrap_or_default().to_string()
+        };
+        let deserialized_order = match order.max_floor {
+            Some(peak) if peak < order.order_qty => DeserializedOrder::Iceberg {order_core, peak},
+            _ => DeserializedOrder::Limit {order_core}
+        };
+        self.orders.insert(id, order);
+        self.pending = Some(PendingRequest::New);
+        Ok(InboundMessage::Order(deserialized_order))
+    }
+
+    fn cancel_request(&mut self, message: &FixMessage) -> Result<InboundMessage, FixMessage> {
+        let (cl_ord_id, orig_cl_ord_id) = match (message.required(tag::CL_ORD_ID), message.number::<u64>(tag::ORIG_CL_ORD_ID)) {
+            (Ok(cl_ord_id), Ok(orig_cl_ord_id)) => (cl_ord_id.to_string(), orig_cl_ord_id),
+            (Err(error), _) | (_, Err(error)) => return Err(self.session_reject(self.next_incoming - 1, error.to_string()))
+        };
+        if !self.orders.contains_key(&orig_cl_ord_id) {
+            let reject = self.cancel_reject(&cl_ord_id, orig_cl_ord_id, "1", "unknown order");
+            return Err(self.stamp(reject));
+        }
+        self.pending = Some(PendingRequest::Cancel {cl_ord_id, orig_cl_ord_id});
+        Ok(InboundMessage::Command(DeserializedCommand::Cancel {id: orig_cl_ord_id}))
+    }
+
+    // OrderQty is the new total quantity including what already filled, the
+    // engine is asked for the remaining quantity.
+    fn replace_request(&mut self, message: &FixMessage) -> Result<InboundMessage, FixMessage> {
+        let (cl_ord_id, orig_cl_ord_id) = match (message.required(tag::CL_ORD_ID), message.number::<u64>(tag::ORIG_CL_ORD_ID)) {
+            (Ok(cl_ord_id), Ok(orig_cl_ord_id)) => (cl_ord_id.to_string(), orig_cl_ord_id),
+            (Err(error), _) | (_, Err(error)) => return Err(self.session_reject(self.next_incoming - 1, error.to_string()))
+        };
+        let parsed = parse_replace(message);
+        let (new_id, side, price, order_qty) = match parsed {
+            Ok(parsed) => parsed,
+            Err(error) => {
+                let reject = self.cancel_reject(&cl_ord_id, orig_cl_ord_id, "2", &error.to_string());
+                return Err(self.stamp(reject));
+            }
+        };
+        let reason = match self.orders.get(&orig_cl_ord_id) {
+            None => Some("unknown order"),
+            Some(order) if order.side != side => Some("side cannot be changed"),
+            Some(order) if order_qty <= order.cum_qty => Some("OrderQty must exceed CumQty"),
+            Some(_) if new_id != orig_cl_ord_id && self.orders.contains_key(&new_id) => Some("duplicate ClOrdID"),
+            Some(_) => None
+        };
+        if let Some(reason) = reason {
+            let reject = self.cancel_reject(&cl_ord_id, orig_cl_ord_id, "2", reason);
+            return Err(self.stamp(reject));
+        }
+
+        let quantity = order_qty - self.orders[&orig_cl_ord_id].cum_qty;
+        self.pending = Some(PendingRequest::Replace {cl_ord_id, orig_cl_ord_id});
+        Ok(InboundMessage::Command(DeserializedCommand::Replace {
+            id: orig_cl_ord_id,
+            new_id: if new_id == orig_cl_ord_id { None } else { Some(new_id) },
+            price,
+            quantity
+        }))
+    }
+
+    fn exec_id(&mut self) -> u64 {
+        self.next_exec_id += 1;
+        self.next_exec_id - 1
+    }
+
+    fn order_reject(&mut self, cl_ord_id: &str, message: &FixMessage, text: String) -> FixMessage {
+        let mut execution_report = FixMessage::new(msg_type::EXECUTION_REPORT)
+            .with(tag::ORDER_ID, "NONE")
+            .with(tag::CL_ORD_ID, cl_ord_id)
+            .with(tag::EXEC_ID, self.exec_id())
+            .with(tag::EXEC_TYPE, "8")
+            .with(tag::ORD_STATUS, "8");
+        for field in [tag::SYMBOL, tag::SIDE].iter() {
+            if let Some(value) = message.get(*field) {
+                execution_report.set(*field, value);
+            }
+        }
+        let execution_report = execution_report
+            .with(tag::LEAVES_QTY, 0)
+            .with(tag::CUM_QTY, 0)
+            .with(tag::AVG_PX, 0)
+            .with(tag::TEXT, text);
+        self.stamp(execution_report)
+    }
+
+    fn cancel_reject(&self, cl_ord_id: &str, orig_cl_ord_id: u64, response_to: &str, text: &str) -> FixMessage {
+        let (order_id, ord_status) = match self.orders.get(&orig_cl_ord_id) {
+            None => ("NONE".to_string(), "8"),
+            Some(order) => (orig_cl_ord_id.to_string(), order.ord_status())
+        };
+        let mut reject = FixMessage::new(msg_type::ORDER_CANCEL_REJECT)
+            .with(tag::ORDER_ID, order_id)
+            .with(tag::CL_ORD_ID, cl_ord_id)
+            .with(tag::ORIG_CL_ORD_ID, orig_cl_ord_id)
+            .with(tag::ORD_STATUS, ord_status)
+            .with(tag::CXL_REJ_RESPONSE_TO, response_to)
+            .with(tag::TEXT, text);
+        if !self.orders.contains_key(&orig_cl_ord_id) {
+            reject.set(tag::CXL_REJ_REASON, 1);
+        }
+        reject
+    }
+
+    fn session_reject(&mut self, ref_sequence: u64, text: String) -> FixMessage {
+        let reject = FixMessage::new(msg_type::REJECT)
+            .with(tag::REF_SEQ_NUM, ref_sequence)
+            .with(tag::TEXT, text);
+        self.stamp(reject)
+    }
+
+    fn stamp(&mut self, message: FixMessage) -> FixMessage {
+        let sequence = self.next_outgoing;
+        self.next_outgoing += 1;
+        self.last_sent = self.now;
+        message
+            .with(tag::SENDER_COMP_ID, &self.comp_id)
+            .with(tag::TARGET_COMP_ID, self.counterparty.as_deref().unwrap_or_default())
+            .with(tag::MSG_SEQ_NUM, sequence)
+            .with(tag::SENDING_TIME, utc_timestamp(SystemTime::now()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use crate::*;
+    use gateway::fix::message::{msg_type, tag};
+
+    fn logon(session: &mut FixSession, now: Instant) {
+        let logon = FixMessage::new("A")
+            .with(tag::SENDER_COMP_ID, "CLIENT")
+            .with(tag::TARGET_COMP_ID, "EXCHANGE")
+            .with(tag::MSG_SEQ_NUM, 1)
+            .with(tag::ENCRYPT_METHOD, 0)
+            .with(tag::HEART_BT_INT, 30);
+        let actions = session.receive(&logon, now);
+        assert_eq!(actions.replies[0].msg_type(), msg_type::LOGON);
+        assert!(session.is_logged_on());
+    }
+
+    fn new_order(sequence: u64, cl_ord_id: u64, side: &str, price: u64, quantity: u64) -> FixMessage {
+        FixMessage::new("D")
+            .with(tag::MSG_SEQ_NUM, sequence)
+            .with(tag::CL_ORD_ID, cl_ord_id)
+            .with(tag::SYMBOL, "XYZ")
+            .with(tag::SIDE, side)
+            .with(tag::ORD_TYPE, 2)
+            .with(tag::PRICE, price)
+            .with(tag::ORDER_QTY, quantity)
+    }
+
+    #[test]
+    fn sequence_handling() {
+        let now = Instant::now();
+        let mut session = FixSession::new("EXCHANGE", now);
+        let actions = session.receive(&new_order(1, 1, "1", 100, 10), now);
+        assert!(actions.disconnect);
+
+        let mut session = FixSession::new("EXCHANGE", now);
+        logon(&mut session, now);
+
+        let actions = session.receive(&new_order(5, 1, "1", 100, 10), now);
+        assert_eq!(actions.request, None);
+        assert_eq!(actions.replies[0].msg_type(), msg_type::RESEND_REQUEST);
+        assert_eq!(actions.replies[0].get(tag::BEGIN_SEQ_NO), Some("2"));
+
+        let reset = FixMessage::new("4").with(tag::MSG_SEQ_NUM, 2).with(tag::GAP_FILL_FLAG, "Y").with(tag::NEW_SEQ_NO, 5);
+        session.receive(&reset, now);
+        let actions = session.receive(&new_order(5, 1, "1", 100, 10), now);
+        assert!(actions.request.is_some());
+
+        let actions = session.receive(&new_order(3, 2, "1", 100, 10), now);
+        assert!(actions.disconnect);
+        assert_eq!(actions.replies[0].msg_type(), msg_type::LOGOUT);
+    }
+
+    #[test]
+    fn heartbeats_and_test_requests() {
+        let now = Instant::now();
+        let mut session = FixSession::new("EXCHANGE", now);
+        logon(&mut session, now);
+
+        let test_request = FixMessage::new("1").with(tag::MSG_SEQ_NUM, 2).with(tag::TEST_REQ_ID, "ping");
+        let actions = session.receive(&test_request, now);
+        assert_eq!(actions.replies[0].msg_type(), msg_type::HEARTBEAT);
+        assert_eq!(actions.replies[0].get(tag::TEST_REQ_ID), Some("ping"));
+
+        let actions = session.tick(now + Duration::from_secs(37));
+        assert_eq!(actions.replies[0].msg_type(), msg_type::TEST_REQUEST);
+        let actions = session.tick(now + Duration::from_secs(38));
+        assert!(actions.replies.is_empty());
+        let actions = session.tick(now + Duration::from_secs(61));
+        assert!(actions.disconnect);
+    }
+
+    #[test]
+    fn map_orders_and_reports() {
+        let now = Instant::now();
+        let mut session = FixSession::new("EXCHANGE", now);
+        logon(&mut session, now);
+
+        let iceberg = new_order(2, 7, "2", 101, 50).with(tag::MAX_FLOOR, 10).with(tag::ACCOUNT, "alice");
+        match session.receive(&iceberg, now).request {
+            Some(InboundMessage::Order(DeserializedOrder::Iceberg {order_core, peak})) => {
+                assert_eq!((order_core.id, order_core.direction, order_core.quantity, peak), (7, OrderSide::Sell, 50, 10));
+                assert_eq!(order_core.account, "alice");
+            }
+            other => panic!("unexpected request {:?}", other)
+        }
+        let new = session.report(&SessionReport::Accepted(OrderAccepted {accepted_order_id: 7}));
+        assert_eq!((new[0].get(tag::EXEC_TYPE), new[0].get(tag::MAX_FLOOR)), (Some("0"), Some("10")));
+
+        let fill_event = FillEvent {buy_order_id: 3, sell_order_id: 7, price: 101, quantity: 20};
+        let fill = session.report(&SessionReport::Fill(FeeAnnotatedFill {fill_event, taker_side: OrderSide::Buy, buy_fee: 0, sell_fee: 0}));
+        assert_eq!(fill.len(), 1);
+        assert_eq!((fill[0].get(tag::ORD_STATUS), fill[0].get(tag::LAST_QTY), fill[0].get(tag::LEAVES_QTY)), (Some("1"), Some("20"), Some("30")));
+
+        let replace = FixMessage::new("G")
+            .with(tag::MSG_SEQ_NUM, 3)
+            .with(tag::CL_ORD_ID, 8)
+            .with(tag::ORIG_CL_ORD_ID, 7)
+            .with(tag::SIDE, 2)
+            .with(tag::ORD_TYPE, 2)
+            .with(tag::PRICE, 102)
+            .with(tag::ORDER_QTY, 40);
+        let request = session.receive(&replace, now).request;
+        assert_eq!(request, Some(InboundMessage::Command(DeserializedCommand::Replace {id: 7, new_id: Some(8), price: 102, quantity: 20})));
+        let replaced = session.report(&SessionReport::Replaced(OrderReplaced {replaced_order_id: 7, order_id: 8, price: 102, quantity: 20}));
+        assert_eq!((replaced[0].get(tag::EXEC_TYPE), replaced[0].get(tag::CUM_QTY), replaced[0].get(tag::LEAVES_QTY)), (Some("5"), Some("20"), Some("20")));
+
+        let cancel = FixMessage::new("F").with(tag::MSG_SEQ_NUM, 4).with(tag::CL_ORD_ID, "c1").with(tag::ORIG_CL_ORD_ID, 7);
+        let actions = session.receive(&cancel, now);
+        assert_eq!(actions.request, None);
+        assert_eq!(actions.replies[0].msg_type(), msg_type::ORDER_CANCEL_REJECT);
+    }
+
+    #[test]
+    fn large_fills_do_not_overflow() {
+        let now = Instant::now();
+        let mut session = FixSession::new("EXCHANGE", now);
+        logon(&mut session, now);
+        session.receive(&new_order(2, 1, "1", u64::MAX, 4), now);
+        let new = session.report(&SessionReport::Accepted(OrderAccepted {accepted_order_id: 1}));
+        assert_eq!(new[0].get(tag::EXEC_ID), Some("1"));
+
+        let fill_event = FillEvent {buy_order_id: 1, sell_order_id: 2, price: u64::MAX, quantity: 2};
+        for exec_id in ["2", "3"].iter() {
+            let fill = session.report(&SessionReport::Fill(FeeAnnotatedFill {fill_event, taker_side: OrderSide::Sell, buy_fee: 0, sell_fee: 0}));
+            assert_eq!(fill[0].get(tag::EXEC_ID), Some(*exec_id));
+            assert_eq!(fill[0].get(tag::AVG_PX), Some((u64::MAX as f64).to_string().as_str()));
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+use serde::{Serialize};
+
+use crate::exchange::{ErrorReport, Exchange};
+use crate::market_data::depth::Depth;
+
+const MAX_HEADER_LINES: usize = 100;
+const MAX_LINE: u64 = 8192;
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: String
+}
+
+// A request waiting for the engine thread, which owns the exchange.
+pub struct HttpQuery {
+    pub request: HttpRequest,
+    reply: Sender<HttpResponse>
+}
+
+impl HttpQuery {
+    pub fn answer(self, exchange: &Exchange) {
+        let _ = self.reply.send(respond(exchange, &self.request));
+    }
+}
+
+impl HttpResponse {
+    fn json<T: Serialize>(status: u16, value: &T) -> Self {
+        HttpResponse {status, body: serde_json::to_string(value).unwrap()}
+    }
+
+    fn error(status: u16, error: &str) -> Self {
+        HttpResponse::json(status, &ErrorReport {error: error.to_string()})
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Service Unavailable"
+    }
+}
+
+// Like `read_line`, but fails on a line longer than `MAX_LINE` bytes instead
+// of buffering it whole.
+pub fn read_line<R: BufRead>(reader: &mut R, line: &mut String) -> io::Result<usize> {
+    let length = reader.take(MAX_LINE).read_line(line)?;
+    if length as u64 == MAX_LINE && !line.ends_with('\n') {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"));
+    }
+    Ok(length)
+}
+
+// Returns `None` when the connection closes before a full request line and
+// header block arrived.
+pub fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Option<HttpRequest>> {
+    let mut request_line = String::new();
+    if read_line(reader, &mut request_line)? == 0 {
+        return Ok(None);
+    }
+    for _ in 0..MAX_HEADER_LINES {
+        let mut line = String::new();
+        if read_line(reader, &mut line)? == 0 {
+            return Ok(None);
+        }
+        if line.trim_end().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method, target),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed request line"))
+    };
+    let (path, query_string) = target.split_once('?').unwrap_or((target, ""));
+    let query = query_string
+        .split('&')
+        .filter(|parameter| !parameter.is_empty())
+        .map(|parameter| {
+            let (name, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+            (name.to_string(), value.to_string())
+        })
+        .collect();
+    Ok(Some(HttpRequest {method: method.to_string(), path: path.to_string(), query}))
+}
+
+pub fn write_response<W: Write>(writer: &mut W, response: &HttpResponse) -> io::Result<()> {
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        reason(response.status),
+        response.body.len(),
+        response.body
+    )
+}
+
+fn parameter(request: &HttpRequest, name: &str) -> Result<Option<u64>, HttpResponse> {
+    match request.query.get(name) {
+        None => Ok(None),
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| HttpResponse::error(400, &format!("invalid value {} for {}", value, name)))
+    }
+}
+
+pub fn respond(exchange: &Exchange, request: &HttpRequest) -> HttpResponse {
+    if request.method != "GET" {
+        return HttpResponse::error(405, "only GET is supported");
+    }
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    let response = match segments.as_slice() {
+        ["book"] => Ok(HttpResponse::json(200, &exchange.orderbook().get_orders())),
+        ["depth"] => parameter(request, "levels").map(|levels| {
+            HttpResponse::json(200, &Depth::from_orderbook(exchange.orderbook(), levels.map(|levels| levels as usize)))
+        }),
+        ["stats"] => Ok(HttpResponse::json(200, &exchange.statistics())),
+        ["trades"] => parameter(request, "since").map(|since| HttpResponse::json(200, &exchange.trades_since(since.unwrap_or(0)))),
+        ["order", id] => match id.parse() {
+            Err(_) => Err(HttpResponse::error(400, &format!("invalid order id {}", id))),
+            Ok(id) => match exchange.orderbook().get_order(id) {
+                None => Err(HttpResponse::error(404, "unknown order id")),
+                Some(order_status) => Ok(HttpResponse::json(200, &order_status))
+            }
+        },
+        _ => Err(HttpResponse::error(404, "not found"))
+    };
+    response.unwrap_or_else(|error| error)
+}
+
+// Serves one request per connection. Requests are handed to the engine with
+// `submit`, which returns false once the engine has stopped.
+pub fn spawn_http<F>(listener: TcpListener, submit: F)
+where
+    F: Fn(HttpQuery) -> bool + Clone + Send + 'static
+{
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let submit = submit.clone();
+            thread::spawn(move || handle_connection(stream, submit));
+        }
+    });
+}
+
+fn handle_connection<F: Fn(HttpQuery) -> bool>(stream: TcpStream, submit: F) {
+    let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return
+    };
+    let response = match read_request(&mut BufReader::new(stream)) {
+        Ok(None) => return,
+        Err(error) => HttpResponse::error(400, &error.to_string()),
+        Ok(Some(request)) => {
+            let (reply, response) = mpsc::channel();
+            match submit(HttpQuery {request, reply}) {
+                false => HttpResponse::error(503, "engine stopped"),
+                true => response.recv().unwrap_or_else(|_| HttpResponse::error(503, "engine stopped"))
+            }
+        }
+    };
+    let _ = write_response(&mut writer, &response);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use gateway::http::{read_request, respond, MAX_LINE};
+
+    fn get(exchange: &Exchange, target: &str) -> (u16, serde_json::Value) {
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", target);
+        let response = respond(exchange, &read_request(&mut request.as_bytes()).unwrap().unwrap());
+        (response.status, serde_json::from_str(&response.body).unwrap())
+    }
+
+    #[test]
+    fn query_book_state() {
+        let mut exchange = Exchange::new(ExchangeConfig::default());
+        for json in [
+            r#"{"type": "Limit", "order": {"direction": "Buy", "id": 1, "price": 100, "quantity": 10}}"#,
+            r#"{"type": "Limit", "order": {"direction": "Buy", "id": 2, "price": 99, "quantity": 5}}"#,
+            r#"{"type": "Iceberg", "order": {"direction": "Sell", "id": 3, "price": 101, "quantity": 50, "peak": 10}}"#,
+            r#"{"type": "Limit", "order": {"direction": "Sell", "id": 4, "price": 100, "quantity": 4}}"#,
+            r#"{"type": "Limit", "order": {"direction": "Buy", "id": 5, "price": 101, "quantity": 12}}"#
+        ].iter() {
+            exchange.handle(serde_json::from_str(json).unwrap());
+        }
+
+        let (status, book) = get(&exchange, "/book");
+        assert_eq!(status, 200);
+        assert_eq!(book["buyOrders"][0], serde_json::json!({"id": 1, "price": 100, "quantity": 6}));
+        assert_eq!(get(&exchange, "/depth?levels=1").1["bids"].as_array().unwrap().len(), 1);
+        assert_eq!(get(&exchange, "/order/3").1, serde_json::json!({
+            "id": 3, "state": "Open", "side": "Sell", "price": 101, "visibleQuantity": 8, "hiddenQuantity": 30, "queuePosition": 1, "filledQuantity": 12
+        }));
+
+        let (_, trades) = get(&exchange, "/trades?since=1");
+        assert_eq!(trades.as_array().unwrap().len(), 2);
+        assert_eq!((trades[0]["sequence"].as_u64(), trades[0]["takerSide"].as_str()), (Some(2), Some("Buy")));
+
+        let (status, statistics) = get(&exchange, "/stats");
+        assert_eq!(status, 200);
+        assert_eq!(statistics, serde_json::to_value(exchange.statistics()).unwrap());
+        assert_eq!(statistics["tradeCount"], 3);
+
+        assert_eq!(get(&exchange, "/order/4").1["state"], "Filled");
+        assert_eq!(get(&exchange, "/order/6").0, 404);
+        assert_eq!(get(&exchange, "/order/x").0, 400);
+        assert_eq!(get(&exchange, "/depth?levels=-1").0, 400);
+        assert_eq!(get(&exchange, "/positions").0, 404);
+    }
+
+    #[test]
+    fn reject_long_lines() {
+        let request = format!("GET /book HTTP/1.1\r\nCookie: {}\r\n\r\n", "a".repeat(MAX_LINE as usize));
+        let error = read_request(&mut request.as_bytes()).unwrap_err();
+        assert_eq!((error.kind(), error.to_string()), (std::io::ErrorKind::InvalidData, "line too long".to_string()));
+
+        let request = format!("GET /book HTTP/1.1\r\nCookie: {}\r\n\r\n", "a".repeat(MAX_LINE as usize - 12));
+        assert_eq!(read_request(&mut request.as_bytes()).unwrap().unwrap().path, "/book");
+    }
+}
@@ -0,0 +1,5 @@
+pub mod fix;
+pub mod http;
+pub mod session;
+pub mod tcp;
+pub mod websocket;
@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use serde::{Serialize};
+
+use crate::accounting::balances::BalanceReport;
+use crate::accounting::fees::FeeAnnotatedFill;
+use crate::accounting::positions::PositionReport;
+use crate::exchange::{ErrorReport, Exchange, ExchangeOutput, OrderCancelled, OrderNotFound, OrderRejected, OrderReplaced};
+use crate::market_data::metrics::BookMetrics;
+use crate::market_data::statistics::SessionStatistics;
+use crate::matching_engine::orderbook::OrderStatus;
+use crate::matching_engine::order::FillEvent;
+use crate::matching_engine::parse::{DeserializedCommand, InboundMessage};
+
+pub type SessionId = u64;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderAccepted {
+    pub accepted_order_id: u64
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct PublicTrade {
+    pub trade: FillEvent
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum SessionReport {
+    Accepted(OrderAccepted),
+    Fill(FeeAnnotatedFill),
+    Rejected(OrderRejected),
+    Cancelled(OrderCancelled),
+    Replaced(OrderReplaced),
+    Status(OrderStatus),
+    NotFound(OrderNotFound),
+    Positions(Vec<PositionReport>),
+    Balances(Vec<BalanceReport>),
+    Statistics(SessionStatistics),
+    Metrics(BookMetrics),
+    Error(ErrorReport),
+    Trade(PublicTrade)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Delivery {
+    Private(SessionId, SessionReport),
+    Public(SessionReport)
+}
+
+// Sequences messages from many sessions into one exchange and decides who
+// gets which report: execution reports go to the sessions owning the orders
+// involved, trades are broadcast to everyone.
+#[derive(Default)]
+pub struct SessionRouter {
+    owners: HashMap<u64, SessionId>
+}
+
+impl SessionRouter {
+    pub fn new() -> Self {
+        SessionRouter {owners: HashMap::new()}
+    }
+
+    pub fn owner(&self, order_id: u64) -> Option<SessionId> {
+        self.owners.get(&order_id).copied()
+    }
+
+    pub fn orders_of(&self, session_id: SessionId) -> Vec<u64> {
+        let mut order_ids: Vec<u64> = self.owners
+            .iter()
+            .filter(|(_, owner)| **owner == session_id)
+            .map(|(order_id, _)| *order_id)
+            .collect();
+        order_ids.sort_unstable();
+        order_ids
+    }
+
+    // Cancels every live order the session left behind. The cancels go
+    // through the exchange like any other command, so they are journaled.
+    pub fn disconnect(&mut self, exchange: &mut Exchange, session_id: SessionId) -> Vec<Delivery> {
+        self.orders_of(session_id)
+            .into_iter()
+            .flat_map(|order_id| self.handle(exchange, session_id, InboundMessage::Command(DeserializedCommand::Cancel {id: order_id})))
+            .collect()
+    }
+
+    pub fn handle(&mut self, exchange: &mut Exchange, session_id: SessionId, mut message: InboundMessage) -> Vec<Delivery> {
+        let mut order_id = None;
+        match message {
+            InboundMessage::Order(ref mut deserialized_order) => {
+                let order_core = deserialized_order.order_core_mut();
+                if order_core.account.is_empty() {
+                    order_core.account = format!("session-{}", session_id);
+                }
+                order_id = Some(order_core.id);
+                if !exchange.orderbook().has_order(order_core.id) {
+                    self.owners.insert(order_core.id, session_id);
+                }
+            }
+            InboundMessage::Command(DeserializedCommand::Cancel {id})
+            | InboundMessage::Command(DeserializedCommand::Replace {id, ..})
+            | InboundMessage::Command(DeserializedCommand::Status {id}) => {
+                if self.owner(id).is_some_and(|owner| owner != session_id) {
+                    let rejection = OrderRejected {rejected_order_id: id, reason: "order belongs to another session".to_string()};
+                    return vec![Delivery::Private(session_id, SessionReport::Rejected(rejection))];
+                }
+            }
+            InboundMessage::Command(_) => ()
+        }
+
+        let outputs = exchange.handle(message);
+        let mut deliveries = Vec::new();
+        let mut finished_orders: Vec<u64> = order_id.into_iter().collect();
+        if let Some(order_id) = order_id {
+            let rejected = outputs.iter().any(|output| matches!(output, ExchangeOutput::Rejected(_) | ExchangeOutput::Error(_)));
+            if !rejected {
+                deliveries.push(Delivery::Private(session_id, SessionReport::Accepted(OrderAccepted {accepted_order_id: order_id})));
+            }
+        }
+
+        for output in outputs {
+            match output {
+                ExchangeOutput::Book(_) => (),
+                ExchangeOutput::Fill(annotated_fill) => {
+                    let fill_event = annotated_fill.fill_event;
+                    let mut owners = Vec::new();
+                    for owner in [self.owner(fill_event.buy_order_id), self.owner(fill_event.sell_order_id)].iter().flatten() {
+                        if !owners.contains(owner) {
+                            owners.push(*owner);
+                        }
+                    }
+                    for owner in owners {
+                        deliveries.push(Delivery::Private(owner, SessionReport::Fill(annotated_fill)));
+                    }
+                    deliveries.push(Delivery::Public(SessionReport::Trade(PublicTrade {trade: fill_event})));
+                    finished_orders.push(fill_event.buy_order_id);
+                    finished_orders.push(fill_event.sell_order_id);
+                }
+                ExchangeOutput::Cancelled(cancelled) => {
+                    let owner = self.owners.remove(&cancelled.cancelled_order_id).unwrap_or(session_id);
+                    deliveries.push(Delivery::Private(owner, SessionReport::Cancelled(cancelled)));
+                }
+                ExchangeOutput::Replaced(replaced) => {
+                    let owner = self.owners.remove(&replaced.replaced_order_id).unwrap_or(session_id);
+                    self.owners.insert(replaced.order_id, owner);
+                    finished_orders.push(replaced.order_id);
+                    deliveries.push(Delivery::Private(owner, SessionReport::Replaced(replaced)));
+                }
+                ExchangeOutput::Rejected(rejected) => deliveries.push(Delivery::Private(session_id, SessionReport::Rejected(rejected))),
+                ExchangeOutput::Status(order_status) => deliveries.push(Delivery::Private(session_id, SessionReport::Status(order_status))),
+                ExchangeOutput::NotFound(not_found) => deliveries.push(Delivery::Private(session_id, SessionReport::NotFound(not_found))),
+                ExchangeOutput::Positions(positions) => deliveries.push(Delivery::Private(session_id, SessionReport::Positions(positions))),
+                ExchangeOutput::Balances(balances) => deliveries.push(Delivery::Private(session_id, SessionReport::Balances(balances))),
+                ExchangeOutput::Statistics(statistics) => deliveries.push(Delivery::Private(session_id, SessionReport::Statistics(statistics))),
+                ExchangeOutput::Metrics(metrics) => deliveries.push(Delivery::Public(SessionReport::Metrics(metrics))),
+                ExchangeOutput::Error(error) => deliveries.push(Delivery::Private(session_id, SessionReport::Error(error)))
+            }
+        }
+
+        for order_id in finished_orders {
+            if !exchange.orderbook().has_order(order_id) {
+                self.owners.remove(&order_id);
+            }
+        }
+        deliveries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::test_support::message;
+
+    #[test]
+    fn route_reports_to_order_owners() {
+        let mut exchange = Exchange::new(ExchangeConfig::default());
+        let mut router = SessionRouter::new();
+
+        let deliveries = router.handle(&mut exchange, 1, message(r#"{"type": "Limit", "order": {"direction": "Buy", "id": 10, "price": 100, "quantity": 5}}"#));
+        assert_eq!(deliveries, vec![Delivery::Private(1, SessionReport::Accepted(OrderAccepted {accepted_order_id: 10}))]);
+
+        let deliveries = router.handle(&mut exchange, 2, message(r#"{"type": "Limit", "order": {"direction": "Sell", "id": 20, "price": 100, "quantity": 5}}"#));
+        let recipients: Vec<Option<SessionId>> = deliveries.iter().map(|delivery| match delivery {
+            Delivery::Private(session_id, _) => Some(*session_id),
+            Delivery::Public(_) => None
+        }).collect();
+        assert_eq!(recipients, vec![Some(2), Some(1), Some(2), None]);
+        assert_eq!(exchange.position_keeper().position("session-1", "default").unwrap().net_quantity, 5);
+    }
+
+    #[test]
+    fn cancel_only_own_orders() {
+        let mut exchange = Exchange::new(ExchangeConfig::default());
+        let mut router = SessionRouter::new();
+        router.handle(&mut exchange, 1, message(r#"{"type": "Limit", "order": {"direction": "Buy", "id": 10, "price": 100, "quantity": 5}}"#));
+
+        match router.handle(&mut exchange, 2, message(r#"{"type": "Cancel", "id": 10}"#)).as_slice() {
+            [Delivery::Private(2, SessionReport::Rejected(_))] => (),
+            other => panic!("unexpected deliveries {:?}", other)
+        }
+        match router.handle(&mut exchange, 1, message(r#"{"type": "Cancel", "id": 10}"#)).as_slice() {
+            [Delivery::Private(1, SessionReport::Cancelled(_))] => (),
+            other => panic!("unexpected deliveries {:?}", other)
+        }
+        assert_eq!(router.owner(10), None);
+    }
+
+    #[test]
+    fn cancel_on_disconnect() {
+        let mut exchange = Exchange::new(ExchangeConfig::default());
+        let mut router = SessionRouter::new();
+        router.handle(&mut exchange, 1, message(r#"{"type": "Limit", "order": {"direction": "Buy", "id": 10, "price": 100, "quantity": 5}}"#));
+        router.handle(&mut exchange, 1, message(r#"{"type": "Iceberg", "order": {"direction": "Sell", "id": 11, "price": 105, "quantity": 50, "peak": 10}}"#));
+        router.handle(&mut exchange, 2, message(r#"{"type": "Limit", "order": {"direction": "Buy", "id": 20, "price": 99, "quantity": 5}}"#));
+        assert_eq!(router.orders_of(1), vec![10, 11]);
+
+        assert_eq!(router.disconnect(&mut exchange, 1), vec![
+            Delivery::Private(1, SessionReport::Cancelled(OrderCancelled {cancelled_order_id: 10, quantity: 5})),
+            Delivery::Private(1, SessionReport::Cancelled(OrderCancelled {cancelled_order_id: 11, quantity: 50}))
+        ]);
+        assert!(router.orders_of(1).is_empty());
+        assert_eq!(exchange.orderbook().best_buy_price(), Some(99));
+        assert_eq!(exchange.orderbook().best_sell_price(), None);
+    }
+}
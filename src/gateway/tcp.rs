@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::exchange::{ErrorReport, Exchange};
+use crate::matching_engine::parse::InboundMessage;
+use super::http::{spawn_http, HttpQuery};
+use super::session::{Delivery, SessionId, SessionReport, SessionRouter};
+
+// Reports waiting for a client that does not keep up. A client whose queue is
+// full is disconnected rather than stalling the engine thread.
+const WRITE_QUEUE: usize = 1024;
+
+enum SessionEvent {
+    Connected(SessionId, Connection),
+    Message(SessionId, InboundMessage),
+    Invalid(SessionId, String),
+    Disconnected(SessionId),
+    Query(HttpQuery)
+}
+
+pub struct TcpServer {
+    local_addr: SocketAddr
+}
+
+// Each connection is written by its own thread, so the engine thread only
+// ever queues lines.
+struct Connection {
+    queue: SyncSender<Arc<str>>,
+    stream: TcpStream
+}
+
+impl Connection {
+    fn open(stream: &TcpStream) -> io::Result<Connection> {
+        let mut writer = stream.try_clone()?;
+        let (queue, lines) = mpsc::sync_channel::<Arc<str>>(WRITE_QUEUE);
+        thread::spawn(move || {
+            for line in lines {
+                if writer.write_all(line.as_bytes()).is_err() {
+                    break;
+                }
+            }
+            // The queue closes when the engine drops the session, so
+            // everything queued before that is written first.
+            let _ = writer.shutdown(Shutdown::Both);
+        });
+        Ok(Connection {queue, stream: stream.try_clone()?})
+    }
+}
+
+impl TcpServer {
+    // Accepts sessions on a background thread and sequences their messages
+    // into the exchange on a single engine thread. Sessions silent for longer
+    // than `heartbeat_timeout` are treated as disconnected. The optional
+    // `http` listener serves read-only queries from the same engine thread.
+    pub fn spawn(listener: TcpListener, exchange: Exchange, heartbeat_timeout: Option<Duration>, http: Option<TcpListener>) -> io::Result<TcpServer> {
+        let local_addr = listener.local_addr()?;
+        let (events, receiver) = mpsc::channel();
+        start_http(http, &events);
+        thread::spawn(move || run_engine(exchange, receiver, heartbeat_timeout));
+        thread::spawn(move || accept_sessions(listener, events));
+        Ok(TcpServer {local_addr})
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+// Blocks the calling thread, which becomes the engine thread.
+pub fn serve(listener: TcpListener, exchange: Exchange, heartbeat_timeout: Option<Duration>, http: Option<TcpListener>) {
+    let (events, receiver) = mpsc::channel();
+    start_http(http, &events);
+    thread::spawn(move || accept_sessions(listener, events));
+    run_engine(exchange, receiver, heartbeat_timeout);
+}
+
+fn start_http(http: Option<TcpListener>, events: &Sender<SessionEvent>) {
+    if let Some(http) = http {
+        let events = events.clone();
+        spawn_http(http, move |query| events.send(SessionEvent::Query(query)).is_ok());
+    }
+}
+
+fn accept_sessions(listener: TcpListener, events: Sender<SessionEvent>) {
+    for (session_id, stream) in (1..).zip(listener.incoming()) {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue
+        };
+        let connection = match Connection::open(&stream) {
+            Ok(connection) => connection,
+            Err(_) => continue
+        };
+        if events.send(SessionEvent::Connected(session_id, connection)).is_err() {
+            break;
+        }
+        let events = events.clone();
+        thread::spawn(move || read_session(session_id, stream, events));
+    }
+}
+
+fn read_session(session_id: SessionId, stream: TcpStream, events: Sender<SessionEvent>) {
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = match serde_json::from_str::<InboundMessage>(&line) {
+            Ok(message) => SessionEvent::Message(session_id, message),
+            Err(error) => SessionEvent::Invalid(session_id, error.to_string())
+        };
+        if events.send(event).is_err() {
+            return;
+        }
+    }
+    let _ = events.send(SessionEvent::Disconnected(session_id));
+}
+
+fn run_engine(mut exchange: Exchange, events: Receiver<SessionEvent>, heartbeat_timeout: Option<Duration>) {
+    let mut router = SessionRouter::new();
+    let mut connections: HashMap<SessionId, Connection> = HashMap::new();
+    let mut last_seen: HashMap<SessionId, Instant> = HashMap::new();
+
+    loop {
+        let event = match heartbeat_timeout {
+            None => events.recv().ok(),
+            Some(timeout) => match events.recv_timeout(timeout / 4) {
+                Ok(event) => Some(event),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break
+            }
+        };
+
+        let deliveries = match event {
+            None if heartbeat_timeout.is_none() => break,
+            None => vec![],
+            Some(SessionEvent::Connected(session_id, connection)) => {
+                connections.insert(session_id, connection);
+                last_seen.insert(session_id, Instant::now());
+                vec![]
+            }
+            Some(SessionEvent::Disconnected(session_id)) => {
+                connections.remove(&session_id);
+                last_seen.remove(&session_id);
+                router.disconnect(&mut exchange, session_id)
+            }
+            Some(SessionEvent::Message(session_id, message)) => {
+                last_seen.insert(session_id, Instant::now());
+                router.handle(&mut exchange, session_id, message)
+            }
+            Some(SessionEvent::Invalid(session_id, error)) => {
+                last_seen.insert(session_id, Instant::now());
+                vec![Delivery::Private(session_id, SessionReport::Error(ErrorReport {error}))]
+            }
+            Some(SessionEvent::Query(query)) => {
+                query.answer(&exchange);
+                vec![]
+            }
+        };
+        for delivery in deliveries {
+            deliver(&mut connections, delivery);
+        }
+
+        if let Some(timeout) = heartbeat_timeout {
+            let expired: Vec<SessionId> = last_seen
+                .iter()
+                .filter(|(_, seen)| seen.elapsed() > timeout)
+                .map(|(session_id, _)| *session_id)
+                .collect();
+            for session_id in expired {
+                last_seen.remove(&session_id);
+                for delivery in router.disconnect(&mut exchange, session_id) {
+                    deliver(&mut connections, delivery);
+                }
+                connections.remove(&session_id);
+            }
+        }
+    }
+}
+
+// Shutting a lagging connection down ends its reader too, which then reports
+// the disconnect so that the session's orders are cancelled.
+fn deliver(connections: &mut HashMap<SessionId, Connection>, delivery: Delivery) {
+    let (recipients, report): (Vec<SessionId>, SessionReport) = match delivery {
+        Delivery::Private(session_id, report) => (vec![session_id], report),
+        Delivery::Public(report) => (connections.keys().copied().collect(), report)
+    };
+    let line: Arc<str> = format!("{}\n", serde_json::to_string(&report).unwrap()).into();
+    for session_id in recipients {
+        let failed = match connections.get(&session_id) {
+            None => false,
+            Some(connection) => connection.queue.try_send(line.clone()).is_err()
+        };
+        if failed {
+            if let Some(connection) = connections.remove(&session_id) {
+                let _ = connection.stream.shutdown(Shutdown::Both);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use crate::*;
+    use crate::gateway::session::{Delivery, SessionReport};
+    use super::{deliver, Connection, WRITE_QUEUE};
+
+    struct Client {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream
+    }
+
+    impl Client {
+        fn connect(server: &TcpServer) -> Client {
+            let stream = TcpStream::connect(server.local_addr()).unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            Client {writer: stream.try_clone().unwrap(), reader: BufReader::new(stream)}
+        }
+
+        fn send(&mut self, line: &str) {
+            writeln!(self.writer, "{}", line).unwrap();
+        }
+
+        fn receive(&mut self) -> serde_json::Value {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).unwrap();
+            serde_json::from_str(&line).unwrap()
+        }
+    }
+
+    #[test]
+    fn loopback_sessions() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = TcpServer::spawn(listener, Exchange::new(ExchangeConfig::default()), None, None).unwrap();
+        let mut buyer = Client::connect(&server);
+        let mut seller = Client::connect(&server);
+
+        buyer.send(r#"{"type": "Limit", "order": {"direction": "Buy", "id": 1, "price": 100, "quantity": 10}}"#);
+        assert_eq!(buyer.receive()["acceptedOrderId"], 1);
+
+        seller.send("not json");
+        assert!(seller.receive()["error"].is_string());
+
+        seller.send(r#"{"type": "Limit", "order": {"direction": "Sell", "id": 2, "price": 99, "quantity": 4}}"#);
+        assert_eq!(seller.receive()["acceptedOrderId"], 2);
+        let seller_fill = seller.receive();
+        assert_eq!(seller_fill["sellOrderId"], 2);
+        assert_eq!(seller_fill["price"], 100);
+        assert_eq!(seller.receive()["trade"]["quantity"], 4);
+
+        assert_eq!(buyer.receive()["buyOrderId"], 1);
+        assert_eq!(buyer.receive()["trade"]["sellOrderId"], 2);
+    }
+
+    #[test]
+    fn lagging_connection_is_disconnected() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        // Nothing drains this queue, like a writer stuck on a client that
+        // stopped reading.
+        let (queue, lines) = mpsc::sync_channel(WRITE_QUEUE);
+        let mut connections = HashMap::new();
+        connections.insert(1, Connection {queue, stream});
+        let report = || Delivery::Private(1, SessionReport::Error(ErrorReport {error: "report".to_string()}));
+
+        (0..WRITE_QUEUE).for_each(|_| deliver(&mut connections, report()));
+        assert!(connections.contains_key(&1));
+        deliver(&mut connections, report());
+        assert!(!connections.contains_key(&1));
+        assert_eq!(lines.try_iter().count(), WRITE_QUEUE);
+        assert_eq!(client.read(&mut [0; 1]).unwrap(), 0);
+    }
+
+    #[test]
+    fn heartbeat_timeout_cancels_orders() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let timeout = Duration::from_millis(200);
+        let server = TcpServer::spawn(listener, Exchange::new(ExchangeConfig::default()), Some(timeout), None).unwrap();
+        let mut client = Client::connect(&server);
+
+        client.send(r#"{"type": "Limit", "order": {"direction": "Buy", "id": 1, "price": 100, "quantity": 10}}"#);
+        assert_eq!(client.receive()["acceptedOrderId"], 1);
+        client.send(r#"{"type": "Heartbeat"}"#);
+
+        let cancelled = client.receive();
+        assert_eq!(cancelled["cancelledOrderId"], 1);
+        assert_eq!(cancelled["quantity"], 10);
+
+        let mut line = String::new();
+        assert_eq!(client.reader.read_line(&mut line).unwrap(), 0);
+    }
+}
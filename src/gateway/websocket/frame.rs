@@ -0,0 +1,135 @@
+use std::io::{self, Read, Write};
+
+pub const MAX_PAYLOAD_LENGTH: u64 = 1 << 20;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong
+}
+
+impl Opcode {
+    fn code(self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Opcode> {
+        match code {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xA => Some(Opcode::Pong),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub fin: bool,
+    pub opcode: Opcode,
+    pub payload: Vec<u8>
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// Servers must only accept masked frames, clients only unmasked ones.
+pub fn read_frame<R: Read>(reader: &mut R, expect_masked: bool) -> io::Result<Frame> {
+    let mut header = [0u8; 2];
+    reader.read_exact(&mut header)?;
+    let fin = header[0] & 0x80 != 0;
+    let opcode = Opcode::from_code(header[0] & 0x0F).ok_or_else(|| invalid("unknown opcode"))?;
+    let masked = header[1] & 0x80 != 0;
+    if masked != expect_masked {
+        return Err(invalid("unexpected frame masking"));
+    }
+
+    let length = match header[1] & 0x7F {
+        126 => {
+            let mut length = [0u8; 2];
+            reader.read_exact(&mut length)?;
+            u16::from_be_bytes(length) as u64
+        }
+        127 => {
+            let mut length = [0u8; 8];
+            reader.read_exact(&mut length)?;
+            u64::from_be_bytes(length)
+        }
+        length => length as u64
+    };
+    if length > MAX_PAYLOAD_LENGTH {
+        return Err(invalid("frame too large"));
+    }
+
+    let mut mask = [0u8; 4];
+    if masked {
+        reader.read_exact(&mut mask)?;
+    }
+    let mut payload = vec![0u8; length as usize];
+    reader.read_exact(&mut payload)?;
+    if masked {
+        for (index, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[index % 4];
+        }
+    }
+    Ok(Frame {fin, opcode, payload})
+}
+
+pub fn write_frame<W: Write>(writer: &mut W, opcode: Opcode, payload: &[u8], mask: Option<[u8; 4]>) -> io::Result<()> {
+    let mut frame = vec![0x80 | opcode.code()];
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    match payload.len() {
+        length if length < 126 => frame.push(mask_bit | length as u8),
+        length if length <= u16::MAX as usize => {
+            frame.push(mask_bit | 126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(mask_bit | 127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    match mask {
+        None => frame.extend_from_slice(payload),
+        Some(mask) => {
+            frame.extend_from_slice(&mask);
+            frame.extend(payload.iter().enumerate().map(|(index, byte)| byte ^ mask[index % 4]));
+        }
+    }
+    writer.write_all(&frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gateway::websocket::frame::{read_frame, write_frame, Frame, Opcode};
+
+    #[test]
+    fn masked_round_trip() {
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, Opcode::Text, b"Hello", Some([0x37, 0xfa, 0x21, 0x3d])).unwrap();
+        assert_eq!(buffer, vec![0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58]);
+        assert_eq!(read_frame(&mut buffer.as_slice(), true).unwrap(), Frame {fin: true, opcode: Opcode::Text, payload: b"Hello".to_vec()});
+        assert!(read_frame(&mut buffer.as_slice(), false).is_err());
+
+        let payload = vec![b'x'; 300];
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, Opcode::Binary, &payload, None).unwrap();
+        assert_eq!(&buffer[..4], &[0x82, 126, 0x01, 0x2c]);
+        assert_eq!(read_frame(&mut buffer.as_slice(), false).unwrap().payload, payload);
+    }
+}
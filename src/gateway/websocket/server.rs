@@ -0,0 +1,493 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, BufReader, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::thread;
+use serde::{Deserialize, Serialize};
+
+use crate::exchange::{ErrorReport, Exchange};
+use crate::gateway::http::{spawn_http, HttpQuery};
+use crate::gateway::session::{Delivery, SessionId, SessionReport, SessionRouter};
+use crate::market_data::depth::{Bbo, Depth};
+use crate::matching_engine::parse::InboundMessage;
+use super::frame::{read_frame, write_frame, Opcode, MAX_PAYLOAD_LENGTH};
+use super::handshake;
+
+// Frames waiting for a client that does not keep up. A client whose queue is
+// full is disconnected rather than stalling the engine thread.
+const WRITE_QUEUE: usize = 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    L2,
+    Trades,
+    Bbo
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub struct Subscription {
+    pub symbol: String,
+    pub channel: Channel
+}
+
+// No symbols means the exchange's own instrument.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type")]
+pub enum SubscriptionRequest {
+    Subscribe {
+        channels: Vec<Channel>,
+        #[serde(default)]
+        symbols: Vec<String>
+    },
+    Unsubscribe {
+        channels: Vec<Channel>,
+        #[serde(default)]
+        symbols: Vec<String>
+    }
+}
+
+#[derive(Serialize)]
+struct MarketData<'a, T: Serialize> {
+    channel: Channel,
+    symbol: &'a str,
+    #[serde(flatten)]
+    data: T
+}
+
+#[derive(Serialize)]
+struct Subscriptions<'a> {
+    subscriptions: &'a BTreeSet<Subscription>
+}
+
+enum ClientEvent {
+    Connected(SessionId, Client),
+    Message(SessionId, InboundMessage),
+    Subscription(SessionId, SubscriptionRequest),
+    Invalid(SessionId, String),
+    Ping(SessionId, Vec<u8>),
+    Disconnected(SessionId),
+    Query(HttpQuery)
+}
+
+// Each client is written by its own thread, so the engine thread only ever
+// queues encoded frames.
+struct Client {
+    queue: SyncSender<Vec<u8>>,
+    stream: TcpStream,
+    subscriptions: BTreeSet<Subscription>
+}
+
+impl Client {
+    fn open(stream: &TcpStream) -> io::Result<Client> {
+        let mut writer = stream.try_clone()?;
+        let (queue, frames) = mpsc::sync_channel::<Vec<u8>>(WRITE_QUEUE);
+        thread::spawn(move || {
+            for frame in frames {
+                if writer.write_all(&frame).is_err() {
+                    break;
+                }
+            }
+            // The queue closes when the engine drops the client, so a close
+            // frame queued before that is still written.
+            let _ = writer.shutdown(Shutdown::Both);
+        });
+        Ok(Client {queue, stream: stream.try_clone()?, subscriptions: BTreeSet::new()})
+    }
+
+    fn send<T: Serialize>(&self, message: &T) -> bool {
+        let text = serde_json::to_string(message).unwrap();
+        self.send_frame(Opcode::Text, text.as_bytes())
+    }
+
+    // Shutting a lagging client down right away ends its writer and reader
+    // without waiting for the queue to drain.
+    fn send_frame(&self, opcode: Opcode, payload: &[u8]) -> bool {
+        let mut frame = Vec::new();
+        // Writing into a vector cannot fail.
+        let _ = write_frame(&mut frame, opcode, payload, None);
+        if self.queue.try_send(frame).is_err() {
+            let _ = self.stream.shutdown(Shutdown::Both);
+            return false;
+        }
+        true
+    }
+
+    fn subscribed(&self, symbol: &str, channel: Channel) -> bool {
+        self.subscriptions.contains(&Subscription {symbol: symbol.to_string(), channel})
+    }
+}
+
+pub struct WebSocketServer {
+    local_addr: SocketAddr
+}
+
+impl WebSocketServer {
+    // Same threading as the JSON order entry server: one thread per client
+    // doing the handshake and reading frames, one writer thread per client and
+    // one engine thread owning the exchange and queueing every client's
+    // frames.
+    pub fn spawn(listener: TcpListener, exchange: Exchange, http: Option<TcpListener>) -> io::Result<WebSocketServer> {
+        let local_addr = listener.local_addr()?;
+        let (events, receiver) = mpsc::channel();
+        start_http(http, &events);
+        thread::spawn(move || run_engine(exchange, receiver));
+        thread::spawn(move || accept_clients(listener, events));
+        Ok(WebSocketServer {local_addr})
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+// Blocks the calling thread, which becomes the engine thread.
+pub fn serve_websocket(listener: TcpListener, exchange: Exchange, http: Option<TcpListener>) {
+    let (events, receiver) = mpsc::channel();
+    start_http(http, &events);
+    thread::spawn(move || accept_clients(listener, events));
+    run_engine(exchange, receiver);
+}
+
+fn start_http(http: Option<TcpListener>, events: &Sender<ClientEvent>) {
+    if let Some(http) = http {
+        let events = events.clone();
+        spawn_http(http, move |query| events.send(ClientEvent::Query(query)).is_ok());
+    }
+}
+
+fn accept_clients(listener: TcpListener, events: Sender<ClientEvent>) {
+    for (session_id, stream) in (1..).zip(listener.incoming()) {
+        if let Ok(stream) = stream {
+            let events = events.clone();
+            thread::spawn(move || read_client(session_id, stream, events));
+        }
+    }
+}
+
+fn parse_text(session_id: SessionId, payload: Vec<u8>) -> ClientEvent {
+    let text = match String::from_utf8(payload) {
+        Ok(text) => text,
+        Err(_) => return ClientEvent::Invalid(session_id, "text frame is not valid UTF-8".to_string())
+    };
+    if let Ok(request) = serde_json::from_str::<SubscriptionRequest>(&text) {
+        return ClientEvent::Subscription(session_id, request);
+    }
+    match serde_json::from_str::<InboundMessage>(&text) {
+        Ok(message) => ClientEvent::Message(session_id, message),
+        Err(error) => ClientEvent::Invalid(session_id, error.to_string())
+    }
+}
+
+fn read_client(session_id: SessionId, stream: TcpStream, events: Sender<ClientEvent>) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return
+    };
+    let mut reader = BufReader::new(stream);
+    if !handshake::accept(&mut reader, &mut writer).unwrap_or(false) {
+        let _ = writer.shutdown(Shutdown::Both);
+        return;
+    }
+    let client = match Client::open(&writer) {
+        Ok(client) => client,
+        Err(_) => return
+    };
+    if events.send(ClientEvent::Connected(session_id, client)).is_err() {
+        return;
+    }
+
+    let mut message = Vec::new();
+    while let Ok(frame) = read_frame(&mut reader, true) {
+        let event = match frame.opcode {
+            Opcode::Close => break,
+            Opcode::Pong => continue,
+            Opcode::Ping => ClientEvent::Ping(session_id, frame.payload),
+            Opcode::Binary => ClientEvent::Invalid(session_id, "binary frames are not supported".to_string()),
+            Opcode::Text | Opcode::Continuation => {
+                message.extend(frame.payload);
+                // Each frame is limited on its own, so a fragmented message
+                // has to be limited as a whole.
+                if message.len() as u64 > MAX_PAYLOAD_LENGTH {
+                    let _ = events.send(ClientEvent::Invalid(session_id, "message too large".to_string()));
+                    break;
+                }
+                if !frame.fin {
+                    continue;
+                }
+                parse_text(session_id, std::mem::take(&mut message))
+            }
+        };
+        if events.send(event).is_err() {
+            return;
+        }
+    }
+    let _ = events.send(ClientEvent::Disconnected(session_id));
+}
+
+fn run_engine(mut exchange: Exchange, events: Receiver<ClientEvent>) {
+    let mut router = SessionRouter::new();
+    let mut clients: HashMap<SessionId, Client> = HashMap::new();
+    let mut depth = Depth::default();
+    let mut bbo = Bbo::default();
+
+    while let Ok(event) = events.recv() {
+        let mut closed = Vec::new();
+        match event {
+            ClientEvent::Connected(session_id, client) => {
+                clients.insert(session_id, client);
+            }
+            ClientEvent::Disconnected(session_id) => closed.push(session_id),
+            ClientEvent::Query(query) => query.answer(&exchange),
+            ClientEvent::Ping(session_id, payload) => {
+                if let Some(client) = clients.get(&session_id) {
+                    if !client.send_frame(Opcode::Pong, &payload) {
+                        closed.push(session_id);
+                    }
+                }
+            }
+            ClientEvent::Invalid(session_id, error) => {
+                let deliveries = vec![Delivery::Private(session_id, SessionReport::Error(ErrorReport {error}))];
+                deliver(&mut clients, exchange.instrument(), deliveries, &mut closed);
+            }
+            ClientEvent::Subscription(session_id, request) => {
+                if let Some(client) = clients.get_mut(&session_id) {
+                    if !subscribe(client, exchange.instrument(), request, &depth, &bbo) {
+                        closed.push(session_id);
+                    }
+                }
+            }
+            ClientEvent::Message(session_id, message) => {
+                let deliveries = router.handle(&mut exchange, session_id, message);
+                deliver(&mut clients, exchange.instrument(), deliveries, &mut closed);
+            }
+        }
+
+        // Closing a client cancels its orders, and publishing the book that
+        // leaves behind can find more clients to close.
+        loop {
+            while let Some(session_id) = closed.pop() {
+                if let Some(client) = clients.remove(&session_id) {
+                    client.send_frame(Opcode::Close, &[]);
+                }
+                let deliveries = router.disconnect(&mut exchange, session_id);
+                deliver(&mut clients, exchange.instrument(), deliveries, &mut closed);
+            }
+
+            let current_depth = Depth::from_orderbook(exchange.orderbook(), None);
+            if current_depth != depth {
+                let current_bbo = current_depth.bbo();
+                publish(&clients, exchange.instrument(), Channel::L2, &current_depth, &mut closed);
+                if current_bbo != bbo {
+                    publish(&clients, exchange.instrument(), Channel::Bbo, &current_bbo, &mut closed);
+                }
+                depth = current_depth;
+                bbo = current_bbo;
+            }
+            if closed.is_empty() {
+                break;
+            }
+        }
+    }
+}
+
+// Replies with the resulting subscriptions, followed by a snapshot of every
+// newly subscribed l2 or bbo channel.
+fn subscribe(client: &mut Client, instrument: &str, request: SubscriptionRequest, depth: &Depth, bbo: &Bbo) -> bool {
+    let (channels, symbols, subscribing) = match request {
+        SubscriptionRequest::Subscribe {channels, symbols} => (channels, symbols, true),
+        SubscriptionRequest::Unsubscribe {channels, symbols} => (channels, symbols, false)
+    };
+    let symbols = if symbols.is_empty() { vec![instrument.to_string()] } else { symbols };
+    if let Some(unknown) = symbols.iter().find(|symbol| *symbol != instrument) {
+        return client.send(&ErrorReport {error: format!("unknown symbol {}", unknown)});
+    }
+
+    let mut snapshots = Vec::new();
+    for channel in channels {
+        for symbol in symbols.iter() {
+            let subscription = Subscription {symbol: symbol.clone(), channel};
+            if !subscribing {
+                client.subscriptions.remove(&subscription);
+            } else if client.subscriptions.insert(subscription) {
+                snapshots.push(channel);
+            }
+        }
+    }
+
+    let subscriptions = client.subscriptions.clone();
+    let mut sent = client.send(&Subscriptions {subscriptions: &subscriptions});
+    for channel in snapshots {
+        sent = sent && match channel {
+            Channel::L2 => client.send(&MarketData {channel, symbol: instrument, data: depth}),
+            Channel::Bbo => client.send(&MarketData {channel, symbol: instrument, data: bbo}),
+            Channel::Trades => true
+        };
+    }
+    sent
+}
+
+fn publish<T: Serialize>(clients: &HashMap<SessionId, Client>, instrument: &str, channel: Channel, data: &T, closed: &mut Vec<SessionId>) {
+    let message = MarketData {channel, symbol: instrument, data};
+    for (session_id, client) in clients.iter() {
+        if client.subscribed(instrument, channel) && !client.send(&message) {
+            closed.push(*session_id);
+        }
+    }
+}
+
+// Execution reports go to the owning client; trades only to clients
+// subscribed to the trades channel.
+fn deliver(clients: &mut HashMap<SessionId, Client>, instrument: &str, deliveries: Vec<Delivery>, closed: &mut Vec<SessionId>) {
+    for delivery in deliveries {
+        match delivery {
+            Delivery::Private(session_id, report) => {
+                if let Some(client) = clients.get_mut(&session_id) {
+                    if !client.send(&report) {
+                        closed.push(session_id);
+                    }
+                }
+            }
+            Delivery::Public(SessionReport::Trade(public_trade)) => {
+                for (session_id, client) in clients.iter_mut() {
+                    if client.subscribed(instrument, Channel::Trades)
+                        && !client.send(&MarketData {channel: Channel::Trades, symbol: instrument, data: public_trade.trade}) {
+                        closed.push(*session_id);
+                    }
+                }
+            }
+            Delivery::Public(_) => ()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashMap};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use crate::*;
+    use gateway::websocket::frame::{read_frame, write_frame, Opcode, MAX_PAYLOAD_LENGTH};
+    use super::{publish, WRITE_QUEUE};
+
+    struct Client {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream
+    }
+
+    impl Client {
+        fn connect(server: &WebSocketServer) -> Client {
+            let stream = TcpStream::connect(server.local_addr()).unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut client = Client {writer: stream.try_clone().unwrap(), reader: BufReader::new(stream)};
+            write!(
+                client.writer,
+                "GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n"
+            ).unwrap();
+            let mut status = String::new();
+            client.reader.read_line(&mut status).unwrap();
+            assert!(status.starts_with("HTTP/1.1 101"));
+            let mut line = String::new();
+            while line != "\r\n" {
+                line.clear();
+                client.reader.read_line(&mut line).unwrap();
+            }
+            client
+        }
+
+        fn send(&mut self, text: &str) {
+            write_frame(&mut self.writer, Opcode::Text, text.as_bytes(), Some([1, 2, 3, 4])).unwrap();
+        }
+
+        fn send_fragment(&mut self, opcode: Opcode, payload: &[u8]) {
+            let mut frame = Vec::new();
+            write_frame(&mut frame, opcode, payload, Some([1, 2, 3, 4])).unwrap();
+            frame[0] &= 0x7F;
+            self.writer.write_all(&frame).unwrap();
+        }
+
+        fn receive(&mut self) -> serde_json::Value {
+            let frame = read_frame(&mut self.reader, false).unwrap();
+            assert_eq!(frame.opcode, Opcode::Text);
+            serde_json::from_slice(&frame.payload).unwrap()
+        }
+    }
+
+    #[test]
+    fn loopback_subscriptions() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = ExchangeConfig {instrument: "XYZ".to_string(), ..ExchangeConfig::default()};
+        let server = WebSocketServer::spawn(listener, Exchange::new(config), None).unwrap();
+        let mut trader = Client::connect(&server);
+        let mut viewer = Client::connect(&server);
+
+        viewer.send(r#"{"type": "Subscribe", "channels": ["bbo", "trades"], "symbols": ["ABC"]}"#);
+        assert_eq!(viewer.receive()["error"], "unknown symbol ABC");
+        viewer.send(r#"{"type": "Subscribe", "channels": ["bbo", "trades"]}"#);
+        assert_eq!(viewer.receive()["subscriptions"].as_array().unwrap().len(), 2);
+        let snapshot = viewer.receive();
+        assert_eq!((snapshot["channel"].as_str(), snapshot["symbol"].as_str()), (Some("bbo"), Some("XYZ")));
+        assert!(snapshot["bid"].is_null());
+
+        trader.send(r#"{"type": "Limit", "order": {"direction": "Buy", "id": 1, "price": 100, "quantity": 10}}"#);
+        assert_eq!(trader.receive()["acceptedOrderId"], 1);
+        assert_eq!(viewer.receive()["bid"], serde_json::json!({"price": 100, "quantity": 10, "orderCount": 1}));
+
+        trader.send(r#"{"type": "Limit", "order": {"direction": "Sell", "id": 2, "price": 100, "quantity": 4}}"#);
+        assert_eq!(trader.receive()["acceptedOrderId"], 2);
+        let fill = trader.receive();
+        assert_eq!((fill["buyOrderId"].as_u64(), fill["sellOrderId"].as_u64()), (Some(1), Some(2)));
+        let trade = viewer.receive();
+        assert_eq!((trade["channel"].as_str(), trade["quantity"].as_u64()), (Some("trades"), Some(4)));
+        assert_eq!(viewer.receive()["bid"]["quantity"], 6);
+
+        viewer.send(r#"{"type": "Unsubscribe", "channels": ["bbo"]}"#);
+        assert_eq!(viewer.receive()["subscriptions"], serde_json::json!([{"symbol": "XYZ", "channel": "trades"}]));
+        viewer.send(r#"{"type": "Subscribe", "channels": ["l2"]}"#);
+        viewer.receive();
+        assert_eq!(viewer.receive()["bids"][0]["quantity"], 6);
+
+        trader.send("not json");
+        assert!(trader.receive()["error"].is_string());
+    }
+
+    #[test]
+    fn fragmented_message_too_large() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = WebSocketServer::spawn(listener, Exchange::new(ExchangeConfig::default()), None).unwrap();
+        let mut client = Client::connect(&server);
+
+        let fragment = vec![b' '; MAX_PAYLOAD_LENGTH as usize / 2];
+        client.send_fragment(Opcode::Text, &fragment);
+        client.send_fragment(Opcode::Continuation, &fragment);
+        client.send_fragment(Opcode::Continuation, &fragment);
+        assert_eq!(client.receive()["error"], "message too large");
+        assert_eq!(read_frame(&mut client.reader, false).unwrap().opcode, Opcode::Close);
+    }
+
+    #[test]
+    fn lagging_client_is_closed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        // Nothing drains this queue, like a writer stuck on a client that
+        // stopped reading.
+        let (queue, frames) = mpsc::sync_channel(WRITE_QUEUE);
+        let subscriptions = vec![Subscription {symbol: "XYZ".to_string(), channel: Channel::Bbo}].into_iter().collect::<BTreeSet<_>>();
+        let mut clients = HashMap::new();
+        clients.insert(1, super::Client {queue, stream, subscriptions});
+
+        let mut closed = Vec::new();
+        (0..WRITE_QUEUE).for_each(|_| publish(&clients, "XYZ", Channel::Bbo, &Bbo::default(), &mut closed));
+        publish(&clients, "XYZ", Channel::L2, &Bbo::default(), &mut closed);
+        assert!(closed.is_empty());
+        publish(&clients, "XYZ", Channel::Bbo, &Bbo::default(), &mut closed);
+        assert_eq!(closed, vec![1]);
+        assert_eq!(frames.try_iter().count(), WRITE_QUEUE);
+        assert_eq!(client.read(&mut [0; 1]).unwrap(), 0);
+    }
+}
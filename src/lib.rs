@@ -1,7 +1,50 @@
+#![cfg_attr(test, allow(clippy::needless_update, clippy::assertions_on_constants))]
+
 mod matching_engine;
-pub use matching_engine::orderbook::Orderbook;
+mod accounting;
+mod exchange;
+mod persistence;
+mod replay;
+mod market_data;
+mod output;
+mod gateway;
+mod simulation;
+mod scenario;
+#[cfg(any(test, feature = "fuzzing"))]
+mod fuzz;
+#[cfg(test)]
+mod test_support;
+pub use matching_engine::orderbook::{ExecutionState, OrderStatus, Orderbook, OrderbookContent, QueuePosition, RestingQuantity, SimulatedExecution, TERMINAL_ORDERS};
 pub use matching_engine::order::{FillEvent, Order, OrderKey, OrderSide, IcebergOrder};
-pub use matching_engine::parse::{parse_order, DeserializedOrder};
+pub use matching_engine::snapshot::{OrderState, OrderbookSnapshot, SnapshotError, SNAPSHOT_VERSION};
+pub use matching_engine::parse::{parse_order, DeserializedOrder, DeserializedCommand, InboundMessage, OrderCore};
+pub use matching_engine::binary::{encode_cancel, encode_inbound, encode_modify, encode_new_order, BinaryError, BinaryMessage, BinaryReader};
+pub use accounting::positions::{MarkMethod, Position, PositionKeeper, PositionReport, PositionSnapshot};
+pub use accounting::fees::{fee_amount, FeeAnnotatedFill, FeeEngine, FeeSchedule, FeeSnapshot, FeeTier};
+pub use accounting::balances::{Balance, BalanceError, BalanceLedger, BalanceReport, BalanceSnapshot};
+pub use persistence::journal::{crc32, Journal, JournalError, JournalRecord};
+pub use persistence::snapshot::{load_snapshot, save_snapshot, RecoveryError, SnapshotFile};
+pub use exchange::{ErrorReport, Exchange, ExchangeConfig, ExchangeOutput, OrderCancelled, OrderNotFound, OrderRejected, OrderReplaced, TradeRecord};
+pub use replay::{diff_fills, read_fills, replay, replay_binary, FillMismatch, ReplayedMessage, ReplaySummary, TimedMessage};
+pub use market_data::bars::{write_bars_csv, write_tape_csv, Bar, BarInterval, MarketStatistics, TapeEntry};
+pub use market_data::depth::{Bbo, Depth, LevelUpdate, PriceLevel};
+pub use market_data::metrics::{BookMetrics, MetricsConfig};
+pub use market_data::statistics::SessionStatistics;
+pub use market_data::itch::{ItchError, ItchMessage};
+pub use market_data::itch::encode::{encode_message, ItchEncoder};
+pub use market_data::itch::decode::{decode_message, decode_stream, L3Book};
+#[cfg(feature = "fuzzing")]
+pub use fuzz::{fuzz_orderbook, fuzz_parse_order};
+pub use scenario::{run_scenario, run_scenario_file, ScenarioError};
+pub use simulation::flow::{FlowConfig, FlowEvent, OrderFlow};
+pub use output::{OutputFormat, OutputOptions, OutputWriter};
+pub use gateway::session::{Delivery, OrderAccepted, PublicTrade, SessionId, SessionReport, SessionRouter};
+pub use gateway::tcp::{serve, TcpServer};
+pub use gateway::http::{read_request, respond, spawn_http, write_response, HttpQuery, HttpRequest, HttpResponse};
+pub use gateway::fix::message::{FixError, FixMessage};
+pub use gateway::fix::session::{FixActions, FixSession};
+pub use gateway::fix::acceptor::{serve_fix, FixAcceptor};
+pub use gateway::websocket::server::{serve_websocket, Channel, Subscription, SubscriptionRequest, WebSocketServer};
 
 #[cfg(test)]
 mod tests {
@@ -342,4 +385,171 @@ mod tests {
         ]);
     }
 
+    #[test]
+    fn cancel_order() {
+        let mut orderbook = Orderbook::new();
+
+        orderbook.process_order(&mut LIMIT_BUY_100_15.clone());
+        orderbook.process_order(&mut LIMIT_BUY_98_100.clone());
+        orderbook.process_order(&mut ICEBERG_SELL_100_25_300.clone());
+
+        assert_eq!(orderbook.cancel_order(42), None);
+        let cancelled = orderbook.cancel_order(5).unwrap();
+        assert_eq!(cancelled.iceberg.unwrap().hidden_quantity, 275);
+        assert_eq!(cancelled.quantity, 10);
+
+        assert_eq!(orderbook.cancel_order(3).unwrap().order_key.id, 3);
+        assert!(!orderbook.has_order(3));
+        assert_eq!(orderbook.best_buy_price(), None);
+        assert_eq!(orderbook.best_sell_price(), None);
+        assert_eq!(orderbook.get_orders().buy_orders, vec![]);
+    }
+
+    #[test]
+    fn order_status() {
+        let mut orderbook = Orderbook::new();
+
+        orderbook.process_order(&mut ICEBERG_SELL_100_25_300.clone());
+        orderbook.process_order(&mut Order {order_key: OrderKey {id: 6, ..ICEBERG_SELL_100_25_300.order_key}, quantity: 10, iceberg: None});
+        orderbook.process_order(&mut Order {quantity: 30, ..LIMIT_BUY_100_15});
+
+        assert_eq!(orderbook.get_order(6), Some(OrderStatus {
+            id: 6,
+            state: ExecutionState::Open,
+            side: OrderSide::Sell,
+            price: 100,
+            visible_quantity: 5,
+            hidden_quantity: 0,
+            queue_position: 1,
+            filled_quantity: 5
+        }));
+        assert_eq!(orderbook.get_order(5), Some(OrderStatus {
+            id: 5,
+            state: ExecutionState::Open,
+            side: OrderSide::Sell,
+            price: 100,
+            visible_quantity: 25,
+            hidden_quantity: 250,
+            queue_position: 2,
+            filled_quantity: 25
+        }));
+        assert_eq!(orderbook.get_order(1), Some(OrderStatus {
+            id: 1,
+            state: ExecutionState::Filled,
+            side: OrderSide::Buy,
+            price: 100,
+            visible_quantity: 0,
+            hidden_quantity: 0,
+            queue_position: 0,
+            filled_quantity: 30
+        }));
+        assert_eq!(orderbook.get_order(2), None);
+
+        orderbook.process_order(&mut Order {quantity: 10, ..LIMIT_BUY_100_15});
+        assert_eq!(orderbook.get_order(5).unwrap().queue_position, 1);
+        assert_eq!(orderbook.get_order(5).unwrap().filled_quantity, 30);
+
+        let mut crossing_buy = Order {order_key: OrderKey {id: 7, price: 100, ..LIMIT_BUY_100_15.order_key}, quantity: 300, iceberg: None};
+        orderbook.process_order(&mut crossing_buy);
+        assert_eq!(orderbook.get_order(7).unwrap().filled_quantity, 270);
+        assert_eq!(orderbook.get_order(7).unwrap().visible_quantity, 30);
+
+        orderbook.cancel_order(7);
+        let cancelled = orderbook.get_order(7).unwrap();
+        assert_eq!((cancelled.state, cancelled.visible_quantity, cancelled.filled_quantity), (ExecutionState::Cancelled, 0, 270));
+    }
+
+    #[test]
+    fn forget_oldest_terminal_statuses() {
+        let mut orderbook = Orderbook::new();
+        for id in 0..=TERMINAL_ORDERS as u64 {
+            orderbook.process_order(&mut Order {order_key: OrderKey {id, ..LIMIT_BUY_100_15.order_key}, ..LIMIT_BUY_100_15});
+            orderbook.cancel_order(id);
+        }
+        assert_eq!(orderbook.get_order(0), None);
+        assert_eq!(orderbook.get_order(1).unwrap().state, ExecutionState::Cancelled);
+
+        let restored = Orderbook::restore(orderbook.snapshot()).unwrap();
+        assert_eq!(restored.get_order(1), orderbook.get_order(1));
+        assert_eq!(restored.get_order(TERMINAL_ORDERS as u64), orderbook.get_order(TERMINAL_ORDERS as u64));
+    }
+
+    #[test]
+    fn queue_position_after_iceberg_reload() {
+        let mut orderbook = Orderbook::new();
+
+        orderbook.process_order(&mut ICEBERG_SELL_100_25_300.clone());
+        (6..8).for_each(|id| {
+            orderbook.process_order(&mut Order {order_key: OrderKey {id, ..ICEBERG_SELL_100_25_300.order_key}, quantity: 10, iceberg: None});
+        });
+        assert_eq!(orderbook.queue_position(5), Some(QueuePosition {rank: 1, volume_ahead: 0}));
+        assert_eq!(orderbook.queue_position(7), Some(QueuePosition {rank: 3, volume_ahead: 35}));
+
+        orderbook.process_order(&mut Order {quantity: 30, ..LIMIT_BUY_100_15});
+        assert_eq!(orderbook.queue_position(6), Some(QueuePosition {rank: 1, volume_ahead: 0}));
+        assert_eq!(orderbook.queue_position(7), Some(QueuePosition {rank: 2, volume_ahead: 5}));
+        assert_eq!(orderbook.queue_position(5), Some(QueuePosition {rank: 3, volume_ahead: 15}));
+
+        orderbook.process_order(&mut Order {
+            order_key: OrderKey {id: 8, price: 101, ..ICEBERG_SELL_100_25_300.order_key},
+            quantity: 10,
+            iceberg: None
+        });
+        assert_eq!(orderbook.queue_position(8), Some(QueuePosition {rank: 1, volume_ahead: 0}));
+        assert_eq!(orderbook.queue_position(1), None);
+    }
+
+
+    #[test]
+    fn simulate_without_changing_book() {
+        let mut orderbook = Orderbook::new();
+        orderbook.process_order(&mut ICEBERG_SELL_100_25_300.clone());
+        orderbook.process_order(&mut Order {order_key: OrderKey {id: 6, ..ICEBERG_SELL_100_25_300.order_key}, quantity: 10, iceberg: None});
+        orderbook.process_order(&mut Order {order_key: OrderKey {id: 7, price: 102, ..ICEBERG_SELL_100_25_300.order_key}, quantity: 10, iceberg: None});
+        let before = orderbook.get_orders();
+
+        let mut buy = Order {order_key: OrderKey {id: 8, price: 100, ..LIMIT_BUY_100_15.order_key}, quantity: 60, iceberg: None};
+        let simulated = orderbook.simulate_order(&buy);
+        assert_eq!(orderbook.get_orders().sell_orders, before.sell_orders);
+        assert_eq!(orderbook.time_counter(), 3);
+        assert_eq!(simulated.fills.iter().map(|fill_event| (fill_event.sell_order_id, fill_event.quantity)).collect::<Vec<_>>(), vec![(5, 25), (6, 10), (5, 25)]);
+        assert_eq!((simulated.filled_quantity, simulated.average_price, simulated.leftover_quantity), (60, Some(100.0), 0));
+        assert_eq!(simulated.fills, orderbook.process_order(&mut buy));
+
+        let iceberg_buy = Order {
+            order_key: OrderKey {id: 9, price: 102, ..LIMIT_BUY_100_15.order_key},
+            quantity: 20,
+            iceberg: Some(IcebergOrder {peak_size: 20, hidden_quantity: 280})
+        };
+        let simulated = orderbook.simulate_order(&iceberg_buy);
+        assert_eq!((simulated.filled_quantity, simulated.leftover_quantity), (260, 40));
+        assert_eq!(simulated.average_price, Some((250.0 * 100.0 + 10.0 * 102.0) / 260.0));
+        assert_eq!(simulated.fills, orderbook.process_order(&mut iceberg_buy.clone()));
+
+        let duplicate = Order {order_key: OrderKey {order_side: OrderSide::Sell, price: 1, ..iceberg_buy.order_key}, ..LIMIT_BUY_100_15};
+        let simulated = orderbook.simulate_order(&duplicate);
+        assert_eq!((simulated.fills.len(), simulated.leftover_quantity), (0, 0));
+        assert_eq!(simulated.fills, orderbook.process_order(&mut duplicate.clone()));
+        assert_eq!(orderbook.get_order(9).map(|order_status| order_status.visible_quantity + order_status.hidden_quantity), Some(40));
+
+        let mut orderbook = Orderbook::new();
+        orderbook.process_order(&mut Order {order_key: OrderKey {price: u64::MAX, ..ICEBERG_SELL_100_25_300.order_key}, quantity: 2, iceberg: None});
+        let simulated = orderbook.simulate_order(&Order {order_key: OrderKey {price: u64::MAX, ..LIMIT_BUY_100_15.order_key}, ..LIMIT_BUY_100_15});
+        assert_eq!(simulated.average_price, Some(u64::MAX as f64));
+    }
+
+    #[test]
+    fn ignore_icebergs_that_never_show_hidden_quantity() {
+        let mut orderbook = Orderbook::new();
+        let zero_peak = Order {iceberg: Some(IcebergOrder {peak_size: 0, hidden_quantity: 275}), ..ICEBERG_SELL_100_25_300};
+        assert_eq!(orderbook.simulate_order(&zero_peak).leftover_quantity, 0);
+        assert_eq!(orderbook.process_order(&mut zero_peak.clone()), vec![]);
+        assert!(!orderbook.has_order(5));
+
+        let hidden_only = Order {quantity: 0, iceberg: Some(IcebergOrder {peak_size: 25, hidden_quantity: 300}), ..ICEBERG_SELL_100_25_300};
+        assert_eq!(orderbook.process_order(&mut hidden_only.clone()), vec![]);
+        assert_eq!(orderbook.resting_order(5).map(|order| order.quantity), Some(25));
+        let fills = orderbook.process_order(&mut Order {quantity: 30, ..LIMIT_BUY_100_15});
+        assert_eq!(fills.iter().map(|fill_event| fill_event.quantity).sum::<u64>(), 30);
+    }
 }
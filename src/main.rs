@@ -1,35 +1,381 @@
-use std::io;
-use serde_json;
-use matching_engine::orderbook::{Orderbook};
-use matching_engine::parse::{parse_order, DeserializedOrder};
-mod matching_engine;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::Path;
+use std::time::Duration;
+use std::{env, process};
+use serde_json::json;
+use orderbook::{write_bars_csv, write_tape_csv, BarInterval, MarketStatistics, diff_fills, read_fills, replay, replay_binary, serve, serve_fix, serve_websocket, Exchange, ExchangeConfig, ExchangeOutput, FeeSchedule, FlowConfig, MetricsConfig, OrderFlow, run_scenario_file, OutputOptions, OutputWriter, ReplayedMessage, ReplaySummary, TimedMessage};
 
+const USAGE: &str = "\
+usage: orderbook [run] [options]
+       orderbook replay <input> [--stop-at <sequence>] [--dump-book] [--expected <output>] [options]
+       orderbook snapshot --journal <path> --output <path> [--snapshot <path>] [options]
+       orderbook stats [--input <path>] [options]
+       orderbook bars [--input <path>] [--interval <interval>] [--tape <count>] [--csv] [options]
+       orderbook generate [--count <count>] [--seed <seed>]
+       orderbook scenario <file>...
+       orderbook serve --listen <address> [--protocol <json|fix|websocket>] [--http <address>] [options]
 
-fn main() {
-    let mut orderbook = Orderbook::new();
-
-    let mut buffer = String::new();
-    loop {
-        buffer.clear();
-        match io::stdin().read_line(&mut buffer) {
-            Ok(0) => break,
-            Ok(_) => (),
-            Err(error) => println!("error: {}", error),
-        }
-
-        match serde_json::from_str::<DeserializedOrder>(buffer.as_str()) {
-            Err(error) => println!("{}", error),
-            Ok(deserialized_order) => {
-                let mut order = parse_order(deserialized_order);
-                let events = orderbook.process_order(&mut order);
-                let orders = orderbook.get_orders();
-
-                println!("{}", serde_json::to_string(&orders).unwrap());
-                for event in events {
-                    println!("{}", serde_json::to_string(&event).unwrap());
+options:
+    --input <path>          read messages from a file instead of stdin
+    --input-format <format> json (default) or binary order entry frames
+    --journal <path>        journal accepted commands and recover from them on start
+    --snapshot <path>       restore the book from a snapshot before replaying the journal
+    --listen <address>      address to accept order entry sessions on, e.g. 127.0.0.1:7000
+    --protocol <protocol>   serve json (default), FIX 4.4 or websocket order entry and market data
+    --http <address>        also serve GET /book, /depth, /order/<id>, /trades and /stats over HTTP
+    --comp-id <id>          SenderCompID of the exchange for FIX sessions (default EXCHANGE)
+    --heartbeat-timeout <s> cancel orders of JSON sessions silent for this many seconds (at least 0.1)
+    --interval <interval>   bar interval for bars, a trade count like 100trades (default) or a time like 500ms, 1s, 5m
+    --tape <count>          print the last <count> trades instead of bars
+    --csv                   print bars or the tape as CSV instead of JSON
+    --metrics               print book metrics (imbalance, microprice, slope, ...) after every message
+    --metrics-levels <n>    price levels per side used for the book metrics (default 5)
+    --metrics-distance <d>  price distance from the best price for depth at distance (default 10)
+    --count <count>         number of messages for generate (default 10000)
+    --seed <seed>           random seed for generate (default 1)
+    --format <format>       book (default), l2, deltas, fills or itch (binary L3 feed)
+    --levels <count>        limit l2 and deltas output to the best price levels
+    --pretty                pretty-print JSON
+    --no-separators         do not print blank lines between messages
+    --instrument <name>     instrument name used for positions
+    --mark <mid|last>       mark positions against the mid price or the last trade
+    --fees <path>           JSON fee schedule
+    --spot <base>/<quote>   reserve spot balances before orders rest";
+
+struct Args {
+    command: String,
+    positional: Vec<String>,
+    input: Option<String>,
+    input_format: String,
+    journal: Option<String>,
+    snapshot: Option<String>,
+    output: Option<String>,
+    listen: Option<String>,
+    http: Option<String>,
+    heartbeat_timeout: Option<f64>,
+    protocol: String,
+    comp_id: String,
+    stop_at: Option<u64>,
+    dump_book: bool,
+    interval: BarInterval,
+    tape: Option<usize>,
+    csv: bool,
+    metrics: bool,
+    count: usize,
+    flow_config: FlowConfig,
+    metrics_config: MetricsConfig,
+    expected: Option<String>,
+    output_options: OutputOptions,
+    config: ExchangeConfig
+}
+
+fn exit_with(message: &str, code: i32) -> ! {
+    eprintln!("{}", message);
+    process::exit(code);
+}
+
+fn value<'a, I: Iterator<Item = &'a String>>(args: &mut I, option: &str) -> Result<String, String> {
+    args.next().cloned().ok_or_else(|| format!("missing value for {}", option))
+}
+
+fn number<T: std::str::FromStr>(value: String, option: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid value {} for {}", value, option))
+}
+
+// Sessions are checked every quarter of the timeout, so a tiny one would keep
+// the engine thread spinning.
+const MIN_HEARTBEAT_TIMEOUT: f64 = 0.1;
+
+fn heartbeat_timeout(value: String, option: &str) -> Result<f64, String> {
+    let seconds: f64 = number(value, option)?;
+    if !seconds.is_finite() || seconds < MIN_HEARTBEAT_TIMEOUT {
+        return Err(format!("invalid value {} for {}, expected a finite number of seconds of at least {}", seconds, option, MIN_HEARTBEAT_TIMEOUT));
+    }
+    Ok(seconds)
+}
+
+fn load_fee_schedule(path: &str) -> Result<FeeSchedule, String> {
+    let content = fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+    let schedule: FeeSchedule = serde_json::from_str(&content).map_err(|error| format!("{}: {}", path, error))?;
+    Ok(FeeSchedule::tiered(schedule.tiers().to_vec()))
+}
+
+fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut args = args.iter().peekable();
+    let command = match args.peek().map(|arg| arg.as_str()) {
+        Some("run") | Some("replay") | Some("snapshot") | Some("stats") | Some("bars") | Some("generate") | Some("scenario") | Some("serve") => args.next().unwrap().clone(),
+        _ => "run".to_string()
+    };
+
+    let mut parsed = Args {
+        command,
+        positional: Vec::new(),
+        input: None,
+        input_format: "json".to_string(),
+        journal: None,
+        snapshot: None,
+        output: None,
+        listen: None,
+        http: None,
+        heartbeat_timeout: None,
+        protocol: "json".to_string(),
+        comp_id: "EXCHANGE".to_string(),
+        stop_at: None,
+        dump_book: false,
+        interval: BarInterval::Trades(100),
+        tape: None,
+        csv: false,
+        metrics: false,
+        count: 10_000,
+        flow_config: FlowConfig::default(),
+        metrics_config: MetricsConfig::default(),
+        expected: None,
+        output_options: OutputOptions::default(),
+        config: ExchangeConfig::default()
+    };
+    while let Some(arg) = args.next() {
+        let option = arg.as_str();
+        match option {
+            "--input" => parsed.input = Some(value(&mut args, option)?),
+            "--input-format" => parsed.input_format = value(&mut args, option)?,
+            "--journal" => parsed.journal = Some(value(&mut args, option)?),
+            "--snapshot" => parsed.snapshot = Some(value(&mut args, option)?),
+            "--output" => parsed.output = Some(value(&mut args, option)?),
+            "--listen" => parsed.listen = Some(value(&mut args, option)?),
+            "--http" => parsed.http = Some(value(&mut args, option)?),
+            "--heartbeat-timeout" => parsed.heartbeat_timeout = Some(heartbeat_timeout(value(&mut args, option)?, option)?),
+            "--protocol" => parsed.protocol = value(&mut args, option)?,
+            "--comp-id" => parsed.comp_id = value(&mut args, option)?,
+            "--stop-at" => parsed.stop_at = Some(number(value(&mut args, option)?, option)?),
+            "--dump-book" => parsed.dump_book = true,
+            "--interval" => parsed.interval = value(&mut args, option)?.parse()?,
+            "--tape" => parsed.tape = Some(number(value(&mut args, option)?, option)?),
+            "--csv" => parsed.csv = true,
+            "--count" => parsed.count = number(value(&mut args, option)?, option)?,
+            "--seed" => parsed.flow_config.seed = number(value(&mut args, option)?, option)?,
+            "--expected" => parsed.expected = Some(value(&mut args, option)?),
+            "--format" => parsed.output_options.format = value(&mut args, option)?.parse()?,
+            "--levels" => parsed.output_options.depth_levels = Some(number(value(&mut args, option)?, option)?),
+            "--pretty" => parsed.output_options.pretty = true,
+            "--no-separators" => parsed.output_options.separators = false,
+            "--instrument" => parsed.config.instrument = value(&mut args, option)?,
+            "--mark" => parsed.config.mark_method = value(&mut args, option)?.parse()?,
+            "--metrics" => parsed.metrics = true,
+            "--metrics-levels" => parsed.metrics_config.levels = number(value(&mut args, option)?, option)?,
+            "--metrics-distance" => parsed.metrics_config.distance = number(value(&mut args, option)?, option)?,
+            "--fees" => parsed.config.fee_schedule = load_fee_schedule(&value(&mut args, option)?)?,
+            "--spot" => {
+                let assets = value(&mut args, option)?;
+                match assets.split_once('/') {
+                    Some((base, quote)) => parsed.config.spot_assets = Some((base.to_string(), quote.to_string())),
+                    None => return Err(format!("invalid value {} for --spot, expected <base>/<quote>", assets))
                 }
-                println!("");
             }
+            "--help" | "-h" => return Err(USAGE.to_string()),
+            _ if option.starts_with("--") => return Err(format!("unknown option {}\n\n{}", option, USAGE)),
+            _ => parsed.positional.push(arg.clone())
+        }
+    }
+    if parsed.metrics {
+        parsed.config.metrics = Some(parsed.metrics_config);
+    }
+    Ok(parsed)
+}
+
+fn open_input(path: Option<&String>) -> Box<dyn BufRead> {
+    match path {
+        None => Box::new(BufReader::new(io::stdin())),
+        Some(path) => match File::open(path) {
+            Ok(file) => Box::new(BufReader::new(file)),
+            Err(error) => exit_with(&format!("{}: {}", path, error), 1)
         }
+    }
+}
+
+fn replay_input<F>(args: &Args, input: Box<dyn BufRead>, exchange: &mut Exchange, stop_at: Option<u64>, mut on_message: F) -> ReplaySummary
+where
+    F: FnMut(ReplayedMessage, Result<&[ExchangeOutput], serde_json::Error>)
+{
+    let summary = match args.input_format.as_str() {
+        "json" => replay(input, exchange, stop_at, on_message),
+        "binary" => replay_binary(input, exchange, stop_at, |sequence, outputs| {
+            on_message(ReplayedMessage {sequence, timestamp: None}, Ok(outputs))
+        }),
+        other => exit_with(&format!("unknown input format {}", other), 2)
+    };
+    summary.unwrap_or_else(|error| exit_with(&format!("error: {}", error), 1))
+}
+
+fn build_exchange(args: &Args) -> Exchange {
+    let config = args.config.clone();
+    let exchange = match (&args.snapshot, &args.journal) {
+        (Some(_), None) => Err("--snapshot requires --journal".to_string()),
+        (Some(snapshot), Some(journal)) if Path::new(snapshot).exists() =>
+            Exchange::recover_with_snapshot(config, snapshot, journal).map_err(|error| error.to_string()),
+        (_, Some(journal)) => Exchange::recover(config, journal).map_err(|error| error.to_string()),
+        (_, None) => Ok(Exchange::new(config))
+    };
+    exchange.unwrap_or_else(|error| exit_with(&error, 1))
+}
+
+fn write_message<W: Write>(output_writer: &mut OutputWriter<W>, outputs: Result<&[ExchangeOutput], serde_json::Error>) {
+    let result = match outputs {
+        Err(error) => output_writer.write_error(&error.to_string()),
+        Ok(outputs) => output_writer.write_outputs(outputs)
     };
+    result.unwrap_or_else(|error| exit_with(&format!("error: {}", error), 1));
+}
+
+fn run(args: Args) {
+    let mut exchange = build_exchange(&args);
+    let mut output_writer = OutputWriter::new(io::stdout(), args.output_options);
+    replay_input(&args, open_input(args.input.as_ref()), &mut exchange, None, |_, outputs| write_message(&mut output_writer, outputs));
+}
+
+fn run_replay(args: Args) {
+    let input = args.positional.first().or(args.input.as_ref()).unwrap_or_else(|| exit_with(USAGE, 2));
+    let quiet = args.dump_book || args.expected.is_some();
+
+    let mut exchange = Exchange::new(args.config.clone());
+    let mut output_writer = OutputWriter::new(io::stdout(), args.output_options);
+    let summary = replay_input(&args, open_input(Some(input)), &mut exchange, args.stop_at, |_, outputs| {
+        if !quiet {
+            write_message(&mut output_writer, outputs);
+        }
+    });
+
+    if args.dump_book {
+        output_writer.write_value(&exchange.orderbook().get_orders()).unwrap();
+    }
+    if let Some(expected) = args.expected.as_ref() {
+        let expected_fills = read_fills(open_input(Some(expected))).unwrap_or_else(|error| exit_with(&error.to_string(), 1));
+        let mismatches = diff_fills(&expected_fills, &summary.fills);
+        for mismatch in mismatches.iter() {
+            output_writer.write_value(mismatch).unwrap();
+        }
+        if !mismatches.is_empty() {
+            process::exit(1);
+        }
+    }
+}
+
+fn run_snapshot(args: Args) {
+    if args.journal.is_none() {
+        exit_with("snapshot requires --journal", 2);
+    }
+    let output = args.output.as_ref().unwrap_or_else(|| exit_with("snapshot requires --output", 2));
+    let exchange = build_exchange(&args);
+    exchange.save_snapshot(output).unwrap_or_else(|error| exit_with(&format!("{}: {}", output, error), 1));
+
+    let mut output_writer = OutputWriter::new(io::stdout(), args.output_options);
+    output_writer.write_value(&json!({
+        "snapshot": output,
+        "journalSequence": exchange.journal().map_or(0, |journal| journal.next_sequence() - 1),
+        "timeCounter": exchange.orderbook().time_counter()
+    })).unwrap();
+}
+
+// Same session statistics as the `Stats` command, after the whole input.
+fn run_stats(args: Args) {
+    let mut exchange = build_exchange(&args);
+    replay_input(&args, open_input(args.input.as_ref()), &mut exchange, args.stop_at, |_, _| ());
+
+    let mut output_writer = OutputWriter::new(io::stdout(), args.output_options);
+    output_writer.write_value(&exchange.statistics()).unwrap();
+}
+
+// Trades take the timestamp of the message that caused them, so clock bars
+// need one on every message that trades.
+fn run_bars(args: Args) {
+    let mut exchange = build_exchange(&args);
+    let mut statistics = MarketStatistics::new(args.interval, args.tape.unwrap_or(0));
+    replay_input(&args, open_input(args.input.as_ref()), &mut exchange, args.stop_at, |replayed_message, outputs| {
+        for output in outputs.unwrap_or(&[]) {
+            if let ExchangeOutput::Fill(annotated_fill) = output {
+                let timestamp = match (args.interval, replayed_message.timestamp) {
+                    (BarInterval::Millis(_), None) => exit_with(
+                        &format!("message {} trades without a timestamp, which clock bars need", replayed_message.sequence),
+                        1
+                    ),
+                    (_, timestamp) => timestamp.unwrap_or(0)
+                };
+                statistics.on_fill(&annotated_fill.fill_event, timestamp);
+            }
+        }
+    });
+
+    let mut bars = statistics.take_bars();
+    bars.extend(statistics.current_bar().copied());
+    let mut stdout = io::stdout();
+    let result = match (args.tape, args.csv) {
+        (Some(_), true) => write_tape_csv(&mut stdout, statistics.tape()),
+        (None, true) => write_bars_csv(&mut stdout, &bars),
+        (tape, false) => {
+            let mut output_writer = OutputWriter::new(stdout, args.output_options);
+            match tape {
+                Some(_) => statistics.tape().try_for_each(|entry| output_writer.write_value(entry)),
+                None => bars.iter().try_for_each(|bar| output_writer.write_value(bar))
+            }
+        }
+    };
+    result.unwrap_or_else(|error| exit_with(&format!("error: {}", error), 1));
+}
+
+// Synthetic order flow as JSON lines, ready for run, replay or a session.
+fn run_generate(args: Args) {
+    let mut output_writer = OutputWriter::new(io::stdout(), args.output_options);
+    for event in OrderFlow::new(args.flow_config).take(args.count) {
+        let timed_message = TimedMessage {timestamp: Some(event.timestamp / 1000), message: event.message};
+        output_writer.write_value(&timed_message).unwrap_or_else(|error| exit_with(&format!("error: {}", error), 1));
+    }
+}
+
+fn run_scenarios(args: Args) {
+    if args.positional.is_empty() {
+        exit_with(USAGE, 2);
+    }
+    let mut failed = 0;
+    for path in args.positional.iter() {
+        match run_scenario_file(path) {
+            Ok(()) => println!("ok      {}", path),
+            Err(error) => {
+                println!("FAILED  {}: {}", path, error);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+fn run_server(args: Args) {
+    let address = args.listen.as_ref().unwrap_or_else(|| exit_with("serve requires --listen", 2));
+    let listener = TcpListener::bind(address).unwrap_or_else(|error| exit_with(&format!("{}: {}", address, error), 1));
+    let http = args.http.as_ref().map(|address| {
+        TcpListener::bind(address).unwrap_or_else(|error| exit_with(&format!("{}: {}", address, error), 1))
+    });
+    match args.protocol.as_str() {
+        "json" => serve(listener, build_exchange(&args), args.heartbeat_timeout.map(Duration::from_secs_f64), http),
+        "fix" => serve_fix(listener, build_exchange(&args), &args.comp_id, http),
+        "websocket" => serve_websocket(listener, build_exchange(&args), http),
+        other => exit_with(&format!("unknown protocol {}", other), 2)
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args = parse_args(&args).unwrap_or_else(|error| exit_with(&error, 2));
+    match args.command.as_str() {
+        "replay" => run_replay(args),
+        "snapshot" => run_snapshot(args),
+        "stats" => run_stats(args),
+        "bars" => run_bars(args),
+        "generate" => run_generate(args),
+        "scenario" => run_scenarios(args),
+        "serve" => run_server(args),
+        _ => run(args)
+    }
 }
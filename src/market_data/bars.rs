@@ -0,0 +1,241 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::str::FromStr;
+use serde::{Serialize};
+
+use crate::matching_engine::order::FillEvent;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BarInterval {
+    Trades(u64),
+    Millis(u64)
+}
+
+// Accepts a trade count like `100trades` or a clock interval like `500ms`,
+// `30s`, `5m` or `1h`.
+impl FromStr for BarInterval {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+        let (count, unit) = value.split_at(split);
+        let count: u64 = count.parse().map_err(|_| format!("invalid interval {}", value))?;
+        let interval = match unit {
+            "trades" => Some(BarInterval::Trades(count)),
+            "ms" => Some(BarInterval::Millis(count)),
+            "s" => count.checked_mul(1000).map(BarInterval::Millis),
+            "m" => count.checked_mul(60_000).map(BarInterval::Millis),
+            "h" => count.checked_mul(3_600_000).map(BarInterval::Millis),
+            _ => return Err(format!("invalid interval {}, expected e.g. 100trades, 500ms, 1s, 5m or 1h", value))
+        };
+        match interval {
+            None | Some(BarInterval::Trades(0)) | Some(BarInterval::Millis(0)) => Err(format!("invalid interval {}", value)),
+            Some(interval) => Ok(interval)
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TapeEntry {
+    pub sequence: u64,
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub fill_event: FillEvent
+}
+
+// `start` is the sequence number of the first trade for trade count bars and
+// the start of the interval, in milliseconds, for clock bars.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Bar {
+    pub start: u64,
+    pub open: u64,
+    pub high: u64,
+    pub low: u64,
+    pub close: u64,
+    pub volume: u64,
+    pub notional: u64,
+    pub vwap: f64,
+    pub trade_count: u64
+}
+
+impl Bar {
+    fn new(start: u64, fill_event: &FillEvent) -> Self {
+        let mut bar = Bar {
+            start,
+            open: fill_event.price,
+            high: fill_event.price,
+            low: fill_event.price,
+            close: fill_event.price,
+            volume: 0,
+            notional: 0,
+            vwap: 0.0,
+            trade_count: 0
+        };
+        bar.add(fill_event);
+        bar
+    }
+
+    fn add(&mut self, fill_event: &FillEvent) {
+        self.high = self.high.max(fill_event.price);
+        self.low = self.low.min(fill_event.price);
+        self.close = fill_event.price;
+        self.volume = self.volume.saturating_add(fill_event.quantity);
+        self.notional = self.notional.saturating_add(fill_event.price.saturating_mul(fill_event.quantity));
+        self.vwap = self.notional as f64 / self.volume as f64;
+        self.trade_count += 1;
+    }
+}
+
+// Keeps the last `tape_capacity` trades and rolls them into bars. Intervals
+// without trades produce no bar.
+pub struct MarketStatistics {
+    interval: BarInterval,
+    tape_capacity: usize,
+    tape: VecDeque<TapeEntry>,
+    next_sequence: u64,
+    current: Option<Bar>,
+    bars: Vec<Bar>
+}
+
+impl MarketStatistics {
+    pub fn new(interval: BarInterval, tape_capacity: usize) -> Self {
+        MarketStatistics {
+            interval,
+            tape_capacity,
+            tape: VecDeque::new(),
+            next_sequence: 1,
+            current: None,
+            bars: Vec::new()
+        }
+    }
+
+    // `timestamp` is in milliseconds and only matters for clock bars.
+    pub fn on_fill(&mut self, fill_event: &FillEvent, timestamp: u64) {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        if self.tape_capacity > 0 {
+            if self.tape.len() == self.tape_capacity {
+                self.tape.pop_front();
+            }
+            self.tape.push_back(TapeEntry {sequence, timestamp, fill_event: *fill_event});
+        }
+
+        let start = match self.interval {
+            BarInterval::Trades(_) => sequence,
+            BarInterval::Millis(millis) => timestamp - timestamp % millis
+        };
+        match self.current.as_mut() {
+            Some(bar) if matches!(self.interval, BarInterval::Trades(_)) || bar.start == start => bar.add(fill_event),
+            _ => {
+                self.bars.extend(self.current.take());
+                self.current = Some(Bar::new(start, fill_event));
+            }
+        }
+
+        if let (BarInterval::Trades(count), Some(bar)) = (self.interval, self.current) {
+            if bar.trade_count == count {
+                self.bars.push(bar);
+                self.current = None;
+            }
+        }
+    }
+
+    pub fn tape(&self) -> impl Iterator<Item = &TapeEntry> {
+        self.tape.iter()
+    }
+
+    pub fn bars(&self) -> &[Bar] {
+        &self.bars
+    }
+
+    // The bar still collecting trades, if any.
+    pub fn current_bar(&self) -> Option<&Bar> {
+        self.current.as_ref()
+    }
+
+    // Removes and returns the completed bars.
+    pub fn take_bars(&mut self) -> Vec<Bar> {
+        std::mem::take(&mut self.bars)
+    }
+}
+
+pub fn write_bars_csv<W: Write>(writer: &mut W, bars: &[Bar]) -> io::Result<()> {
+    writeln!(writer, "start,open,high,low,close,volume,notional,vwap,tradeCount")?;
+    for bar in bars {
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{},{},{}",
+            bar.start, bar.open, bar.high, bar.low, bar.close, bar.volume, bar.notional, bar.vwap, bar.trade_count
+        )?;
+    }
+    Ok(())
+}
+
+pub fn write_tape_csv<'a, W: Write, I: IntoIterator<Item = &'a TapeEntry>>(writer: &mut W, tape: I) -> io::Result<()> {
+    writeln!(writer, "sequence,timestamp,buyOrderId,sellOrderId,price,quantity")?;
+    for entry in tape {
+        let fill_event = entry.fill_event;
+        writeln!(
+            writer,
+            "{},{},{},{},{},{}",
+            entry.sequence, entry.timestamp, fill_event.buy_order_id, fill_event.sell_order_id, fill_event.price, fill_event.quantity
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn fill(price: u64, quantity: u64) -> FillEvent {
+        FillEvent {buy_order_id: 1, sell_order_id: 2, price, quantity}
+    }
+
+    #[test]
+    fn trade_count_bars() {
+        let mut statistics = MarketStatistics::new("3trades".parse().unwrap(), 2);
+        for (price, quantity) in [(100, 10), (102, 5), (99, 5), (101, 1)].iter() {
+            statistics.on_fill(&fill(*price, *quantity), 0);
+        }
+
+        assert_eq!(statistics.bars(), &[Bar {
+            start: 1,
+            open: 100,
+            high: 102,
+            low: 99,
+            close: 99,
+            volume: 20,
+            notional: 2005,
+            vwap: 100.25,
+            trade_count: 3
+        }]);
+        assert_eq!(statistics.current_bar().map(|bar| (bar.start, bar.trade_count)), Some((4, 1)));
+        assert_eq!(statistics.tape().map(|entry| entry.sequence).collect::<Vec<u64>>(), vec![3, 4]);
+
+        let mut csv = Vec::new();
+        write_bars_csv(&mut csv, &statistics.take_bars()).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "start,open,high,low,close,volume,notional,vwap,tradeCount\n1,100,102,99,99,20,2005,100.25,3\n");
+        assert!(statistics.bars().is_empty());
+    }
+
+    #[test]
+    fn clock_bars() {
+        let mut statistics = MarketStatistics::new("1s".parse().unwrap(), 0);
+        statistics.on_fill(&fill(100, 1), 1_200);
+        statistics.on_fill(&fill(101, 1), 1_900);
+        statistics.on_fill(&fill(98, 2), 4_000);
+
+        let bars: Vec<(u64, u64, u64)> = statistics.bars().iter().map(|bar| (bar.start, bar.close, bar.trade_count)).collect();
+        assert_eq!(bars, vec![(1_000, 101, 2)]);
+        assert_eq!(statistics.current_bar().unwrap().start, 4_000);
+        assert_eq!(statistics.tape().count(), 0);
+
+        assert_eq!("5m".parse(), Ok(BarInterval::Millis(300_000)));
+        assert!("0trades".parse::<BarInterval>().is_err());
+        assert!("5 minutes".parse::<BarInterval>().is_err());
+        assert!(format!("{}h", u64::MAX / 1000).parse::<BarInterval>().is_err());
+    }
+}
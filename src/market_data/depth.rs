@@ -0,0 +1,138 @@
+use serde::{Serialize};
+
+use crate::matching_engine::order::{Order, OrderSide};
+use crate::matching_engine::orderbook::{Orderbook, OrderbookContent};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PriceLevel {
+    pub price: u64,
+    pub quantity: u64,
+    pub order_count: u64
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Depth {
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Bbo {
+    pub bid: Option<PriceLevel>,
+    pub ask: Option<PriceLevel>
+}
+
+// A level update with zero quantity and order count means the level is gone.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LevelUpdate {
+    pub side: OrderSide,
+    #[serde(flatten)]
+    pub level: PriceLevel
+}
+
+fn aggregate(orders: &[Order], levels: Option<usize>) -> Vec<PriceLevel> {
+    let mut price_levels: Vec<PriceLevel> = Vec::new();
+    for order in orders {
+        match price_levels.last_mut() {
+            Some(level) if level.price == order.order_key.price => {
+                level.quantity = level.quantity.saturating_add(order.quantity);
+                level.order_count += 1;
+            }
+            _ => {
+                if levels.is_some_and(|levels| price_levels.len() == levels) {
+                    break;
+                }
+                price_levels.push(PriceLevel {price: order.order_key.price, quantity: order.quantity, order_count: 1});
+            }
+        }
+    }
+    price_levels
+}
+
+fn side_updates(side: OrderSide, previous: &[PriceLevel], current: &[PriceLevel], updates: &mut Vec<LevelUpdate>) {
+    for level in current {
+        if !previous.contains(level) {
+            updates.push(LevelUpdate {side, level: *level});
+        }
+    }
+    for level in previous {
+        if !current.iter().any(|current_level| current_level.price == level.price) {
+            updates.push(LevelUpdate {side, level: PriceLevel {price: level.price, quantity: 0, order_count: 0}});
+        }
+    }
+}
+
+impl Depth {
+    pub fn from_content(content: &OrderbookContent, levels: Option<usize>) -> Self {
+        Depth {
+            bids: aggregate(&content.buy_orders, levels),
+            asks: aggregate(&content.sell_orders, levels)
+        }
+    }
+
+    pub fn from_orderbook(orderbook: &Orderbook, levels: Option<usize>) -> Self {
+        Depth::from_content(&orderbook.get_orders(), levels)
+    }
+
+    pub fn bbo(&self) -> Bbo {
+        Bbo {bid: self.bids.first().copied(), ask: self.asks.first().copied()}
+    }
+
+    pub fn deltas(&self, previous: &Depth) -> Vec<LevelUpdate> {
+        let mut updates = Vec::new();
+        side_updates(OrderSide::Buy, &previous.bids, &self.bids, &mut updates);
+        side_updates(OrderSide::Sell, &previous.asks, &self.asks, &mut updates);
+        updates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::test_support::limit;
+
+    #[test]
+    fn aggregate_levels() {
+        let mut orderbook = Orderbook::new();
+        orderbook.process_order(&mut limit(1, OrderSide::Buy, 100, 10));
+        orderbook.process_order(&mut limit(2, OrderSide::Buy, 100, 5));
+        orderbook.process_order(&mut limit(3, OrderSide::Buy, 99, 7));
+        orderbook.process_order(&mut Order {
+            iceberg: Some(IcebergOrder {peak_size: 20, hidden_quantity: 80}),
+            ..limit(4, OrderSide::Sell, 102, 20)
+        });
+
+        let depth = Depth::from_orderbook(&orderbook, None);
+        assert_eq!(depth.bids, vec![
+            PriceLevel {price: 100, quantity: 15, order_count: 2},
+            PriceLevel {price: 99, quantity: 7, order_count: 1}
+        ]);
+        assert_eq!(depth.asks, vec![PriceLevel {price: 102, quantity: 20, order_count: 1}]);
+        assert_eq!(Depth::from_orderbook(&orderbook, Some(1)).bids.len(), 1);
+        assert_eq!(depth.bbo(), Bbo {bid: Some(depth.bids[0]), ask: Some(depth.asks[0])});
+
+        orderbook.process_order(&mut limit(5, OrderSide::Buy, 101, u64::MAX / 2 + 1));
+        orderbook.process_order(&mut limit(6, OrderSide::Buy, 101, u64::MAX / 2 + 1));
+        assert_eq!(Depth::from_orderbook(&orderbook, Some(1)).bids, vec![PriceLevel {price: 101, quantity: u64::MAX, order_count: 2}]);
+    }
+
+    #[test]
+    fn level_deltas() {
+        let mut orderbook = Orderbook::new();
+        orderbook.process_order(&mut limit(1, OrderSide::Buy, 100, 10));
+        orderbook.process_order(&mut limit(2, OrderSide::Buy, 99, 10));
+        let previous = Depth::from_orderbook(&orderbook, None);
+
+        orderbook.process_order(&mut limit(3, OrderSide::Sell, 100, 10));
+        orderbook.process_order(&mut limit(4, OrderSide::Buy, 99, 5));
+        let current = Depth::from_orderbook(&orderbook, None);
+
+        assert_eq!(current.deltas(&previous), vec![
+            LevelUpdate {side: OrderSide::Buy, level: PriceLevel {price: 99, quantity: 15, order_count: 2}},
+            LevelUpdate {side: OrderSide::Buy, level: PriceLevel {price: 100, quantity: 0, order_count: 0}}
+        ]);
+        assert_eq!(current.deltas(&current), vec![]);
+    }
+}
@@ -0,0 +1,215 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+use crate::matching_engine::order::{Order, OrderKey, OrderSide};
+use crate::matching_engine::orderbook::OrderbookContent;
+use super::{parse_side, ItchError, ItchMessage, HEADER_LENGTH};
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+// Decodes the message at the start of `bytes`, returning its sequence number
+// and encoded length. `Ok(None)` means more bytes are needed.
+pub fn decode_message(bytes: &[u8]) -> Result<Option<(u64, ItchMessage, usize)>, ItchError> {
+    let message_type = match bytes.first() {
+        None => return Ok(None),
+        Some(message_type) => *message_type
+    };
+    let length = ItchMessage::length(message_type).ok_or(ItchError::UnknownMessageType(message_type))?;
+    if bytes.len() < length {
+        return Ok(None);
+    }
+
+    let sequence = read_u64(bytes, 1);
+    let body = HEADER_LENGTH;
+    let message = match message_type {
+        b'A' => ItchMessage::AddOrder {
+            order_id: read_u64(bytes, body),
+            side: parse_side(bytes[body + 8])?,
+            quantity: read_u64(bytes, body + 9),
+            price: read_u64(bytes, body + 17)
+        },
+        b'E' => ItchMessage::OrderExecuted {
+            order_id: read_u64(bytes, body),
+            quantity: read_u64(bytes, body + 8),
+            match_id: read_u64(bytes, body + 16)
+        },
+        b'D' => ItchMessage::OrderDelete {order_id: read_u64(bytes, body)},
+        b'U' => ItchMessage::OrderReplace {
+            original_order_id: read_u64(bytes, body),
+            new_order_id: read_u64(bytes, body + 8),
+            quantity: read_u64(bytes, body + 16),
+            price: read_u64(bytes, body + 24)
+        },
+        _ => ItchMessage::Trade {
+            buy_order_id: read_u64(bytes, body),
+            sell_order_id: read_u64(bytes, body + 8),
+            aggressor: parse_side(bytes[body + 16])?,
+            quantity: read_u64(bytes, body + 17),
+            price: read_u64(bytes, body + 25),
+            match_id: read_u64(bytes, body + 33)
+        }
+    };
+    Ok(Some((sequence, message, length)))
+}
+
+pub fn decode_stream(bytes: &[u8]) -> Result<Vec<(u64, ItchMessage)>, ItchError> {
+    let mut messages = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        match decode_message(&bytes[offset..])? {
+            None => return Err(ItchError::Truncated),
+            Some((sequence, message, length)) => {
+                messages.push((sequence, message));
+                offset += length;
+            }
+        }
+    }
+    Ok(messages)
+}
+
+// Rebuilds the visible order-by-order book from a feed. Orders keep the
+// sequence number of the message that added them as their time priority.
+pub struct L3Book {
+    next_sequence: u64,
+    orders: HashMap<u64, Order>
+}
+
+impl Default for L3Book {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl L3Book {
+    pub fn new() -> Self {
+        L3Book {next_sequence: 1, orders: HashMap::new()}
+    }
+
+    pub fn apply(&mut self, sequence: u64, message: &ItchMessage) -> Result<(), ItchError> {
+        if sequence != self.next_sequence {
+            return Err(ItchError::SequenceGap {expected: self.next_sequence, received: sequence});
+        }
+        self.next_sequence += 1;
+
+        match *message {
+            ItchMessage::AddOrder {order_id, side, quantity, price} => self.add(order_id, side, quantity, price, sequence),
+            ItchMessage::OrderExecuted {order_id, quantity, ..} => {
+                let order = self.orders.get_mut(&order_id).ok_or(ItchError::UnknownOrder(order_id))?;
+                order.quantity = order.quantity.saturating_sub(quantity);
+                if order.quantity == 0 {
+                    self.orders.remove(&order_id);
+                }
+                Ok(())
+            }
+            ItchMessage::OrderDelete {order_id} => self.orders.remove(&order_id).map(|_| ()).ok_or(ItchError::UnknownOrder(order_id)),
+            ItchMessage::OrderReplace {original_order_id, new_order_id, quantity, price} => {
+                let original = self.orders.remove(&original_order_id).ok_or(ItchError::UnknownOrder(original_order_id))?;
+                self.add(new_order_id, original.order_key.order_side, quantity, price, sequence)
+            }
+            ItchMessage::Trade {..} => Ok(())
+        }
+    }
+
+    pub fn order(&self, order_id: u64) -> Option<&Order> {
+        self.orders.get(&order_id)
+    }
+
+    // Same ordering as `Orderbook::get_orders`, best price and oldest first.
+    pub fn content(&self) -> OrderbookContent {
+        let side = |order_side: OrderSide| {
+            let mut orders: Vec<Order> = self.orders
+                .values()
+                .filter(|order| order.order_key.order_side == order_side)
+                .copied()
+                .collect();
+            orders.sort_by_key(|order| Reverse(order.order_key));
+            orders
+        };
+        OrderbookContent {buy_orders: side(OrderSide::Buy), sell_orders: side(OrderSide::Sell)}
+    }
+
+    fn add(&mut self, order_id: u64, side: OrderSide, quantity: u64, price: u64, sequence: u64) -> Result<(), ItchError> {
+        if self.orders.contains_key(&order_id) {
+            return Err(ItchError::DuplicateOrder(order_id));
+        }
+        let order_key = OrderKey {id: order_id, price, timestamp: sequence, order_side: side};
+        self.orders.insert(order_id, Order {order_key, quantity, iceberg: None});
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::test_support::message;
+
+    #[test]
+    fn round_trip_every_message_type() {
+        let messages = [
+            ItchMessage::AddOrder {order_id: 1, side: OrderSide::Buy, quantity: 10, price: 100},
+            ItchMessage::OrderExecuted {order_id: 1, quantity: 4, match_id: 1},
+            ItchMessage::OrderDelete {order_id: u64::MAX},
+            ItchMessage::OrderReplace {original_order_id: 1, new_order_id: 2, quantity: 6, price: 101},
+            ItchMessage::Trade {buy_order_id: 2, sell_order_id: 3, aggressor: OrderSide::Sell, quantity: 6, price: 101, match_id: 2}
+        ];
+        let mut buffer = Vec::new();
+        for (sequence, message) in messages.iter().enumerate() {
+            let start = buffer.len();
+            encode_message(sequence as u64 + 1, message, &mut buffer);
+            assert_eq!(buffer.len() - start, ItchMessage::length(message.message_type()).unwrap());
+        }
+
+        let decoded = decode_stream(&buffer).unwrap();
+        let expected: Vec<(u64, ItchMessage)> = messages.iter().enumerate().map(|(index, message)| (index as u64 + 1, *message)).collect();
+        assert_eq!(decoded, expected);
+        assert_eq!(decode_message(&buffer[..20]), Ok(None));
+        assert_eq!(decode_stream(&buffer[..buffer.len() - 1]), Err(ItchError::Truncated));
+        assert_eq!(decode_message(b"Z"), Err(ItchError::UnknownMessageType(b'Z')));
+    }
+
+    #[test]
+    fn rebuild_book_from_feed() {
+        let messages = [
+            r#"{"type": "Limit", "order": {"direction": "Buy", "id": 1, "price": 100, "quantity": 30}}"#,
+            r#"{"type": "Iceberg", "order": {"direction": "Buy", "id": 2, "price": 100, "quantity": 50, "peak": 10}}"#,
+            r#"{"type": "Limit", "order": {"direction": "Buy", "id": 3, "price": 99, "quantity": 5}}"#,
+            r#"{"type": "Limit", "order": {"direction": "Sell", "id": 4, "price": 100, "quantity": 45}}"#,
+            r#"{"type": "Iceberg", "order": {"direction": "Sell", "id": 5, "price": 101, "quantity": 40, "peak": 15}}"#,
+            r#"{"type": "Replace", "id": 3, "newId": 6, "price": 98, "quantity": 7}"#,
+            r#"{"type": "Cancel", "id": 1}"#,
+            r#"{"type": "Limit", "order": {"direction": "Buy", "id": 7, "price": 101, "quantity": 20}}"#,
+            r#"{"type": "Replace", "id": 2, "price": 100, "quantity": 8}"#
+        ];
+        let mut exchange = Exchange::new(ExchangeConfig::default());
+        let mut encoder = ItchEncoder::new();
+        let mut book = L3Book::new();
+        let mut stream = Vec::new();
+        for json in messages.iter() {
+            let encoded = encoder.encode(&exchange.handle(message(json)));
+            for (sequence, message) in decode_stream(&encoded).unwrap() {
+                book.apply(sequence, &message).unwrap();
+            }
+            assert_eq!(
+                serde_json::to_string(&book.content()).unwrap(),
+                serde_json::to_string(&exchange.orderbook().get_orders()).unwrap(),
+                "after {}", json
+            );
+            stream.extend(encoded);
+        }
+
+        let decoded = decode_stream(&stream).unwrap();
+        assert!(decoded.iter().any(|(_, message)| matches!(message, ItchMessage::OrderReplace {original_order_id: 3, new_order_id: 6, ..})));
+        let traded: u64 = decoded.iter().filter_map(|(_, message)| match message {
+            ItchMessage::Trade {quantity, ..} => Some(*quantity),
+            _ => None
+        }).sum();
+        assert_eq!(traded, 65);
+        assert_eq!(encoder.next_sequence(), decoded.len() as u64 + 1);
+
+        let mut book = L3Book::new();
+        assert_eq!(book.apply(2, &decoded[1].1), Err(ItchError::SequenceGap {expected: 1, received: 2}));
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::exchange::ExchangeOutput;
+use crate::matching_engine::order::{Order, OrderSide};
+use super::{side_code, ItchMessage};
+
+pub fn encode_message(sequence: u64, message: &ItchMessage, buffer: &mut Vec<u8>) {
+    buffer.push(message.message_type());
+    buffer.extend_from_slice(&sequence.to_be_bytes());
+    let fields = match *message {
+        ItchMessage::AddOrder {order_id, side, quantity, price} => {
+            buffer.extend_from_slice(&order_id.to_be_bytes());
+            buffer.push(side_code(side));
+            vec![quantity, price]
+        }
+        ItchMessage::OrderExecuted {order_id, quantity, match_id} => vec![order_id, quantity, match_id],
+        ItchMessage::OrderDelete {order_id} => vec![order_id],
+        ItchMessage::OrderReplace {original_order_id, new_order_id, quantity, price} => vec![original_order_id, new_order_id, quantity, price],
+        ItchMessage::Trade {buy_order_id, sell_order_id, aggressor, quantity, price, match_id} => {
+            buffer.extend_from_slice(&buy_order_id.to_be_bytes());
+            buffer.extend_from_slice(&sell_order_id.to_be_bytes());
+            buffer.push(side_code(aggressor));
+            vec![quantity, price, match_id]
+        }
+    };
+    for field in fields {
+        buffer.extend_from_slice(&field.to_be_bytes());
+    }
+}
+
+// Derives an L3 feed from the exchange outputs by keeping a shadow copy of
+// the visible book. Executions are reported against the resting order, and
+// every fill is also printed as a trade; consumers rebuilding the book only
+// need add, execute, delete and replace. An iceberg reload rests with a new
+// time priority, so it shows up as an add for the same order id once the
+// previous slice has been executed away. The add is sent straight after the
+// execution, since the reloaded slice can trade again within the same batch.
+pub struct ItchEncoder {
+    next_sequence: u64,
+    next_match_id: u64,
+    book: HashMap<u64, Order>
+}
+
+// The time priority of a slice reloaded within the batch is only known from
+// the book content, so it is not compared.
+fn same_visible_order(order: &Order, other: &Order, reloaded: &HashSet<u64>) -> bool {
+    order.order_key.price == other.order_key.price
+        && (order.order_key.timestamp == other.order_key.timestamp || reloaded.contains(&order.order_key.id))
+        && order.quantity == other.quantity
+}
+
+impl Default for ItchEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ItchEncoder {
+    pub fn new() -> Self {
+        ItchEncoder {next_sequence: 1, next_match_id: 1, book: HashMap::new()}
+    }
+
+    pub fn next_sequence(&self) -> u64 {
+        self.next_sequence
+    }
+
+    pub fn messages(&mut self, outputs: &[ExchangeOutput]) -> Vec<ItchMessage> {
+        let mut messages = Vec::new();
+        let mut reloaded = HashSet::new();
+        for output in outputs {
+            if let ExchangeOutput::Fill(annotated_fill) = output {
+                let fill_event = annotated_fill.fill_event;
+                let maker_id = match annotated_fill.taker_side {
+                    OrderSide::Buy => fill_event.sell_order_id,
+                    OrderSide::Sell => fill_event.buy_order_id
+                };
+                let match_id = self.next_match_id;
+                self.next_match_id += 1;
+                messages.push(ItchMessage::OrderExecuted {order_id: maker_id, quantity: fill_event.quantity, match_id});
+                messages.push(ItchMessage::Trade {
+                    buy_order_id: fill_event.buy_order_id,
+                    sell_order_id: fill_event.sell_order_id,
+                    aggressor: annotated_fill.taker_side,
+                    quantity: fill_event.quantity,
+                    price: fill_event.price,
+                    match_id
+                });
+
+                let mut reload = None;
+                let executed_away = match self.book.get_mut(&maker_id) {
+                    None => false,
+                    Some(order) => {
+                        order.quantity = order.quantity.saturating_sub(fill_event.quantity);
+                        if order.quantity == 0 {
+                            order.reload_iceberg_order();
+                            reload = Some(*order).filter(|order| order.quantity > 0);
+                        }
+                        order.quantity == 0
+                    }
+                };
+                if executed_away {
+                    self.book.remove(&maker_id);
+                }
+                if let Some(order) = reload {
+                    reloaded.insert(maker_id);
+                    messages.push(ItchMessage::AddOrder {
+                        order_id: maker_id,
+                        side: order.order_key.order_side,
+                        quantity: order.quantity,
+                        price: order.order_key.price
+                    });
+                }
+            }
+        }
+
+        let replaced: HashMap<u64, u64> = outputs
+            .iter()
+            .filter_map(|output| match output {
+                ExchangeOutput::Replaced(replaced) => Some((replaced.order_id, replaced.replaced_order_id)),
+                _ => None
+            })
+            .collect();
+        let content = outputs.iter().rev().find_map(|output| match output {
+            ExchangeOutput::Book(content) => Some(content),
+            _ => None
+        });
+        if let Some(content) = content {
+            let current: HashMap<u64, Order> = content.buy_orders
+                .iter()
+                .chain(content.sell_orders.iter())
+                .map(|order| (order.order_key.id, *order))
+                .collect();
+
+            let mut removed: Vec<u64> = self.book
+                .iter()
+                .filter(|(id, order)| current.get(id).map_or(true, |current_order| !same_visible_order(current_order, order, &reloaded)))
+                .map(|(id, _)| *id)
+                .collect();
+            let mut added: Vec<Order> = current
+                .values()
+                .filter(|order| self.book.get(&order.order_key.id).map_or(true, |previous| !same_visible_order(previous, order, &reloaded)))
+                .copied()
+                .collect();
+            removed.sort_unstable();
+            added.sort_by_key(|order| order.order_key.timestamp);
+
+            let mut replacements = Vec::new();
+            for order in added.iter() {
+                let new_order_id = order.order_key.id;
+                match replaced.get(&new_order_id) {
+                    Some(original_order_id) if new_order_id != *original_order_id && removed.contains(original_order_id) => {
+                        removed.retain(|id| id != original_order_id);
+                        replacements.push(ItchMessage::OrderReplace {
+                            original_order_id: *original_order_id,
+                            new_order_id,
+                            quantity: order.quantity,
+                            price: order.order_key.price
+                        });
+                    }
+                    _ => replacements.push(ItchMessage::AddOrder {
+                        order_id: new_order_id,
+                        side: order.order_key.order_side,
+                        quantity: order.quantity,
+                        price: order.order_key.price
+                    })
+                }
+            }
+            messages.extend(removed.into_iter().map(|order_id| ItchMessage::OrderDelete {order_id}));
+            messages.extend(replacements);
+            self.book = current;
+        }
+        messages
+    }
+
+    pub fn encode(&mut self, outputs: &[ExchangeOutput]) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        for message in self.messages(outputs) {
+            encode_message(self.next_sequence, &message, &mut buffer);
+            self.next_sequence += 1;
+        }
+        buffer
+    }
+}
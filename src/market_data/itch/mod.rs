@@ -0,0 +1,82 @@
+pub mod decode;
+pub mod encode;
+
+use std::fmt;
+
+use crate::matching_engine::order::OrderSide;
+
+// Every message starts with a one byte type and the eight byte stream
+// sequence number, followed by fixed-width big-endian fields.
+pub const HEADER_LENGTH: usize = 9;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ItchMessage {
+    AddOrder {order_id: u64, side: OrderSide, quantity: u64, price: u64},
+    OrderExecuted {order_id: u64, quantity: u64, match_id: u64},
+    OrderDelete {order_id: u64},
+    OrderReplace {original_order_id: u64, new_order_id: u64, quantity: u64, price: u64},
+    Trade {buy_order_id: u64, sell_order_id: u64, aggressor: OrderSide, quantity: u64, price: u64, match_id: u64}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ItchError {
+    UnknownMessageType(u8),
+    InvalidSide(u8),
+    SequenceGap {expected: u64, received: u64},
+    UnknownOrder(u64),
+    DuplicateOrder(u64),
+    Truncated
+}
+
+impl fmt::Display for ItchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ItchError::UnknownMessageType(message_type) => write!(f, "unknown message type {:#04x}", message_type),
+            ItchError::InvalidSide(side) => write!(f, "invalid side {:#04x}", side),
+            ItchError::SequenceGap {expected, received} => write!(f, "expected sequence {} but received {}", expected, received),
+            ItchError::UnknownOrder(order_id) => write!(f, "unknown order {}", order_id),
+            ItchError::DuplicateOrder(order_id) => write!(f, "order {} already exists", order_id),
+            ItchError::Truncated => write!(f, "stream ends in the middle of a message")
+        }
+    }
+}
+
+impl ItchMessage {
+    pub fn message_type(&self) -> u8 {
+        match self {
+            ItchMessage::AddOrder {..} => b'A',
+            ItchMessage::OrderExecuted {..} => b'E',
+            ItchMessage::OrderDelete {..} => b'D',
+            ItchMessage::OrderReplace {..} => b'U',
+            ItchMessage::Trade {..} => b'P'
+        }
+    }
+
+    // Encoded length including the header.
+    pub fn length(message_type: u8) -> Option<usize> {
+        let body_length = match message_type {
+            b'A' => 25,
+            b'E' => 24,
+            b'D' => 8,
+            b'U' => 32,
+            b'P' => 41,
+            _ => return None
+        };
+        Some(HEADER_LENGTH + body_length)
+    }
+}
+
+fn side_code(side: OrderSide) -> u8 {
+    match side {
+        OrderSide::Buy => b'B',
+        OrderSide::Sell => b'S'
+    }
+}
+
+fn parse_side(code: u8) -> Result<OrderSide, ItchError> {
+    match code {
+        b'B' => Ok(OrderSide::Buy),
+        b'S' => Ok(OrderSide::Sell),
+        _ => Err(ItchError::InvalidSide(code))
+    }
+}
@@ -0,0 +1,152 @@
+use serde::{Serialize};
+
+use crate::matching_engine::order::OrderSide;
+use crate::matching_engine::orderbook::Orderbook;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MetricsConfig {
+    pub levels: usize,
+    pub distance: u64
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {levels: 5, distance: 10}
+    }
+}
+
+// Prices are `None` while a side they need is empty.
+//
+// imbalance: (bid - ask) / (bid + ask) visible quantity over the top levels
+// microprice: best bid and ask weighted by the quantity on the opposite side
+// weightedMid: midpoint of the volume weighted bid and ask over the top levels
+// slope: price distance from the best to the last of the top levels per unit
+//        of visible quantity in them
+// depthAtDistance: visible quantity within `distance` of the best price
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BookMetrics {
+    pub imbalance: Option<f64>,
+    pub mid: Option<f64>,
+    pub microprice: Option<f64>,
+    pub weighted_mid: Option<f64>,
+    pub bid_slope: Option<f64>,
+    pub ask_slope: Option<f64>,
+    pub bid_depth_at_distance: u64,
+    pub ask_depth_at_distance: u64
+}
+
+// Level totals are summed in u128, where a u64 price times a u64 quantity
+// always fits.
+fn volume(levels: &[(u64, u64)]) -> u128 {
+    levels.iter().fold(0u128, |volume, (_, quantity)| volume.saturating_add(*quantity as u128))
+}
+
+fn vwap(levels: &[(u64, u64)]) -> Option<f64> {
+    let notional = levels.iter().fold(0u128, |notional, (price, quantity)| notional.saturating_add(*price as u128 * *quantity as u128));
+    match volume(levels) {
+        0 => None,
+        volume => Some(notional as f64 / volume as f64)
+    }
+}
+
+fn slope(levels: &[(u64, u64)]) -> Option<f64> {
+    match (levels.first(), levels.last()) {
+        (Some((best, _)), Some((last, _))) if levels.len() > 1 => Some(best.abs_diff(*last) as f64 / volume(levels) as f64),
+        _ => None
+    }
+}
+
+fn depth_at_distance(levels: &[(u64, u64)], distance: u64) -> u64 {
+    match levels.first() {
+        None => 0,
+        Some((best, _)) => levels
+            .iter()
+            .take_while(|(price, _)| price.abs_diff(*best) <= distance)
+            .fold(0u64, |depth, (_, quantity)| depth.saturating_add(*quantity))
+    }
+}
+
+impl BookMetrics {
+    pub fn from_orderbook(orderbook: &Orderbook, config: MetricsConfig) -> Self {
+        let bids = orderbook.visible_levels(OrderSide::Buy);
+        let asks = orderbook.visible_levels(OrderSide::Sell);
+        let top_bids = &bids[..bids.len().min(config.levels)];
+        let top_asks = &asks[..asks.len().min(config.levels)];
+
+        let (bid_volume, ask_volume) = (volume(top_bids) as f64, volume(top_asks) as f64);
+        let imbalance = match bid_volume + ask_volume {
+            total if total > 0.0 => Some((bid_volume - ask_volume) / total),
+            _ => None
+        };
+        let (mid, microprice) = match (bids.first(), asks.first()) {
+            (Some(&(bid, bid_quantity)), Some(&(ask, ask_quantity))) => {
+                let (bid, ask, bid_quantity, ask_quantity) = (bid as f64, ask as f64, bid_quantity as f64, ask_quantity as f64);
+                (Some((bid + ask) / 2.0), Some((bid * ask_quantity + ask * bid_quantity) / (bid_quantity + ask_quantity)))
+            }
+            _ => (None, None)
+        };
+        let weighted_mid = match (vwap(top_bids), vwap(top_asks)) {
+            (Some(bid), Some(ask)) => Some((bid + ask) / 2.0),
+            _ => None
+        };
+
+        BookMetrics {
+            imbalance,
+            mid,
+            microprice,
+            weighted_mid,
+            bid_slope: slope(top_bids),
+            ask_slope: slope(top_asks),
+            bid_depth_at_distance: depth_at_distance(&bids, config.distance),
+            ask_depth_at_distance: depth_at_distance(&asks, config.distance)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::test_support::limit;
+
+    #[test]
+    fn book_metrics() {
+        let mut orderbook = Orderbook::new();
+        assert_eq!(BookMetrics::from_orderbook(&orderbook, MetricsConfig::default()).microprice, None);
+
+        orderbook.process_order(&mut limit(1, OrderSide::Buy, 100, 30));
+        orderbook.process_order(&mut limit(2, OrderSide::Buy, 98, 10));
+        orderbook.process_order(&mut limit(3, OrderSide::Buy, 90, 50));
+        orderbook.process_order(&mut limit(4, OrderSide::Sell, 102, 10));
+        orderbook.process_order(&mut Order {
+            iceberg: Some(IcebergOrder {peak_size: 10, hidden_quantity: 90}),
+            ..limit(5, OrderSide::Sell, 104, 10)
+        });
+        assert_eq!(orderbook.visible_levels(OrderSide::Buy), vec![(100, 30), (98, 10), (90, 50)]);
+
+        let metrics = BookMetrics::from_orderbook(&orderbook, MetricsConfig {levels: 2, distance: 5});
+        assert_eq!(metrics, BookMetrics {
+            imbalance: Some(20.0 / 60.0),
+            mid: Some(101.0),
+            microprice: Some((100.0 * 10.0 + 102.0 * 30.0) / 40.0),
+            weighted_mid: Some((99.5 + 103.0) / 2.0),
+            bid_slope: Some(2.0 / 40.0),
+            ask_slope: Some(2.0 / 20.0),
+            bid_depth_at_distance: 40,
+            ask_depth_at_distance: 20
+        });
+    }
+
+    #[test]
+    fn large_orders_do_not_overflow() {
+        let mut orderbook = Orderbook::new();
+        orderbook.process_order(&mut limit(1, OrderSide::Buy, u64::MAX / 4, 8));
+        orderbook.process_order(&mut limit(2, OrderSide::Buy, 10, u64::MAX / 2 + 1));
+        orderbook.process_order(&mut limit(3, OrderSide::Buy, 10, u64::MAX / 2 + 1));
+        assert_eq!(orderbook.visible_levels(OrderSide::Buy)[1], (10, u64::MAX));
+
+        let metrics = BookMetrics::from_orderbook(&orderbook, MetricsConfig {levels: 1, distance: u64::MAX});
+        assert_eq!(metrics.bid_depth_at_distance, u64::MAX);
+        assert_eq!(metrics.imbalance, Some(1.0));
+    }
+}
@@ -0,0 +1,5 @@
+pub mod bars;
+pub mod depth;
+pub mod itch;
+pub mod metrics;
+pub mod statistics;
@@ -0,0 +1,39 @@
+use serde::{Deserialize, Serialize};
+
+use crate::matching_engine::order::FillEvent;
+use crate::matching_engine::orderbook::RestingQuantity;
+
+// Trade figures are updated on every fill and saturate instead of overflowing;
+// the resting quantities are taken from the book when the statistics are
+// requested.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionStatistics {
+    pub last_price: Option<u64>,
+    pub last_quantity: Option<u64>,
+    pub high: Option<u64>,
+    pub low: Option<u64>,
+    pub volume: u64,
+    pub notional: u64,
+    pub vwap: Option<f64>,
+    pub trade_count: u64,
+    pub bids: RestingQuantity,
+    pub asks: RestingQuantity
+}
+
+impl SessionStatistics {
+    pub fn new() -> Self {
+        SessionStatistics::default()
+    }
+
+    pub fn on_fill(&mut self, fill_event: &FillEvent) {
+        self.last_price = Some(fill_event.price);
+        self.last_quantity = Some(fill_event.quantity);
+        self.high = Some(self.high.map_or(fill_event.price, |high| high.max(fill_event.price)));
+        self.low = Some(self.low.map_or(fill_event.price, |low| low.min(fill_event.price)));
+        self.volume = self.volume.saturating_add(fill_event.quantity);
+        self.notional = self.notional.saturating_add(fill_event.price.saturating_mul(fill_event.quantity));
+        self.vwap = Some(self.notional as f64 / self.volume as f64);
+        self.trade_count += 1;
+    }
+}
@@ -0,0 +1,274 @@
+use std::convert::TryInto;
+use std::fmt;
+use std::io::{self, Read};
+
+use super::order::OrderSide;
+use super::parse::{DeserializedCommand, DeserializedOrder, InboundMessage, OrderCore};
+
+// Fixed-width little-endian frames, each starting with its type byte:
+//
+//   new order  'N' side:u8 id:u64 price:u64 quantity:u64 peak:u64   34 bytes
+//   cancel     'C' id:u64                                            9 bytes
+//   modify     'M' id:u64 new_id:u64 price:u64 quantity:u64         33 bytes
+//
+// Side is 'B' or 'S', a peak of zero means a plain limit order and a modify
+// with new_id equal to id keeps the order id. An iceberg with a zero peak
+// therefore has no encoding; the JSON path rejects it too.
+pub const NEW_ORDER: u8 = b'N';
+pub const CANCEL: u8 = b'C';
+pub const MODIFY: u8 = b'M';
+pub const MAX_MESSAGE_LENGTH: usize = 34;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BinaryError {
+    UnknownMessageType(u8),
+    InvalidSide(u8),
+    ZeroPeak(u64),
+    Truncated(u8),
+    Unsupported(String)
+}
+
+impl fmt::Display for BinaryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BinaryError::UnknownMessageType(message_type) => write!(f, "unknown message type {:#04x}", message_type),
+            BinaryError::InvalidSide(side) => write!(f, "invalid side {:#04x}", side),
+            BinaryError::ZeroPeak(id) => write!(f, "iceberg order {} has a zero peak", id),
+            BinaryError::Truncated(message_type) => write!(f, "input ends inside a {:?} frame", *message_type as char),
+            BinaryError::Unsupported(message) => write!(f, "{} has no binary encoding", message)
+        }
+    }
+}
+
+impl From<BinaryError> for io::Error {
+    fn from(error: BinaryError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+    }
+}
+
+pub fn message_length(message_type: u8) -> Result<usize, BinaryError> {
+    match message_type {
+        NEW_ORDER => Ok(34),
+        CANCEL => Ok(9),
+        MODIFY => Ok(33),
+        _ => Err(BinaryError::UnknownMessageType(message_type))
+    }
+}
+
+// A validated frame borrowed from the reader's buffer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BinaryMessage<'a> {
+    bytes: &'a [u8]
+}
+
+impl<'a> BinaryMessage<'a> {
+    // Returns `Ok(None)` when `bytes` does not hold a complete frame yet.
+    pub fn parse(bytes: &'a [u8]) -> Result<Option<BinaryMessage<'a>>, BinaryError> {
+        let message_type = match bytes.first() {
+            None => return Ok(None),
+            Some(message_type) => *message_type
+        };
+        let length = message_length(message_type)?;
+        if bytes.len() < length {
+            return Ok(None);
+        }
+        if message_type == NEW_ORDER && bytes[1] != b'B' && bytes[1] != b'S' {
+            return Err(BinaryError::InvalidSide(bytes[1]));
+        }
+        Ok(Some(BinaryMessage {bytes: &bytes[..length]}))
+    }
+
+    pub fn message_type(&self) -> u8 {
+        self.bytes[0]
+    }
+
+    pub fn id(&self) -> u64 {
+        match self.message_type() {
+            NEW_ORDER => self.u64_at(2),
+            _ => self.u64_at(1)
+        }
+    }
+
+    fn u64_at(&self, offset: usize) -> u64 {
+        u64::from_le_bytes(self.bytes[offset..offset + 8].try_into().unwrap())
+    }
+
+    fn side(&self) -> OrderSide {
+        match self.bytes[1] {
+            b'B' => OrderSide::Buy,
+            _ => OrderSide::Sell
+        }
+    }
+
+    pub fn to_inbound(&self) -> InboundMessage {
+        match self.message_type() {
+            NEW_ORDER => {
+                let order_core = OrderCore {
+                    direction: self.side(),
+                    id: self.id(),
+                    price: self.u64_at(10),
+                    quantity: self.u64_at(18),
+                    account: String::new()
+                };
+                match self.u64_at(26) {
+                    0 => InboundMessage::Order(DeserializedOrder::Limit {order_core}),
+                    peak => InboundMessage::Order(DeserializedOrder::Iceberg {order_core, peak})
+                }
+            }
+            CANCEL => InboundMessage::Command(DeserializedCommand::Cancel {id: self.id()}),
+            _ => {
+                let new_id = self.u64_at(9);
+                InboundMessage::Command(DeserializedCommand::Replace {
+                    id: self.id(),
+                    new_id: if new_id == self.id() { None } else { Some(new_id) },
+                    price: self.u64_at(17),
+                    quantity: self.u64_at(25)
+                })
+            }
+        }
+    }
+}
+
+pub fn encode_new_order(order_side: OrderSide, id: u64, price: u64, quantity: u64, peak: Option<u64>, buffer: &mut Vec<u8>) {
+    buffer.push(NEW_ORDER);
+    buffer.push(match order_side {
+        OrderSide::Buy => b'B',
+        OrderSide::Sell => b'S'
+    });
+    for field in [id, price, quantity, peak.unwrap_or(0)].iter() {
+        buffer.extend_from_slice(&field.to_le_bytes());
+    }
+}
+
+pub fn encode_cancel(id: u64, buffer: &mut Vec<u8>) {
+    buffer.push(CANCEL);
+    buffer.extend_from_slice(&id.to_le_bytes());
+}
+
+pub fn encode_modify(id: u64, new_id: u64, price: u64, quantity: u64, buffer: &mut Vec<u8>) {
+    buffer.push(MODIFY);
+    for field in [id, new_id, price, quantity].iter() {
+        buffer.extend_from_slice(&field.to_le_bytes());
+    }
+}
+
+// Order entry only; accounts, deposits and queries stay on the JSON path.
+pub fn encode_inbound(message: &InboundMessage, buffer: &mut Vec<u8>) -> Result<(), BinaryError> {
+    match message {
+        InboundMessage::Order(deserialized_order) if deserialized_order.order_core().account.is_empty() => {
+            let order_core = deserialized_order.order_core();
+            let peak = match deserialized_order {
+                DeserializedOrder::Limit {..} => None,
+                DeserializedOrder::Iceberg {peak: 0, ..} => return Err(BinaryError::ZeroPeak(order_core.id)),
+                DeserializedOrder::Iceberg {peak, ..} => Some(*peak)
+            };
+            encode_new_order(order_core.direction, order_core.id, order_core.price, order_core.quantity, peak, buffer);
+        }
+        InboundMessage::Command(DeserializedCommand::Cancel {id}) => encode_cancel(*id, buffer),
+        InboundMessage::Command(DeserializedCommand::Replace {id, new_id, price, quantity}) =>
+            encode_modify(*id, new_id.unwrap_or(*id), *price, *quantity, buffer),
+        other => return Err(BinaryError::Unsupported(serde_json::to_string(other).unwrap_or_default()))
+    }
+    Ok(())
+}
+
+pub struct BinaryReader<R: Read> {
+    input: R,
+    buffer: [u8; MAX_MESSAGE_LENGTH]
+}
+
+impl<R: Read> BinaryReader<R> {
+    pub fn new(input: R) -> Self {
+        BinaryReader {input, buffer: [0; MAX_MESSAGE_LENGTH]}
+    }
+
+    // Returns `None` at a clean end of input; a stream ending inside a frame
+    // is an error.
+    pub fn next_message(&mut self) -> io::Result<Option<BinaryMessage<'_>>> {
+        match self.input.read(&mut self.buffer[..1]) {
+            Ok(0) => return Ok(None),
+            Ok(_) => (),
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => return self.next_message(),
+            Err(error) => return Err(error)
+        }
+        let length = message_length(self.buffer[0])?;
+        match self.input.read_exact(&mut self.buffer[1..length]) {
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Err(BinaryError::Truncated(self.buffer[0]).into()),
+            result => result?
+        }
+        Ok(BinaryMessage::parse(&self.buffer[..length])?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use crate::*;
+
+    const SESSION: [&str; 9] = [
+        r#"{"type": "Limit", "order": {"direction": "Buy", "id": 1, "price": 100, "quantity": 30}}"#,
+        r#"{"type": "Iceberg", "order": {"direction": "Buy", "id": 2, "price": 100, "quantity": 50, "peak": 10}}"#,
+        r#"{"type": "Limit", "order": {"direction": "Buy", "id": 3, "price": 99, "quantity": 5}}"#,
+        r#"{"type": "Limit", "order": {"direction": "Sell", "id": 4, "price": 100, "quantity": 45}}"#,
+        r#"{"type": "Iceberg", "order": {"direction": "Sell", "id": 5, "price": 101, "quantity": 40, "peak": 15}}"#,
+        r#"{"type": "Replace", "id": 3, "newId": 6, "price": 98, "quantity": 7}"#,
+        r#"{"type": "Cancel", "id": 6}"#,
+        r#"{"type": "Limit", "order": {"direction": "Buy", "id": 7, "price": 101, "quantity": 20}}"#,
+        r#"{"type": "Replace", "id": 2, "price": 101, "quantity": 30}"#
+    ];
+
+    #[test]
+    fn decode_frames() {
+        let mut buffer = Vec::new();
+        encode_new_order(OrderSide::Sell, 4, 100, 500, Some(100), &mut buffer);
+        encode_cancel(4, &mut buffer);
+        encode_modify(4, 4, 101, 300, &mut buffer);
+        assert_eq!(buffer.len(), 34 + 9 + 33);
+
+        let new_order = BinaryMessage::parse(&buffer).unwrap().unwrap();
+        assert_eq!(new_order.to_inbound(), serde_json::from_str(
+            r#"{"type": "Iceberg", "order": {"direction": "Sell", "id": 4, "price": 100, "quantity": 500, "peak": 100}}"#
+        ).unwrap());
+        assert_eq!(BinaryMessage::parse(&buffer[34..]).unwrap().unwrap().to_inbound(), InboundMessage::Command(DeserializedCommand::Cancel {id: 4}));
+        assert_eq!(
+            BinaryMessage::parse(&buffer[43..]).unwrap().unwrap().to_inbound(),
+            InboundMessage::Command(DeserializedCommand::Replace {id: 4, new_id: None, price: 101, quantity: 300})
+        );
+
+        assert_eq!(BinaryMessage::parse(&buffer[..33]), Ok(None));
+        assert_eq!(BinaryMessage::parse(b"X"), Err(BinaryError::UnknownMessageType(b'X')));
+        let mut invalid_side = buffer[..34].to_vec();
+        invalid_side[1] = b'?';
+        assert_eq!(BinaryMessage::parse(&invalid_side), Err(BinaryError::InvalidSide(b'?')));
+        let mut reader = BinaryReader::new(&buffer[..40]);
+        assert_eq!(reader.next_message().unwrap().map(|message| message.to_inbound()), Some(new_order.to_inbound()));
+        let truncated = reader.next_message().unwrap_err();
+        assert_eq!((truncated.kind(), truncated.to_string()), (io::ErrorKind::InvalidData, BinaryError::Truncated(b'C').to_string()));
+
+        let zero_peak = serde_json::from_str(r#"{"type": "Iceberg", "order": {"direction": "Buy", "id": 8, "price": 100, "quantity": 50, "peak": 0}}"#).unwrap();
+        assert_eq!(encode_inbound(&zero_peak, &mut Vec::new()), Err(BinaryError::ZeroPeak(8)));
+    }
+
+    #[test]
+    fn same_fills_as_json() {
+        let mut binary_input = Vec::new();
+        for json in SESSION.iter() {
+            encode_inbound(&serde_json::from_str(json).unwrap(), &mut binary_input).unwrap();
+        }
+
+        let mut json_exchange = Exchange::new(ExchangeConfig::default());
+        let json_summary = replay(SESSION.join("\n").as_bytes(), &mut json_exchange, None, |_, _| ()).unwrap();
+        let mut binary_exchange = Exchange::new(ExchangeConfig::default());
+        let binary_summary = replay_binary(binary_input.as_slice(), &mut binary_exchange, None, |_, _| ()).unwrap();
+
+        assert_eq!(binary_summary, json_summary);
+        assert_eq!(json_summary.fills.len(), 7);
+        assert_eq!(
+            serde_json::to_string(&binary_exchange.orderbook().get_orders()).unwrap(),
+            serde_json::to_string(&json_exchange.orderbook().get_orders()).unwrap()
+        );
+
+        let truncated = replay_binary(&binary_input[..binary_input.len() - 1], &mut Exchange::new(ExchangeConfig::default()), None, |_, _| ());
+        assert!(truncated.is_err());
+    }
+}
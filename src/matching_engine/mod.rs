@@ -1,3 +1,10 @@
+pub mod binary;
 pub mod order;
 pub mod orderbook;
-pub mod parse;
\ No newline at end of file
+pub mod parse;
+pub mod snapshot;
+
+#[cfg(test)]
+mod properties;
+#[cfg(test)]
+mod reference;
@@ -1,7 +1,7 @@
 use std::cmp::Ordering;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct FillEvent {
     pub buy_order_id: u64,
@@ -29,13 +29,13 @@ pub struct Order {
     pub iceberg: Option<IcebergOrder>
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct IcebergOrder {
     pub hidden_quantity: u64,
     pub peak_size: u64,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum OrderSide {
     Buy,
     Sell
@@ -77,15 +77,15 @@ impl Order {
 impl Ord for OrderKey {
     fn cmp(&self, other: &Self) -> Ordering {
         if self.price < other.price {
-            return match self.order_side {
+            match self.order_side {
                 OrderSide::Buy => Ordering::Less,
                 OrderSide::Sell => Ordering::Greater
             }
         }
         else if self.price > other.price {
             match self.order_side {
-                OrderSide::Buy => return Ordering::Greater,
-                OrderSide::Sell => return Ordering::Less
+                OrderSide::Buy => Ordering::Greater,
+                OrderSide::Sell => Ordering::Less
             }
         } else {
             match self.timestamp < other.timestamp {
@@ -1,14 +1,70 @@
-use std::collections::{BinaryHeap, HashMap};
+use std::collections::{BTreeMap, BinaryHeap, HashMap, VecDeque};
 use super::order::{Order, OrderKey, FillEvent, OrderSide};
-use serde::{Serialize};
+use super::snapshot::{OrderState, OrderbookSnapshot, SnapshotError, SNAPSHOT_VERSION};
+use serde::{Deserialize, Serialize};
+
+// Statuses of filled and cancelled orders stay queryable until this many
+// newer orders have finished.
+pub const TERMINAL_ORDERS: usize = 10000;
 
 pub struct Orderbook {
     orders: HashMap<u64, Order>,
+    filled: HashMap<u64, u64>,
+    terminal: HashMap<u64, OrderStatus>,
+    terminal_ids: VecDeque<u64>,
     best_sell_orders: BinaryHeap<OrderKey>,
     best_buy_orders: BinaryHeap<OrderKey>,
     time_counter: u64
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExecutionState {
+    Open,
+    Filled,
+    Cancelled
+}
+
+// Queue position is 1 for the order first in line at its price level.
+// Finished orders have no quantity left and a queue position of 0.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderStatus {
+    pub id: u64,
+    pub state: ExecutionState,
+    pub side: OrderSide,
+    pub price: u64,
+    pub visible_quantity: u64,
+    pub hidden_quantity: u64,
+    pub queue_position: u64,
+    pub filled_quantity: u64
+}
+
+// Volume ahead only counts visible quantity: the hidden part of an iceberg
+// ahead in the queue reloads behind every order resting at the time.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueuePosition {
+    pub rank: u64,
+    pub volume_ahead: u64
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RestingQuantity {
+    pub visible: u64,
+    pub hidden: u64
+}
+
+// Leftover is the quantity, visible and hidden, that would rest in the book.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulatedExecution {
+    pub fills: Vec<FillEvent>,
+    pub filled_quantity: u64,
+    pub average_price: Option<f64>,
+    pub leftover_quantity: u64
+}
+
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct OrderbookContent {
@@ -16,17 +72,32 @@ pub struct OrderbookContent {
     pub sell_orders: Vec<Order>
 }
 
+impl Default for Orderbook {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Orderbook {
     pub fn new() -> Self {
         Orderbook {
             orders: HashMap::new(),
+            filled: HashMap::new(),
+            terminal: HashMap::new(),
+            terminal_ids: VecDeque::new(),
             best_sell_orders: BinaryHeap::new(),
             best_buy_orders: BinaryHeap::new(),
             time_counter: 0
         }
     }
 
+    // Orders reusing the id of a resting order are ignored: they would leave
+    // two heap entries pointing at one order. `Exchange` rejects them first.
     pub fn process_order(&mut self, order: &mut Order)  -> Vec<FillEvent> {
+        if self.ignores(order) {
+            return Vec::new();
+        }
+        order.reload_iceberg_order();
         let mut match_events = Vec::new();
         let mut ids_to_remove = Vec::new();
 
@@ -56,6 +127,7 @@ impl Orderbook {
 
                         order.quantity -= fill_event.quantity;
                         best_opposite_order.quantity -= fill_event.quantity;
+                        *self.filled.entry(best_opposite_order.order_key.id).or_insert(0) += fill_event.quantity;
 
                         if best_opposite_order.empty() {
                             best_opposite_orders.pop();
@@ -65,7 +137,7 @@ impl Orderbook {
                             best_opposite_order.reload_iceberg_order();
                             self.time_counter += 1;
                             best_opposite_order.order_key.timestamp = self.time_counter;
-                            best_opposite_orders.push(best_opposite_order.order_key.clone());
+                            best_opposite_orders.push(best_opposite_order.order_key);
                         }
 
                         if order.is_iceberg() {
@@ -75,11 +147,211 @@ impl Orderbook {
                 }
             }
         }
-        ids_to_remove.iter().for_each(|k| { self.orders.remove(&k); });
+        for id in ids_to_remove {
+            let maker = self.orders.remove(&id).unwrap();
+            let filled_quantity = self.filled.remove(&id).unwrap_or(0);
+            self.finish(&maker, ExecutionState::Filled, filled_quantity);
+        }
+        let taker_filled: u64 = match_events.iter().map(|fill_event| fill_event.quantity).sum();
+        if self.orders.contains_key(&order.order_key.id) {
+            if taker_filled > 0 {
+                self.filled.insert(order.order_key.id, taker_filled);
+            }
+        } else if taker_filled > 0 {
+            self.finish(order, ExecutionState::Filled, taker_filled);
+        }
         match_events
     }
 
 
+    // An order reusing the id of a resting one is ignored, and so is an iceberg
+    // with a zero peak and hidden quantity left, which would reload nothing
+    // forever. An iceberg without visible quantity shows its first peak.
+    fn ignores(&self, order: &Order) -> bool {
+        self.orders.contains_key(&order.order_key.id)
+            || order.iceberg.is_some_and(|iceberg| iceberg.peak_size == 0 && iceberg.hidden_quantity > 0)
+    }
+
+    // Runs the matching loop of `process_order` against copies of the resting
+    // orders the order crosses, reloading icebergs to the back of their level
+    // the same way, and leaves the book untouched. It ignores the same orders
+    // as `process_order`.
+    pub fn simulate_order(&self, order: &Order) -> SimulatedExecution {
+        if self.ignores(order) {
+            return SimulatedExecution {fills: Vec::new(), filled_quantity: 0, average_price: None, leftover_quantity: 0};
+        }
+        let mut order = *order;
+        order.reload_iceberg_order();
+        let opposite_order_keys = match order.order_key.order_side {
+            OrderSide::Sell => &self.best_buy_orders,
+            OrderSide::Buy => &self.best_sell_orders
+        };
+        let mut crossed_order_keys: BinaryHeap<OrderKey> = opposite_order_keys
+            .iter()
+            .filter(|order_key| match order_key.order_side {
+                OrderSide::Buy => order.order_key.price <= order_key.price,
+                OrderSide::Sell => order.order_key.price >= order_key.price
+            })
+            .copied()
+            .collect();
+        let mut makers: HashMap<u64, Order> = HashMap::new();
+        let mut time_counter = self.time_counter;
+        let mut fills = Vec::new();
+
+        while order.quantity != 0 {
+            let order_key = match crossed_order_keys.peek() {
+                None => break,
+                Some(order_key) => *order_key
+            };
+            let maker = makers.entry(order_key.id).or_insert_with(|| self.orders[&order_key.id]);
+            let fill_event = order.get_fill_event(maker);
+            fills.push(fill_event);
+
+            order.quantity -= fill_event.quantity;
+            maker.quantity -= fill_event.quantity;
+            if maker.empty() {
+                crossed_order_keys.pop();
+            } else if maker.quantity == 0 {
+                crossed_order_keys.pop();
+                maker.reload_iceberg_order();
+                time_counter += 1;
+                maker.order_key.timestamp = time_counter;
+                crossed_order_keys.push(maker.order_key);
+            }
+
+            if order.is_iceberg() {
+                order.reload_iceberg_order();
+            }
+        }
+
+        let filled_quantity: u64 = fills.iter().map(|fill_event| fill_event.quantity).sum();
+        // A u64 price times a u64 quantity always fits in a u128.
+        let notional = fills.iter().fold(0u128, |notional, fill_event| {
+            notional.saturating_add(fill_event.price as u128 * fill_event.quantity as u128)
+        });
+        SimulatedExecution {
+            fills,
+            filled_quantity,
+            average_price: (filled_quantity > 0).then(|| notional as f64 / filled_quantity as f64),
+            leftover_quantity: order.quantity + order.iceberg.map_or(0, |iceberg| iceberg.hidden_quantity)
+        }
+    }
+
+    pub fn cancel_order(&mut self, id: u64) -> Option<Order> {
+        let order = self.orders.remove(&id)?;
+        let filled_quantity = self.filled.remove(&id).unwrap_or(0);
+        self.finish(&order, ExecutionState::Cancelled, filled_quantity);
+        match order.order_key.order_side {
+            OrderSide::Buy => self.best_buy_orders.retain(|order_key| order_key.id != id),
+            OrderSide::Sell => self.best_sell_orders.retain(|order_key| order_key.id != id)
+        };
+        Some(order)
+    }
+
+    // A reused id replaces the older terminal status.
+    fn finish(&mut self, order: &Order, state: ExecutionState, filled_quantity: u64) {
+        let order_key = order.order_key;
+        self.remember(OrderStatus {
+            id: order_key.id,
+            state,
+            side: order_key.order_side,
+            price: order_key.price,
+            visible_quantity: 0,
+            hidden_quantity: 0,
+            queue_position: 0,
+            filled_quantity
+        });
+    }
+
+    fn remember(&mut self, order_status: OrderStatus) {
+        if self.terminal.insert(order_status.id, order_status).is_some() {
+            self.terminal_ids.retain(|id| *id != order_status.id);
+        }
+        self.terminal_ids.push_back(order_status.id);
+        if self.terminal_ids.len() > TERMINAL_ORDERS {
+            if let Some(id) = self.terminal_ids.pop_front() {
+                self.terminal.remove(&id);
+            }
+        }
+    }
+
+    pub fn time_counter(&self) -> u64 {
+        self.time_counter
+    }
+
+    // Resting orders first, then the last `TERMINAL_ORDERS` filled or
+    // cancelled ones.
+    pub fn get_order(&self, id: u64) -> Option<OrderStatus> {
+        let order = match self.orders.get(&id) {
+            None => return self.terminal.get(&id).copied(),
+            Some(order) => order
+        };
+        let order_key = order.order_key;
+        Some(OrderStatus {
+            id,
+            state: ExecutionState::Open,
+            side: order_key.order_side,
+            price: order_key.price,
+            visible_quantity: order.quantity,
+            hidden_quantity: order.iceberg.map_or(0, |iceberg| iceberg.hidden_quantity),
+            queue_position: self.orders_ahead(&order_key).count() as u64 + 1,
+            filled_quantity: self.filled.get(&id).copied().unwrap_or(0)
+        })
+    }
+
+    // Rank 1 is next to trade at the order's price. Ranks follow the queue
+    // timestamps, which an iceberg reload renews.
+    pub fn queue_position(&self, id: u64) -> Option<QueuePosition> {
+        let order_key = self.orders.get(&id)?.order_key;
+        let (orders_ahead, volume_ahead) = self.orders_ahead(&order_key)
+            .fold((0, 0), |(count, volume), order| (count + 1, volume + order.quantity));
+        Some(QueuePosition {rank: orders_ahead + 1, volume_ahead})
+    }
+
+    fn orders_ahead<'a>(&'a self, order_key: &'a OrderKey) -> impl Iterator<Item = &'a Order> + 'a {
+        self.orders.values().filter(move |other| {
+            other.order_key.order_side == order_key.order_side
+                && other.order_key.price == order_key.price
+                && other.order_key.timestamp < order_key.timestamp
+        })
+    }
+
+    pub fn resting_quantity(&self, order_side: OrderSide) -> RestingQuantity {
+        self.orders
+            .values()
+            .filter(|order| order.order_key.order_side == order_side)
+            .fold(RestingQuantity::default(), |resting, order| RestingQuantity {
+                visible: resting.visible + order.quantity,
+                hidden: resting.hidden + order.iceberg.map_or(0, |iceberg| iceberg.hidden_quantity)
+            })
+    }
+
+    // Visible quantity per price, best price first, aggregated straight from
+    // the side's heap instead of sorting it.
+    pub fn visible_levels(&self, order_side: OrderSide) -> Vec<(u64, u64)> {
+        let order_keys = match order_side {
+            OrderSide::Buy => &self.best_buy_orders,
+            OrderSide::Sell => &self.best_sell_orders
+        };
+        let mut levels: BTreeMap<u64, u64> = BTreeMap::new();
+        for order_key in order_keys.iter() {
+            let quantity = levels.entry(order_key.price).or_insert(0);
+            *quantity = quantity.saturating_add(self.orders[&order_key.id].quantity);
+        }
+        match order_side {
+            OrderSide::Buy => levels.into_iter().rev().collect(),
+            OrderSide::Sell => levels.into_iter().collect()
+        }
+    }
+
+    pub fn has_order(&self, id: u64) -> bool {
+        self.orders.contains_key(&id)
+    }
+
+    pub fn resting_order(&self, id: u64) -> Option<Order> {
+        self.orders.get(&id).copied()
+    }
+
     fn add_order(&mut self, order: &mut Order) {
         self.time_counter += 1;
         order.order_key.timestamp = self.time_counter;
@@ -97,7 +369,7 @@ impl Orderbook {
             .into_sorted_vec()
             .iter()
             .rev()
-            .map(|order_key| self.orders.get(&order_key.id).unwrap().clone())
+            .map(|order_key| *self.orders.get(&order_key.id).unwrap())
             .collect()
     }
 
@@ -107,14 +379,63 @@ impl Orderbook {
             .into_sorted_vec()
             .iter()
             .rev()
-            .map(|order_key| self.orders.get(&order_key.id).unwrap().clone())
+            .map(|order_key| *self.orders.get(&order_key.id).unwrap())
             .collect()
     }
 
+    pub fn best_buy_price(&self) -> Option<u64> {
+        self.best_buy_orders.peek().map(|order_key| order_key.price)
+    }
+
+    pub fn best_sell_price(&self) -> Option<u64> {
+        self.best_sell_orders.peek().map(|order_key| order_key.price)
+    }
+
     pub fn get_orders(&self) -> OrderbookContent {
         OrderbookContent {
             buy_orders: self.get_buy_orders(),
             sell_orders: self.get_sell_orders()
         }
     }
+
+    pub fn snapshot(&self) -> OrderbookSnapshot {
+        let orders = self.get_buy_orders()
+            .into_iter()
+            .chain(self.get_sell_orders())
+            .map(|order| OrderState {
+                filled_quantity: self.filled.get(&order.order_key.id).copied().unwrap_or(0),
+                ..order.into()
+            })
+            .collect();
+        let terminal = self.terminal_ids.iter().map(|id| self.terminal[id]).collect();
+        OrderbookSnapshot {version: SNAPSHOT_VERSION, time_counter: self.time_counter, orders, terminal}
+    }
+
+    pub fn restore(snapshot: OrderbookSnapshot) -> Result<Self, SnapshotError> {
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(snapshot.version));
+        }
+
+        let mut orderbook = Orderbook::new();
+        orderbook.time_counter = snapshot.time_counter;
+        snapshot.terminal.into_iter().for_each(|order_status| orderbook.remember(order_status));
+        for order_state in snapshot.orders {
+            if order_state.filled_quantity > 0 {
+                orderbook.filled.insert(order_state.id, order_state.filled_quantity);
+            }
+            let order: Order = order_state.into();
+            let order_key = order.order_key;
+            if order_key.timestamp > snapshot.time_counter {
+                return Err(SnapshotError::TimestampAhead {id: order_key.id, timestamp: order_key.timestamp});
+            }
+            if orderbook.orders.insert(order_key.id, order).is_some() {
+                return Err(SnapshotError::DuplicateOrderId(order_key.id));
+            }
+            match order_key.order_side {
+                OrderSide::Buy => orderbook.best_buy_orders.push(order_key),
+                OrderSide::Sell => orderbook.best_sell_orders.push(order_key)
+            };
+        }
+        Ok(orderbook)
+    }
 }
@@ -1,16 +1,18 @@
-use serde::{Deserialize};
+use serde::{Deserialize, Serialize};
 
 use super::order::{Order, OrderKey, OrderSide, IcebergOrder};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct OrderCore {
     pub direction: OrderSide,
     pub id: u64,
     pub price: u64,
-    pub quantity: u64
+    pub quantity: u64,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub account: String
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(tag = "type", content = "order")]
 pub enum DeserializedOrder {
     Limit {
@@ -24,6 +26,69 @@ pub enum DeserializedOrder {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum DeserializedCommand {
+    Cancel {
+        id: u64
+    },
+    #[serde(rename_all = "camelCase")]
+    Replace {
+        id: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        new_id: Option<u64>,
+        price: u64,
+        quantity: u64
+    },
+    Deposit {
+        account: String,
+        asset: String,
+        amount: u64
+    },
+    Status {
+        id: u64
+    },
+    Positions,
+    Balances,
+    Stats,
+    Heartbeat
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum InboundMessage {
+    Order(DeserializedOrder),
+    Command(DeserializedCommand)
+}
+
+impl InboundMessage {
+    pub fn is_query(&self) -> bool {
+        match self {
+            InboundMessage::Order(_) => false,
+            InboundMessage::Command(command) => match command {
+                DeserializedCommand::Cancel {..} | DeserializedCommand::Replace {..} | DeserializedCommand::Deposit {..} => false,
+                DeserializedCommand::Status {..} | DeserializedCommand::Positions | DeserializedCommand::Balances
+                    | DeserializedCommand::Stats | DeserializedCommand::Heartbeat => true
+            }
+        }
+    }
+}
+
+impl DeserializedOrder {
+    pub fn order_core(&self) -> &OrderCore {
+        match self {
+            DeserializedOrder::Limit {order_core} => order_core,
+            DeserializedOrder::Iceberg {order_core, ..} => order_core
+        }
+    }
+
+    pub fn order_core_mut(&mut self) -> &mut OrderCore {
+        match self {
+            DeserializedOrder::Limit {order_core} => order_core,
+            DeserializedOrder::Iceberg {order_core, ..} => order_core
+        }
+    }
+}
 
 pub fn parse_order(deserialized_order: DeserializedOrder) -> Order {
     match deserialized_order {
@@ -47,7 +112,7 @@ pub fn parse_order(deserialized_order: DeserializedOrder) -> Order {
             quantity: std::cmp::min(peak, order_core.quantity),
             iceberg: Some(IcebergOrder {
                 peak_size: peak,
-                hidden_quantity: std::cmp::max(order_core.quantity - peak, 0)
+                hidden_quantity: order_core.quantity.saturating_sub(peak)
             })
         }
     }
@@ -130,4 +195,30 @@ mod tests {
             }
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    pub fn parse_inbound_messages() {
+        let order = r#"{"type": "Limit", "order": {"direction": "Buy", "id": 1, "price": 100, "quantity": 5, "account": "alice"}}"#;
+        match serde_json::from_str::<InboundMessage>(order).unwrap() {
+            InboundMessage::Order(deserialized_order) => assert_eq!(deserialized_order.order_core().account, "alice"),
+            other => panic!("unexpected message {:?}", other)
+        }
+
+        let command = r#"{"type": "Positions"}"#;
+        match serde_json::from_str::<InboundMessage>(command).unwrap() {
+            InboundMessage::Command(DeserializedCommand::Positions) => (),
+            other => panic!("unexpected message {:?}", other)
+        }
+
+        let status: InboundMessage = serde_json::from_str(r#"{"type": "Status", "id": 3}"#).unwrap();
+        assert_eq!(status, InboundMessage::Command(DeserializedCommand::Status {id: 3}));
+        assert!(status.is_query());
+    }
+
+    #[test]
+    pub fn parse_iceberg_with_peak_above_quantity() {
+        let serialized_iceberg_order = r#"{"type": "Iceberg", "order": {"direction": "Sell", "id": 7, "price": 100, "quantity": 50, "peak": 80}}"#;
+        let order = parse_order(serde_json::from_str(serialized_iceberg_order).unwrap());
+        assert_eq!((order.quantity, order.iceberg), (50, Some(IcebergOrder {peak_size: 80, hidden_quantity: 0})));
+    }
+}
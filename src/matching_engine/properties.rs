@@ -0,0 +1,124 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::*;
+use crate::simulation::flow::Rng;
+
+const SEEDS: u64 = 100;
+const STEPS: u64 = 300;
+
+pub(crate) fn random_order(rng: &mut Rng, id: u64) -> Order {
+    let order_side = if rng.below(2) == 0 { OrderSide::Buy } else { OrderSide::Sell };
+    let price = 95 + rng.below(11);
+    let quantity = 1 + rng.below(50);
+    let order_key = OrderKey {id, price, timestamp: 0, order_side};
+    match rng.below(3) {
+        0 => {
+            let peak = 1 + rng.below(quantity);
+            Order {order_key, quantity: peak, iceberg: Some(IcebergOrder {peak_size: peak, hidden_quantity: quantity - peak})}
+        }
+        _ => Order {order_key, quantity, iceberg: None}
+    }
+}
+
+fn total_quantity(order: &Order) -> u64 {
+    order.quantity + order.iceberg.map_or(0, |iceberg| iceberg.hidden_quantity)
+}
+
+struct Stream {
+    orderbook: Orderbook,
+    submitted: HashMap<u64, u64>,
+    filled: HashMap<u64, u64>,
+    cancelled: u64
+}
+
+impl Stream {
+    fn new() -> Self {
+        Stream {orderbook: Orderbook::new(), submitted: HashMap::new(), filled: HashMap::new(), cancelled: 0}
+    }
+
+    fn submit(&mut self, mut order: Order, context: &str) {
+        let taker = order;
+        let content = self.orderbook.get_orders();
+        let queue = match taker.order_key.order_side {
+            OrderSide::Buy => content.sell_orders,
+            OrderSide::Sell => content.buy_orders
+        };
+        self.submitted.insert(taker.order_key.id, total_quantity(&taker));
+
+        let fill_events = self.orderbook.process_order(&mut order);
+
+        let mut makers_hit = HashSet::new();
+        for fill_event in fill_events.iter() {
+            let (maker_id, taker_id) = match taker.order_key.order_side {
+                OrderSide::Buy => (fill_event.sell_order_id, fill_event.buy_order_id),
+                OrderSide::Sell => (fill_event.buy_order_id, fill_event.sell_order_id)
+            };
+            assert_eq!(taker_id, taker.order_key.id, "{}: fill for another taker {:?}", context, fill_event);
+            let position = queue
+                .iter()
+                .position(|maker| maker.order_key.id == maker_id)
+                .unwrap_or_else(|| panic!("{}: fill against an order that was not resting {:?}", context, fill_event));
+            let maker = queue[position];
+
+            assert_eq!(fill_event.price, maker.order_key.price, "{}: fill away from the maker's price", context);
+            match taker.order_key.order_side {
+                OrderSide::Buy => assert!(fill_event.price <= taker.order_key.price, "{}: buy filled above its limit", context),
+                OrderSide::Sell => assert!(fill_event.price >= taker.order_key.price, "{}: sell filled below its limit", context)
+            }
+            assert!(fill_event.quantity > 0, "{}: empty fill", context);
+
+            // Everything ahead in the maker's level must have traded first.
+            for ahead in queue[..position].iter().filter(|ahead| ahead.order_key.price == maker.order_key.price) {
+                assert!(makers_hit.contains(&ahead.order_key.id), "{}: order {} filled before {} ahead of it", context, maker_id, ahead.order_key.id);
+            }
+            makers_hit.insert(maker_id);
+
+            *self.filled.entry(maker_id).or_insert(0) += fill_event.quantity;
+            *self.filled.entry(taker_id).or_insert(0) += fill_event.quantity;
+        }
+        self.check(context);
+    }
+
+    fn cancel(&mut self, id: u64, context: &str) {
+        if let Some(order) = self.orderbook.cancel_order(id) {
+            self.cancelled += total_quantity(&order);
+        }
+        self.check(context);
+    }
+
+    fn check(&self, context: &str) {
+        if let (Some(best_buy), Some(best_sell)) = (self.orderbook.best_buy_price(), self.orderbook.best_sell_price()) {
+            assert!(best_buy < best_sell, "{}: crossed book {} >= {}", context, best_buy, best_sell);
+        }
+
+        let content = self.orderbook.get_orders();
+        let resting: Vec<&Order> = content.buy_orders.iter().chain(content.sell_orders.iter()).collect();
+        let submitted: u64 = self.submitted.values().sum();
+        let filled: u64 = self.filled.values().sum();
+        let remaining: u64 = resting.iter().map(|order| total_quantity(order)).sum();
+        assert_eq!(submitted, filled + remaining + self.cancelled, "{}: quantity not conserved", context);
+
+        for order in resting.iter() {
+            let id = order.order_key.id;
+            assert!(order.quantity > 0, "{}: order {} rests without visible quantity", context, id);
+            let filled = self.filled.get(&id).copied().unwrap_or(0);
+            assert!(filled < self.submitted[&id], "{}: order {} rests although fully filled", context, id);
+            assert_eq!(filled + total_quantity(order), self.submitted[&id], "{}: order {} lost quantity", context, id);
+        }
+    }
+}
+
+#[test]
+fn random_streams_keep_invariants() {
+    for seed in 0..SEEDS {
+        let mut rng = Rng::new(seed);
+        let mut stream = Stream::new();
+        for step in 1..=STEPS {
+            let context = format!("seed {} step {}", seed, step);
+            match rng.below(10) {
+                0 => stream.cancel(1 + rng.below(step), &context),
+                _ => stream.submit(random_order(&mut rng, step), &context)
+            }
+        }
+    }
+}
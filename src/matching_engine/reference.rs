@@ -0,0 +1,201 @@
+use super::order::{FillEvent, Order, OrderSide};
+use super::orderbook::OrderbookContent;
+
+// A deliberately naive matcher: every step scans all resting orders. It keeps
+// the iceberg semantics of `Orderbook` (reloads go to the back of their level)
+// and serves as the model the real book is checked against.
+pub struct ReferenceBook {
+    orders: Vec<Order>,
+    time_counter: u64
+}
+
+impl Default for ReferenceBook {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReferenceBook {
+    pub fn new() -> Self {
+        ReferenceBook {orders: Vec::new(), time_counter: 0}
+    }
+
+    fn best_opposite(&self, order: &Order) -> Option<usize> {
+        let mut best: Option<usize> = None;
+        for (index, resting) in self.orders.iter().enumerate() {
+            if resting.order_key.order_side == order.order_key.order_side {
+                continue;
+            }
+            let crosses = match order.order_key.order_side {
+                OrderSide::Buy => resting.order_key.price <= order.order_key.price,
+                OrderSide::Sell => resting.order_key.price >= order.order_key.price
+            };
+            if !crosses {
+                continue;
+            }
+            best = match best {
+                None => Some(index),
+                Some(best_index) => {
+                    let current = &self.orders[best_index].order_key;
+                    let better_price = match order.order_key.order_side {
+                        OrderSide::Buy => resting.order_key.price < current.price,
+                        OrderSide::Sell => resting.order_key.price > current.price
+                    };
+                    let earlier = resting.order_key.price == current.price && resting.order_key.timestamp < current.timestamp;
+                    if better_price || earlier { Some(index) } else { Some(best_index) }
+                }
+            };
+        }
+        best
+    }
+
+    pub fn process_order(&mut self, order: &mut Order) -> Vec<FillEvent> {
+        let mut fill_events = Vec::new();
+        while order.quantity != 0 {
+            let index = match self.best_opposite(order) {
+                None => {
+                    self.time_counter += 1;
+                    order.order_key.timestamp = self.time_counter;
+                    self.orders.push(*order);
+                    break;
+                }
+                Some(index) => index
+            };
+
+            let maker = &mut self.orders[index];
+            let quantity = order.quantity.min(maker.quantity);
+            fill_events.push(match order.order_key.order_side {
+                OrderSide::Buy => FillEvent {buy_order_id: order.order_key.id, sell_order_id: maker.order_key.id, price: maker.order_key.price, quantity},
+                OrderSide::Sell => FillEvent {buy_order_id: maker.order_key.id, sell_order_id: order.order_key.id, price: maker.order_key.price, quantity}
+            });
+            order.quantity -= quantity;
+            maker.quantity -= quantity;
+
+            if maker.quantity == 0 {
+                match maker.iceberg {
+                    Some(ref mut iceberg) if iceberg.hidden_quantity > 0 => {
+                        maker.quantity = iceberg.peak_size.min(iceberg.hidden_quantity);
+                        iceberg.hidden_quantity -= maker.quantity;
+                        self.time_counter += 1;
+                        maker.order_key.timestamp = self.time_counter;
+                    }
+                    _ => {
+                        self.orders.remove(index);
+                    }
+                }
+            }
+            if order.quantity == 0 {
+                if let Some(ref mut iceberg) = order.iceberg {
+                    order.quantity = iceberg.peak_size.min(iceberg.hidden_quantity);
+                    iceberg.hidden_quantity -= order.quantity;
+                }
+            }
+        }
+        fill_events
+    }
+
+    pub fn cancel_order(&mut self, id: u64) -> Option<Order> {
+        let index = self.orders.iter().position(|order| order.order_key.id == id)?;
+        Some(self.orders.remove(index))
+    }
+
+    pub fn get_orders(&self) -> OrderbookContent {
+        let mut buy_orders: Vec<Order> = self.orders.iter().filter(|order| order.order_key.order_side == OrderSide::Buy).copied().collect();
+        let mut sell_orders: Vec<Order> = self.orders.iter().filter(|order| order.order_key.order_side == OrderSide::Sell).copied().collect();
+        buy_orders.sort_by_key(|order| (std::cmp::Reverse(order.order_key.price), order.order_key.timestamp));
+        sell_orders.sort_by_key(|order| (order.order_key.price, order.order_key.timestamp));
+        OrderbookContent {buy_orders, sell_orders}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::matching_engine::properties::random_order;
+    use crate::simulation::flow::Rng;
+    use crate::matching_engine::reference::ReferenceBook;
+
+    // Same session the SETSmm iceberg examples walk through, plus cancels.
+    const RECORDED: &str = r#"
+        {"type": "Limit", "order": {"direction": "Buy", "id": 1, "price": 99, "quantity": 50000}}
+        {"type": "Limit", "order": {"direction": "Buy", "id": 2, "price": 98, "quantity": 25500}}
+        {"type": "Limit", "order": {"direction": "Sell", "id": 3, "price": 100, "quantity": 10000}}
+        {"type": "Limit", "order": {"direction": "Sell", "id": 4, "price": 100, "quantity": 7500}}
+        {"type": "Limit", "order": {"direction": "Sell", "id": 5, "price": 101, "quantity": 20000}}
+        {"type": "Iceberg", "order": {"direction": "Buy", "id": 6, "price": 100, "quantity": 100000, "peak": 10000}}
+        {"type": "Iceberg", "order": {"direction": "Sell", "id": 7, "price": 100, "quantity": 20000, "peak": 5000}}
+        {"type": "Iceberg", "order": {"direction": "Sell", "id": 8, "price": 100, "quantity": 35000, "peak": 10000}}
+        {"type": "Cancel", "id": 2}
+        {"type": "Limit", "order": {"direction": "Sell", "id": 9, "price": 98, "quantity": 90000}}
+        {"type": "Iceberg", "order": {"direction": "Buy", "id": 10, "price": 102, "quantity": 45000, "peak": 2000}}
+        {"type": "Cancel", "id": 5}
+    "#;
+
+    enum Step {
+        Submit(Order),
+        Cancel(u64)
+    }
+
+    fn state(content: &OrderbookContent) -> Vec<(OrderSide, u64, u64, u64, u64, Option<IcebergOrder>)> {
+        content.buy_orders
+            .iter()
+            .chain(content.sell_orders.iter())
+            .map(|order| {
+                let order_key = order.order_key;
+                (order_key.order_side, order_key.id, order_key.price, order_key.timestamp, order.quantity, order.iceberg)
+            })
+            .collect()
+    }
+
+    fn run_both(steps: Vec<Step>, context: &str) {
+        let mut orderbook = Orderbook::new();
+        let mut reference = ReferenceBook::new();
+        for (index, step) in steps.into_iter().enumerate() {
+            match step {
+                Step::Submit(order) => assert_eq!(
+                    orderbook.process_order(&mut order.clone()),
+                    reference.process_order(&mut order.clone()),
+                    "{} step {}: fills differ",
+                    context,
+                    index + 1
+                ),
+                Step::Cancel(id) => assert_eq!(
+                    orderbook.cancel_order(id).map(|order| order.order_key.id),
+                    reference.cancel_order(id).map(|order| order.order_key.id),
+                    "{} step {}: cancels differ",
+                    context,
+                    index + 1
+                )
+            }
+        }
+        assert_eq!(state(&orderbook.get_orders()), state(&reference.get_orders()), "{}: final books differ", context);
+    }
+
+    #[test]
+    fn recorded_stream_matches_reference() {
+        let steps = RECORDED
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| match serde_json::from_str(line).unwrap() {
+                InboundMessage::Order(deserialized_order) => Step::Submit(parse_order(deserialized_order)),
+                InboundMessage::Command(DeserializedCommand::Cancel {id}) => Step::Cancel(id),
+                InboundMessage::Command(command) => panic!("unexpected command {:?}", command)
+            })
+            .collect();
+        run_both(steps, "recorded");
+    }
+
+    #[test]
+    fn random_streams_match_reference() {
+        for seed in 0..100 {
+            let mut rng = Rng::new(seed);
+            let steps = (1..=300)
+                .map(|id| match rng.below(10) {
+                    0 => Step::Cancel(1 + rng.below(id)),
+                    _ => Step::Submit(random_order(&mut rng, id))
+                })
+                .collect();
+            run_both(steps, &format!("seed {}", seed));
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+
+use super::order::{IcebergOrder, Order, OrderKey, OrderSide};
+use super::orderbook::OrderStatus;
+
+pub const SNAPSHOT_VERSION: u32 = 3;
+
+// `Order` serializes to the book output format, which leaves out the queue
+// timestamp, the side and the iceberg reload state. A snapshot needs all three
+// for priority to survive a restart, and one derive cannot produce both
+// formats, so orders are saved as order states instead of deriving
+// `Deserialize` on `Order` for a format it could not read back.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderState {
+    pub id: u64,
+    pub price: u64,
+    pub timestamp: u64,
+    pub order_side: OrderSide,
+    pub quantity: u64,
+    pub iceberg: Option<IcebergOrder>,
+    pub filled_quantity: u64
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderbookSnapshot {
+    pub version: u32,
+    pub time_counter: u64,
+    pub orders: Vec<OrderState>,
+    pub terminal: Vec<OrderStatus>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    UnsupportedVersion(u32),
+    DuplicateOrderId(u64),
+    TimestampAhead {
+        id: u64,
+        timestamp: u64
+    }
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::UnsupportedVersion(version) => write!(f, "unsupported snapshot version {}", version),
+            SnapshotError::DuplicateOrderId(id) => write!(f, "duplicate order id {} in snapshot", id),
+            SnapshotError::TimestampAhead {id, timestamp} =>
+                write!(f, "order {} has timestamp {} beyond the snapshot time counter", id, timestamp)
+        }
+    }
+}
+
+impl From<Order> for OrderState {
+    fn from(order: Order) -> Self {
+        OrderState {
+            id: order.order_key.id,
+            price: order.order_key.price,
+            timestamp: order.order_key.timestamp,
+            order_side: order.order_key.order_side,
+            quantity: order.quantity,
+            iceberg: order.iceberg,
+            filled_quantity: 0
+        }
+    }
+}
+
+impl From<OrderState> for Order {
+    fn from(order_state: OrderState) -> Self {
+        Order {
+            order_key: OrderKey {
+                id: order_state.id,
+                price: order_state.price,
+                timestamp: order_state.timestamp,
+                order_side: order_state.order_side
+            },
+            quantity: order_state.quantity,
+            iceberg: order_state.iceberg
+        }
+    }
+}
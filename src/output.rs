@@ -0,0 +1,179 @@
+use std::io::{self, Write};
+use std::str::FromStr;
+use serde::{Serialize};
+
+use crate::exchange::ExchangeOutput;
+use crate::market_data::depth::Depth;
+use crate::market_data::itch::encode::ItchEncoder;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OutputFormat {
+    Book,
+    L2,
+    Deltas,
+    Fills,
+    Itch
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct OutputOptions {
+    pub format: OutputFormat,
+    pub pretty: bool,
+    pub separators: bool,
+    pub depth_levels: Option<usize>
+}
+
+pub struct OutputWriter<W: Write> {
+    writer: W,
+    options: OutputOptions,
+    previous_depth: Depth,
+    itch_encoder: ItchEncoder
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "book" => Ok(OutputFormat::Book),
+            "l2" => Ok(OutputFormat::L2),
+            "deltas" => Ok(OutputFormat::Deltas),
+            "fills" => Ok(OutputFormat::Fills),
+            "itch" => Ok(OutputFormat::Itch),
+            _ => Err(format!("unknown output format {}", format))
+        }
+    }
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        OutputOptions {
+            format: OutputFormat::Book,
+            pretty: false,
+            separators: true,
+            depth_levels: None
+        }
+    }
+}
+
+impl<W: Write> OutputWriter<W> {
+    pub fn new(writer: W, options: OutputOptions) -> Self {
+        OutputWriter {writer, options, previous_depth: Depth::default(), itch_encoder: ItchEncoder::new()}
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    pub fn write_value<T: Serialize>(&mut self, value: &T) -> io::Result<()> {
+        let line = if self.options.pretty {
+            serde_json::to_string_pretty(value)
+        } else {
+            serde_json::to_string(value)
+        };
+        writeln!(self.writer, "{}", line.map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?)
+    }
+
+    // Errors would corrupt a binary feed, so they go to stderr instead.
+    pub fn write_error(&mut self, error: &str) -> io::Result<()> {
+        if self.options.format == OutputFormat::Itch {
+            return writeln!(io::stderr(), "{}", error);
+        }
+        writeln!(self.writer, "{}", error)
+    }
+
+    // Writes the result of one inbound message, followed by a blank separator
+    // line unless separators are disabled or nothing was written.
+    pub fn write_outputs(&mut self, outputs: &[ExchangeOutput]) -> io::Result<()> {
+        if self.options.format == OutputFormat::Itch {
+            let encoded = self.itch_encoder.encode(outputs);
+            return self.writer.write_all(&encoded);
+        }
+        let mut written = 0;
+        for output in outputs {
+            match (self.options.format, output) {
+                (OutputFormat::Book, _) => self.write_value(output)?,
+                (OutputFormat::L2, ExchangeOutput::Book(content)) => {
+                    let depth = Depth::from_content(content, self.options.depth_levels);
+                    self.write_value(&depth)?;
+                }
+                (OutputFormat::Deltas, ExchangeOutput::Book(content)) => {
+                    let depth = Depth::from_content(content, self.options.depth_levels);
+                    let updates = depth.deltas(&self.previous_depth);
+                    for update in updates.iter() {
+                        self.write_value(update)?;
+                    }
+                    written += updates.len();
+                    self.previous_depth = depth;
+                    continue;
+                }
+                (OutputFormat::Fills, ExchangeOutput::Fill(_)) => self.write_value(output)?,
+                (OutputFormat::Fills, _) | (OutputFormat::Itch, _) => continue,
+                (_, output) => self.write_value(output)?
+            }
+            written += 1;
+        }
+        if self.options.separators && written > 0 {
+            writeln!(self.writer)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn run(options: OutputOptions, messages: &[&str]) -> String {
+        let mut exchange = Exchange::new(ExchangeConfig::default());
+        let mut output_writer = OutputWriter::new(Vec::new(), options);
+        for json in messages {
+            let outputs = exchange.handle(serde_json::from_str(json).unwrap());
+            output_writer.write_outputs(&outputs).unwrap();
+        }
+        String::from_utf8(output_writer.into_inner()).unwrap()
+    }
+
+    const MESSAGES: [&str; 3] = [
+        r#"{"type": "Limit", "order": {"direction": "Buy", "id": 1, "price": 100, "quantity": 30}}"#,
+        r#"{"type": "Limit", "order": {"direction": "Buy", "id": 2, "price": 100, "quantity": 10}}"#,
+        r#"{"type": "Limit", "order": {"direction": "Sell", "id": 3, "price": 100, "quantity": 40}}"#
+    ];
+
+    #[test]
+    fn l2_without_separators() {
+        let options = OutputOptions {format: OutputFormat::L2, separators: false, ..OutputOptions::default()};
+        let output = run(options, &MESSAGES[..2]);
+        assert_eq!(output, concat!(
+            r#"{"bids":[{"price":100,"quantity":30,"orderCount":1}],"asks":[]}"#, "\n",
+            r#"{"bids":[{"price":100,"quantity":40,"orderCount":2}],"asks":[]}"#, "\n"
+        ));
+    }
+
+    #[test]
+    fn deltas_and_fills_only() {
+        let options = OutputOptions {format: OutputFormat::Deltas, ..OutputOptions::default()};
+        let output = run(options, &MESSAGES);
+        assert_eq!(output.lines().filter(|line| line.contains("\"side\"")).count(), 3);
+        assert!(output.ends_with("\n{\"buyOrderId\":2,\"sellOrderId\":3,\"price\":100,\"quantity\":10,\"takerSide\":\"Sell\",\"buyFee\":0,\"sellFee\":0}\n\n"));
+
+        let options = OutputOptions {format: OutputFormat::Fills, ..OutputOptions::default()};
+        let output = run(options, &MESSAGES);
+        assert_eq!(output.lines().count(), 3);
+        assert!(output.starts_with("{\"buyOrderId\":1,"));
+    }
+
+    #[test]
+    fn itch_feed() {
+        let options = OutputOptions {format: OutputFormat::Itch, ..OutputOptions::default()};
+        let mut exchange = Exchange::new(ExchangeConfig::default());
+        let mut output_writer = OutputWriter::new(Vec::new(), options);
+        for json in MESSAGES.iter() {
+            output_writer.write_outputs(&exchange.handle(serde_json::from_str(json).unwrap())).unwrap();
+        }
+
+        let messages = decode_stream(&output_writer.into_inner()).unwrap();
+        let types: Vec<u8> = messages.iter().map(|(_, message)| message.message_type()).collect();
+        assert_eq!(types, b"AAEPEP".to_vec());
+    }
+}
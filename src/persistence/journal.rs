@@ -0,0 +1,217 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use crate::matching_engine::parse::InboundMessage;
+
+// Each record is one line: `<sequence>\t<crc32 of payload, hex>\t<payload json>`.
+// A record is only durable once its trailing newline has been synced, so an
+// incomplete last line is treated as a torn write and dropped on recovery.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalRecord {
+    pub sequence: u64,
+    pub message: InboundMessage
+}
+
+#[derive(Debug)]
+pub enum JournalError {
+    Io(io::Error),
+    Corrupt {
+        line: usize,
+        reason: String
+    }
+}
+
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    next_sequence: u64
+}
+
+impl fmt::Display for JournalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JournalError::Io(error) => write!(f, "journal io error: {}", error),
+            JournalError::Corrupt {line, reason} => write!(f, "journal corrupt at line {}: {}", line, reason)
+        }
+    }
+}
+
+impl From<io::Error> for JournalError {
+    fn from(error: io::Error) -> Self {
+        JournalError::Io(error)
+    }
+}
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+impl Journal {
+    // Opens the journal for appending, truncating a torn trailing record left
+    // behind by a crash so that new records start on a clean line.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Journal, JournalError> {
+        let path = path.as_ref().to_path_buf();
+        let (records, valid_length) = match File::open(&path) {
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => (Vec::new(), 0),
+            Err(error) => return Err(error.into()),
+            Ok(file) => read_records(file)?
+        };
+
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        file.set_len(valid_length)?;
+        let next_sequence = records.last().map_or(1, |record| record.sequence + 1);
+        Ok(Journal {path, file, next_sequence})
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Vec<JournalRecord>, JournalError> {
+        Ok(read_records(File::open(path)?)?.0)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn next_sequence(&self) -> u64 {
+        self.next_sequence
+    }
+
+    pub fn append(&mut self, message: &InboundMessage) -> Result<u64, JournalError> {
+        let payload = serde_json::to_string(message)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        let sequence = self.next_sequence;
+        let line = format!("{}\t{:08x}\t{}\n", sequence, crc32(payload.as_bytes()), payload);
+
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()?;
+        self.next_sequence += 1;
+        Ok(sequence)
+    }
+}
+
+fn read_records(file: File) -> Result<(Vec<JournalRecord>, u64), JournalError> {
+    let mut reader = BufReader::new(file);
+    let mut records = Vec::new();
+    let mut valid_length = 0;
+    let mut line = String::new();
+
+    for line_number in 1.. {
+        line.clear();
+        let length = reader.read_line(&mut line)?;
+        if length == 0 || !line.ends_with('\n') {
+            break;
+        }
+
+        let expected_sequence = records.last().map_or(1, |record: &JournalRecord| record.sequence + 1);
+        let record = parse_record(line.trim_end_matches('\n'), expected_sequence)
+            .map_err(|reason| JournalError::Corrupt {line: line_number, reason})?;
+        records.push(record);
+        valid_length += length as u64;
+    }
+    Ok((records, valid_length))
+}
+
+fn parse_record(line: &str, expected_sequence: u64) -> Result<JournalRecord, String> {
+    let mut fields = line.splitn(3, '\t');
+    let (sequence, checksum, payload) = match (fields.next(), fields.next(), fields.next()) {
+        (Some(sequence), Some(checksum), Some(payload)) => (sequence, checksum, payload),
+        _ => return Err("missing fields".to_string())
+    };
+
+    let sequence = sequence.parse::<u64>().map_err(|error| error.to_string())?;
+    if sequence != expected_sequence {
+        return Err(format!("expected sequence {}, found {}", expected_sequence, sequence));
+    }
+    let checksum = u32::from_str_radix(checksum, 16).map_err(|error| error.to_string())?;
+    if checksum != crc32(payload.as_bytes()) {
+        return Err(format!("checksum mismatch for sequence {}", sequence));
+    }
+    let message = serde_json::from_str(payload).map_err(|error| error.to_string())?;
+    Ok(JournalRecord {sequence, message})
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Write;
+    use crate::*;
+    use crate::test_support::{message, temp_path};
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn recover_identical_orderbook() {
+        let path = temp_path("recover.journal");
+        let messages = [
+            r#"{"type": "Iceberg", "order": {"direction": "Sell", "id": 5, "price": 100, "quantity": 300, "peak": 25}}"#,
+            r#"{"type": "Limit", "order": {"direction": "Sell", "id": 6, "price": 100, "quantity": 40}}"#,
+            r#"{"type": "Positions"}"#,
+            r#"{"type": "Limit", "order": {"direction": "Buy", "id": 1, "price": 100, "quantity": 60}}"#,
+            r#"{"type": "Cancel", "id": 6}"#
+        ];
+
+        let mut exchange = Exchange::recover(ExchangeConfig::default(), &path).unwrap();
+        messages.iter().for_each(|json| { exchange.handle(message(json)); });
+        assert_eq!(Journal::read(&path).unwrap().len(), 4);
+
+        let recovered = Exchange::recover(ExchangeConfig::default(), &path).unwrap();
+        assert_eq!(recovered.orderbook().get_orders().sell_orders, exchange.orderbook().get_orders().sell_orders);
+        assert_eq!(recovered.orderbook().time_counter(), exchange.orderbook().time_counter());
+        assert_eq!(recovered.journal().unwrap().next_sequence(), 5);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn journal_only_accepted_commands() {
+        let path = temp_path("accepted.journal");
+        let messages = [
+            r#"{"type": "Limit", "order": {"direction": "Buy", "id": 1, "price": 100, "quantity": 10}}"#,
+            r#"{"type": "Limit", "order": {"direction": "Buy", "id": 1, "price": 99, "quantity": 10}}"#,
+            r#"{"type": "Limit", "order": {"direction": "Buy", "id": 2, "price": 99, "quantity": 0}}"#,
+            r#"{"type": "Cancel", "id": 3}"#,
+            r#"{"type": "Replace", "id": 3, "price": 99, "quantity": 5}"#,
+            r#"{"type": "Deposit", "account": "a", "asset": "USD", "amount": 5}"#,
+            r#"{"type": "Replace", "id": 1, "price": 101, "quantity": 5}"#
+        ];
+
+        let mut exchange = Exchange::recover(ExchangeConfig::default(), &path).unwrap();
+        messages.iter().for_each(|json| { exchange.handle(message(json)); });
+        let records = Journal::read(&path).unwrap();
+        assert_eq!(records.iter().map(|record| record.message.clone()).collect::<Vec<_>>(), vec![message(messages[0]), message(messages[6])]);
+
+        let recovered = Exchange::recover(ExchangeConfig::default(), &path).unwrap();
+        assert_eq!(recovered.orderbook().get_orders().buy_orders, exchange.orderbook().get_orders().buy_orders);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn torn_tail_is_dropped_and_corruption_reported() {
+        let path = temp_path("torn.journal");
+        let mut journal = Journal::open(&path).unwrap();
+        journal.append(&message(r#"{"type": "Cancel", "id": 1}"#)).unwrap();
+        fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"2\t0000").unwrap();
+
+        let journal = Journal::open(&path).unwrap();
+        assert_eq!(journal.next_sequence(), 2);
+        assert_eq!(Journal::read(&path).unwrap().len(), 1);
+
+        fs::write(&path, "1\tdeadbeef\t{\"type\":\"Cancel\",\"id\":1}\n").unwrap();
+        match Journal::read(&path) {
+            Err(JournalError::Corrupt {line: 1, ..}) => (),
+            other => panic!("unexpected result {:?}", other)
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,241 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+
+use crate::accounting::balances::BalanceSnapshot;
+use crate::accounting::fees::FeeSnapshot;
+use crate::accounting::positions::PositionSnapshot;
+use crate::exchange::TradeRecord;
+use crate::market_data::statistics::SessionStatistics;
+use crate::matching_engine::snapshot::{OrderbookSnapshot, SnapshotError};
+use super::journal::JournalError;
+
+// `journalSequence` is the last journal record already reflected in the
+// snapshot; recovery only replays the records that follow it. `balances` is
+// only present for exchanges with spot balances.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotFile {
+    pub journal_sequence: u64,
+    pub orderbook: OrderbookSnapshot,
+    pub positions: PositionSnapshot,
+    pub fees: FeeSnapshot,
+    pub balances: Option<BalanceSnapshot>,
+    pub statistics: SessionStatistics,
+    pub trades: Vec<TradeRecord>,
+    pub next_trade_sequence: u64
+}
+
+#[derive(Debug)]
+pub enum RecoveryError {
+    Io(io::Error),
+    Journal(JournalError),
+    Snapshot(SnapshotError),
+    Unsupported(String)
+}
+
+impl fmt::Display for RecoveryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecoveryError::Io(error) => write!(f, "snapshot io error: {}", error),
+            RecoveryError::Journal(error) => error.fmt(f),
+            RecoveryError::Snapshot(error) => error.fmt(f),
+            RecoveryError::Unsupported(reason) => write!(f, "cannot recover from snapshot: {}", reason)
+        }
+    }
+}
+
+impl From<io::Error> for RecoveryError {
+    fn from(error: io::Error) -> Self {
+        RecoveryError::Io(error)
+    }
+}
+
+impl From<JournalError> for RecoveryError {
+    fn from(error: JournalError) -> Self {
+        RecoveryError::Journal(error)
+    }
+}
+
+impl From<SnapshotError> for RecoveryError {
+    fn from(error: SnapshotError) -> Self {
+        RecoveryError::Snapshot(error)
+    }
+}
+
+// Writes to a sibling temporary file first so a crash never leaves a
+// half-written snapshot in place of the previous one. The directory is synced
+// after the rename so that the new entry is durable too.
+pub fn save_snapshot<P: AsRef<Path>>(path: P, snapshot_file: &SnapshotFile) -> io::Result<()> {
+    let path = path.as_ref();
+    let mut temporary_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "snapshot path has no file name"))?
+        .to_os_string();
+    temporary_name.push(".tmp");
+    let temporary_path = path.with_file_name(temporary_name);
+
+    let content = serde_json::to_vec(snapshot_file)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+    fs::write(&temporary_path, content)?;
+    fs::File::open(&temporary_path)?.sync_all()?;
+    fs::rename(&temporary_path, path)?;
+    sync_directory(path)
+}
+
+#[cfg(unix)]
+fn sync_directory(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => fs::File::open(directory)?.sync_all(),
+        _ => fs::File::open(".")?.sync_all()
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_directory(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+pub fn load_snapshot<P: AsRef<Path>>(path: P) -> io::Result<SnapshotFile> {
+    let content = fs::read(path)?;
+    serde_json::from_slice(&content).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::*;
+    use crate::test_support::{message, temp_path};
+
+    #[test]
+    fn restore_preserves_queue_priority() {
+        let mut orderbook = Orderbook::new();
+        (0..3).for_each(|i| {
+            orderbook.process_order(&mut Order {
+                order_key: OrderKey {id: i + 1, timestamp: 0, price: 100, order_side: OrderSide::Sell},
+                quantity: 100,
+                iceberg: Some(IcebergOrder {hidden_quantity: 100 + i * 100, peak_size: 100})
+            });
+        });
+        orderbook.process_order(&mut Order {
+            order_key: OrderKey {id: 4, timestamp: 0, price: 100, order_side: OrderSide::Buy},
+            quantity: 150,
+            iceberg: None
+        });
+
+        let snapshot = orderbook.snapshot();
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let mut restored = Orderbook::restore(serde_json::from_str(&json).unwrap()).unwrap();
+        assert_eq!(restored.get_orders().sell_orders, orderbook.get_orders().sell_orders);
+        assert_eq!(restored.time_counter(), orderbook.time_counter());
+
+        let mut buy_order = Order {
+            order_key: OrderKey {id: 5, timestamp: 0, price: 100, order_side: OrderSide::Buy},
+            quantity: 500,
+            iceberg: None
+        };
+        assert_eq!(restored.process_order(&mut buy_order.clone()), orderbook.process_order(&mut buy_order));
+        assert_eq!(restored.get_orders().sell_orders, orderbook.get_orders().sell_orders);
+    }
+
+    #[test]
+    fn save_next_to_unrelated_tmp_files() {
+        let path = temp_path("save.snapshot");
+        let unrelated_path = temp_path("save.tmp");
+        let temporary_path = temp_path("save.snapshot.tmp");
+        fs::write(&unrelated_path, "unrelated").unwrap();
+
+        Exchange::new(ExchangeConfig::default()).save_snapshot(&path).unwrap();
+        assert_eq!(load_snapshot(&path).unwrap().journal_sequence, 0);
+        assert_eq!(fs::read_to_string(&unrelated_path).unwrap(), "unrelated");
+        assert!(!temporary_path.exists());
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&unrelated_path).unwrap();
+    }
+
+    #[test]
+    fn restore_rejects_invalid_snapshots() {
+        let order_state = OrderState {id: 1, price: 100, timestamp: 1, order_side: OrderSide::Buy, quantity: 5, iceberg: None, filled_quantity: 0};
+        let snapshot = OrderbookSnapshot {version: SNAPSHOT_VERSION + 1, time_counter: 1, orders: vec![], terminal: vec![]};
+        assert_eq!(Orderbook::restore(snapshot).err(), Some(SnapshotError::UnsupportedVersion(SNAPSHOT_VERSION + 1)));
+
+        let snapshot = OrderbookSnapshot {version: SNAPSHOT_VERSION, time_counter: 1, orders: vec![order_state, order_state], terminal: vec![]};
+        assert_eq!(Orderbook::restore(snapshot).err(), Some(SnapshotError::DuplicateOrderId(1)));
+    }
+
+    #[test]
+    fn recover_from_snapshot_and_journal_tail() {
+        let snapshot_path = temp_path("recover.snapshot");
+        let journal_path = temp_path("recover-snapshot.journal");
+        let messages = [
+            r#"{"type": "Iceberg", "order": {"direction": "Sell", "id": 1, "price": 100, "quantity": 300, "peak": 50, "account": "a"}}"#,
+            r#"{"type": "Limit", "order": {"direction": "Sell", "id": 2, "price": 100, "quantity": 40, "account": "b"}}"#,
+            r#"{"type": "Limit", "order": {"direction": "Buy", "id": 3, "price": 100, "quantity": 60, "account": "c"}}"#,
+            r#"{"type": "Limit", "order": {"direction": "Buy", "id": 4, "price": 100, "quantity": 70, "account": "c"}}"#
+        ];
+
+        let mut exchange = Exchange::recover(ExchangeConfig::default(), &journal_path).unwrap();
+        messages[..3].iter().for_each(|json| { exchange.handle(message(json)); });
+        exchange.save_snapshot(&snapshot_path).unwrap();
+        messages[3..].iter().for_each(|json| { exchange.handle(message(json)); });
+
+        assert_eq!(load_snapshot(&snapshot_path).unwrap().journal_sequence, 3);
+        let recovered = Exchange::recover_with_snapshot(ExchangeConfig::default(), &snapshot_path, &journal_path).unwrap();
+        assert_eq!(recovered.orderbook().get_orders().sell_orders, exchange.orderbook().get_orders().sell_orders);
+        assert_eq!(recovered.orderbook().time_counter(), exchange.orderbook().time_counter());
+        assert_eq!(recovered.journal().unwrap().next_sequence(), 5);
+
+        // State built from the fills before the snapshot survives as well.
+        assert_eq!(recovered.orderbook().get_order(1), exchange.orderbook().get_order(1));
+        assert_eq!(recovered.orderbook().get_order(1).unwrap().filled_quantity, 90);
+        assert_eq!(recovered.orderbook().get_order(3), exchange.orderbook().get_order(3));
+        assert_eq!(recovered.orderbook().get_order(3).unwrap().state, ExecutionState::Filled);
+        assert_eq!(recovered.position_keeper().positions(), exchange.position_keeper().positions());
+        assert_eq!(recovered.statistics(), exchange.statistics());
+        assert_eq!(recovered.trades_since(0), exchange.trades_since(0));
+
+        fs::remove_file(&snapshot_path).unwrap();
+        fs::remove_file(&journal_path).unwrap();
+    }
+
+    #[test]
+    fn recover_spot_balances_from_snapshot() {
+        let snapshot_path = temp_path("recover-spot.snapshot");
+        let journal_path = temp_path("recover-spot.journal");
+        let config = ExchangeConfig {spot_assets: Some(("BTC".to_string(), "USD".to_string())), ..ExchangeConfig::default()};
+        let messages = [
+            r#"{"type": "Deposit", "account": "a", "asset": "BTC", "amount": 10}"#,
+            r#"{"type": "Deposit", "account": "b", "asset": "USD", "amount": 2000}"#,
+            r#"{"type": "Limit", "order": {"direction": "Sell", "id": 1, "price": 100, "quantity": 10, "account": "a"}}"#,
+            r#"{"type": "Limit", "order": {"direction": "Buy", "id": 2, "price": 110, "quantity": 4, "account": "b"}}"#,
+            r#"{"type": "Limit", "order": {"direction": "Buy", "id": 3, "price": 100, "quantity": 6, "account": "b"}}"#
+        ];
+
+        let mut exchange = Exchange::recover(config.clone(), &journal_path).unwrap();
+        messages[..4].iter().for_each(|json| { exchange.handle(message(json)); });
+        exchange.save_snapshot(&snapshot_path).unwrap();
+        messages[4..].iter().for_each(|json| { exchange.handle(message(json)); });
+
+        let mut recovered = Exchange::recover_with_snapshot(config, &snapshot_path, &journal_path).unwrap();
+        assert_eq!(recovered.balance_ledger().unwrap().balances(), exchange.balance_ledger().unwrap().balances());
+        assert_eq!(recovered.balance_ledger().unwrap().balance("a", "USD"), Balance {available: 1000, reserved: 0});
+
+        // The asset totals are rebuilt from the balances.
+        let deposit = format!(r#"{{"type": "Deposit", "account": "c", "asset": "USD", "amount": {}}}"#, u64::MAX - 1999);
+        match recovered.handle(message(&deposit)).as_slice() {
+            [ExchangeOutput::Error(_)] => (),
+            other => panic!("unexpected outputs {:?}", other)
+        }
+
+        assert!(matches!(
+            Exchange::recover_with_snapshot(ExchangeConfig::default(), &snapshot_path, &journal_path),
+            Err(RecoveryError::Unsupported(_))
+        ));
+
+        fs::remove_file(&snapshot_path).unwrap();
+        fs::remove_file(&journal_path).unwrap();
+    }
+}
@@ -0,0 +1,169 @@
+use std::io::{self, BufRead, Read};
+use serde::{Deserialize, Serialize};
+
+use crate::exchange::{Exchange, ExchangeOutput};
+use crate::matching_engine::binary::BinaryReader;
+use crate::matching_engine::order::FillEvent;
+use crate::matching_engine::parse::InboundMessage;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplaySummary {
+    pub sequence: u64,
+    pub fills: Vec<FillEvent>
+}
+
+// A message line may carry the time of the event in milliseconds next to the
+// message fields, e.g. `{"timestamp": 1500, "type": "Cancel", "id": 1}`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimedMessage {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    #[serde(flatten)]
+    pub message: InboundMessage
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ReplayedMessage {
+    pub sequence: u64,
+    pub timestamp: Option<u64>
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FillMismatch {
+    pub index: usize,
+    pub expected: Option<FillEvent>,
+    pub actual: Option<FillEvent>
+}
+
+// Feeds every message line through the exchange, numbering the successfully
+// parsed ones from 1. Replay stops after the message numbered `stop_at`.
+pub fn replay<R, F>(input: R, exchange: &mut Exchange, stop_at: Option<u64>, mut on_message: F) -> io::Result<ReplaySummary>
+where
+    R: BufRead,
+    F: FnMut(ReplayedMessage, Result<&[ExchangeOutput], serde_json::Error>)
+{
+    let mut summary = ReplaySummary {sequence: 0, fills: Vec::new()};
+    for line in input.lines() {
+        if stop_at.is_some_and(|stop_at| summary.sequence >= stop_at) {
+            break;
+        }
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match serde_json::from_str::<TimedMessage>(&line) {
+            Err(error) => on_message(ReplayedMessage {sequence: summary.sequence, timestamp: None}, Err(error)),
+            Ok(timed_message) => {
+                let outputs = apply(&mut summary, exchange, timed_message.message);
+                on_message(ReplayedMessage {sequence: summary.sequence, timestamp: timed_message.timestamp}, Ok(&outputs));
+            }
+        }
+    }
+    Ok(summary)
+}
+
+// Same as `replay` for binary order entry frames. A malformed frame leaves no
+// way to find the start of the next one, so it ends the replay with an error.
+pub fn replay_binary<R, F>(input: R, exchange: &mut Exchange, stop_at: Option<u64>, mut on_message: F) -> io::Result<ReplaySummary>
+where
+    R: Read,
+    F: FnMut(u64, &[ExchangeOutput])
+{
+    let mut summary = ReplaySummary {sequence: 0, fills: Vec::new()};
+    let mut reader = BinaryReader::new(input);
+    while stop_at.map_or(true, |stop_at| summary.sequence < stop_at) {
+        let message = match reader.next_message()? {
+            None => break,
+            Some(message) => message.to_inbound()
+        };
+        let outputs = apply(&mut summary, exchange, message);
+        on_message(summary.sequence, &outputs);
+    }
+    Ok(summary)
+}
+
+fn apply(summary: &mut ReplaySummary, exchange: &mut Exchange, message: InboundMessage) -> Vec<ExchangeOutput> {
+    summary.sequence += 1;
+    let outputs = exchange.handle(message);
+    for output in outputs.iter() {
+        if let ExchangeOutput::Fill(annotated_fill) = output {
+            summary.fills.push(annotated_fill.fill_event);
+        }
+    }
+    outputs
+}
+
+// Picks the JSON fill lines out of recorded program output; book dumps,
+// reports and blank separator lines are ignored.
+pub fn read_fills<R: BufRead>(input: R) -> io::Result<Vec<FillEvent>> {
+    let mut fills = Vec::new();
+    for line in input.lines() {
+        if let Ok(fill_event) = serde_json::from_str::<FillEvent>(&line?) {
+            fills.push(fill_event);
+        }
+    }
+    Ok(fills)
+}
+
+pub fn diff_fills(expected: &[FillEvent], actual: &[FillEvent]) -> Vec<FillMismatch> {
+    (0..std::cmp::max(expected.len(), actual.len()))
+        .map(|index| FillMismatch {index, expected: expected.get(index).copied(), actual: actual.get(index).copied()})
+        .filter(|mismatch| mismatch.expected != mismatch.actual)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    const SESSION: &str = r#"{"type": "Limit", "order": {"direction": "Buy", "id": 1, "price": 100, "quantity": 30}}
+not json
+
+{"timestamp": 1500, "type": "Limit", "order": {"direction": "Sell", "id": 2, "price": 100, "quantity": 10}}
+{"type": "Limit", "order": {"direction": "Sell", "id": 3, "price": 99, "quantity": 10}}
+"#;
+
+    #[test]
+    fn replay_stops_at_sequence() {
+        let mut exchange = Exchange::new(ExchangeConfig::default());
+        let mut errors = 0;
+        let mut replayed = Vec::new();
+        let summary = replay(SESSION.as_bytes(), &mut exchange, Some(2), |replayed_message, outputs| {
+            match outputs {
+                Err(_) => errors += 1,
+                Ok(_) => replayed.push(replayed_message)
+            }
+        }).unwrap();
+
+        assert_eq!(errors, 1);
+        assert_eq!(replayed, vec![
+            ReplayedMessage {sequence: 1, timestamp: None},
+            ReplayedMessage {sequence: 2, timestamp: Some(1500)}
+        ]);
+        assert_eq!(summary.sequence, 2);
+        assert_eq!(summary.fills, vec![FillEvent {buy_order_id: 1, sell_order_id: 2, price: 100, quantity: 10}]);
+        assert_eq!(exchange.orderbook().get_orders().buy_orders[0].quantity, 20);
+    }
+
+    #[test]
+    fn diff_against_recorded_output() {
+        let recorded = r#"{"buyOrders":[{"id":1,"price":100,"quantity":30}],"sellOrders":[]}
+
+{"buyOrders":[{"id":1,"price":100,"quantity":20}],"sellOrders":[]}
+{"buyOrderId":1,"sellOrderId":2,"price":100,"quantity":10,"takerSide":"Sell","buyFee":0,"sellFee":0}
+{"buyOrderId":1,"sellOrderId":3,"price":99,"quantity":10}
+"#;
+        let expected = read_fills(recorded.as_bytes()).unwrap();
+        assert_eq!(expected.len(), 2);
+
+        let mut exchange = Exchange::new(ExchangeConfig::default());
+        let summary = replay(SESSION.as_bytes(), &mut exchange, None, |_, _| ()).unwrap();
+        assert_eq!(diff_fills(&expected, &summary.fills), vec![FillMismatch {
+            index: 1,
+            expected: Some(FillEvent {buy_order_id: 1, sell_order_id: 3, price: 99, quantity: 10}),
+            actual: Some(FillEvent {buy_order_id: 1, sell_order_id: 3, price: 100, quantity: 10})
+        }]);
+    }
+}
@@ -0,0 +1,228 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::matching_engine::order::{FillEvent, Order, OrderSide};
+use crate::matching_engine::orderbook::Orderbook;
+use crate::matching_engine::parse::{parse_order, DeserializedOrder, OrderCore};
+
+// A plain text matching test, one statement per line; `#` starts a comment.
+//
+//     BUY L id=1 100x15               limit order, price x quantity
+//     SELL ICE id=5 100x300 peak=25   iceberg order
+//     CANCEL id=1                     the order must be resting
+//     EXPECT FILL 1/5 100x25          next fill: buy id / sell id, price x quantity
+//     EXPECT BOOK BUY 1:100x10 3:98x100
+//     EXPECT BOOK SELL 5:100x25+250   orders in priority order, id:price x visible
+//                                     quantity, optionally +hidden quantity
+//
+// Every fill of an order has to be expected before the next order or the end
+// of the scenario.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScenarioError {
+    pub line: usize,
+    pub message: String
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+fn number(value: &str, what: &str) -> Result<u64, String> {
+    value.parse().map_err(|_| format!("invalid {} {}", what, value))
+}
+
+fn named(token: Option<&str>, name: &str) -> Result<u64, String> {
+    match token.and_then(|token| token.strip_prefix(name)).and_then(|value| value.strip_prefix('=')) {
+        Some(value) => number(value, name),
+        None => Err(format!("expected {}=<number>", name))
+    }
+}
+
+fn price_quantity(token: Option<&str>) -> Result<(u64, u64), String> {
+    match token.and_then(|token| token.split_once('x')) {
+        Some((price, quantity)) => Ok((number(price, "price")?, number(quantity, "quantity")?)),
+        None => Err("expected <price>x<quantity>".to_string())
+    }
+}
+
+fn end<'a, I: Iterator<Item = &'a str>>(mut tokens: I) -> Result<(), String> {
+    match tokens.next() {
+        None => Ok(()),
+        Some(token) => Err(format!("unexpected {}", token))
+    }
+}
+
+fn parse_side(token: &str) -> Result<OrderSide, String> {
+    match token {
+        "BUY" => Ok(OrderSide::Buy),
+        "SELL" => Ok(OrderSide::Sell),
+        _ => Err(format!("expected BUY or SELL, got {}", token))
+    }
+}
+
+fn parse_order_line<'a, I: Iterator<Item = &'a str>>(direction: OrderSide, mut tokens: I) -> Result<Order, String> {
+    let kind = tokens.next().ok_or("expected L or ICE")?;
+    let id = named(tokens.next(), "id")?;
+    let (price, quantity) = price_quantity(tokens.next())?;
+    let order_core = OrderCore {direction, id, price, quantity, account: String::new()};
+    let deserialized_order = match kind {
+        "L" => DeserializedOrder::Limit {order_core},
+        "ICE" => DeserializedOrder::Iceberg {order_core, peak: named(tokens.next(), "peak")?},
+        _ => return Err(format!("expected L or ICE, got {}", kind))
+    };
+    end(tokens)?;
+    Ok(parse_order(deserialized_order))
+}
+
+fn parse_fill<'a, I: Iterator<Item = &'a str>>(mut tokens: I) -> Result<FillEvent, String> {
+    let (buy_order_id, sell_order_id) = match tokens.next().and_then(|token| token.split_once('/')) {
+        Some((buy, sell)) => (number(buy, "id")?, number(sell, "id")?),
+        None => return Err("expected <buy id>/<sell id>".to_string())
+    };
+    let (price, quantity) = price_quantity(tokens.next())?;
+    end(tokens)?;
+    Ok(FillEvent {buy_order_id, sell_order_id, price, quantity})
+}
+
+fn describe(order: &Order) -> String {
+    match order.iceberg {
+        None => format!("{}:{}x{}", order.order_key.id, order.order_key.price, order.quantity),
+        Some(iceberg) => format!("{}:{}x{}+{}", order.order_key.id, order.order_key.price, order.quantity, iceberg.hidden_quantity)
+    }
+}
+
+// Entries without a hidden quantity match icebergs on their visible part.
+fn check_book<'a, I: Iterator<Item = &'a str>>(orderbook: &Orderbook, side: OrderSide, tokens: I) -> Result<(), String> {
+    let content = orderbook.get_orders();
+    let orders = match side {
+        OrderSide::Buy => content.buy_orders,
+        OrderSide::Sell => content.sell_orders
+    };
+    let expected: Vec<&str> = tokens.collect();
+    let matches = expected.len() == orders.len()
+        && expected.iter().zip(orders.iter()).all(|(entry, order)| {
+            let described = describe(order);
+            *entry == described || (!entry.contains('+') && described.starts_with(&format!("{}+", entry)))
+        });
+    match matches {
+        true => Ok(()),
+        false => Err(format!(
+            "expected {:?} book [{}], got [{}]",
+            side,
+            expected.join(" "),
+            orders.iter().map(describe).collect::<Vec<String>>().join(" ")
+        ))
+    }
+}
+
+struct Runner {
+    orderbook: Orderbook,
+    pending: Vec<FillEvent>
+}
+
+impl Runner {
+    fn unexpected_fills(&mut self) -> Result<(), String> {
+        match self.pending.last() {
+            None => Ok(()),
+            Some(fill_event) => Err(format!(
+                "unexpected fill {}/{} {}x{}",
+                fill_event.buy_order_id, fill_event.sell_order_id, fill_event.price, fill_event.quantity
+            ))
+        }
+    }
+
+    fn statement(&mut self, line: &str) -> Result<(), String> {
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some(side @ "BUY") | Some(side @ "SELL") => {
+                self.unexpected_fills()?;
+                let mut order = parse_order_line(parse_side(side)?, tokens)?;
+                self.pending = self.orderbook.process_order(&mut order);
+                self.pending.reverse();
+                Ok(())
+            }
+            Some("CANCEL") => {
+                let id = named(tokens.next(), "id")?;
+                end(tokens)?;
+                match self.orderbook.cancel_order(id) {
+                    None => Err(format!("order {} is not resting", id)),
+                    Some(_) => Ok(())
+                }
+            }
+            Some("EXPECT") => match tokens.next() {
+                Some("FILL") => {
+                    let expected = parse_fill(tokens)?;
+                    match self.pending.pop() {
+                        Some(actual) if actual == expected => Ok(()),
+                        Some(actual) => Err(format!(
+                            "expected fill {}/{} {}x{}, got {}/{} {}x{}",
+                            expected.buy_order_id, expected.sell_order_id, expected.price, expected.quantity,
+                            actual.buy_order_id, actual.sell_order_id, actual.price, actual.quantity
+                        )),
+                        None => Err("expected a fill, got none".to_string())
+                    }
+                }
+                Some("BOOK") => {
+                    let side = parse_side(tokens.next().unwrap_or(""))?;
+                    check_book(&self.orderbook, side, tokens)
+                }
+                other => Err(format!("expected FILL or BOOK, got {}", other.unwrap_or("nothing")))
+            },
+            Some(other) => Err(format!("unknown statement {}", other)),
+            None => Ok(())
+        }
+    }
+}
+
+// Stops at the first failing statement.
+pub fn run_scenario(scenario: &str) -> Result<(), ScenarioError> {
+    let mut runner = Runner {orderbook: Orderbook::new(), pending: Vec::new()};
+    let mut last_line = 0;
+    for (index, line) in scenario.lines().enumerate() {
+        last_line = index + 1;
+        let statement = line.split('#').next().unwrap_or("");
+        runner.statement(statement).map_err(|message| ScenarioError {line: last_line, message})?;
+    }
+    runner.unexpected_fills().map_err(|message| ScenarioError {line: last_line, message})
+}
+
+pub fn run_scenario_file<P: AsRef<Path>>(path: P) -> Result<(), ScenarioError> {
+    match fs::read_to_string(&path) {
+        Err(error) => Err(ScenarioError {line: 0, message: error.to_string()}),
+        Ok(scenario) => run_scenario(&scenario)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use crate::*;
+
+    #[test]
+    fn scenario_files() {
+        let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("scenarios");
+        let mut paths: Vec<_> = fs::read_dir(&directory).unwrap().map(|entry| entry.unwrap().path()).collect();
+        paths.sort();
+        assert!(!paths.is_empty());
+        for path in paths {
+            if let Err(error) = run_scenario_file(&path) {
+                panic!("{}: {}", path.display(), error);
+            }
+        }
+    }
+
+    #[test]
+    fn report_failing_line() {
+        let scenario = "BUY L id=1 100x15\nSELL L id=2 100x5\nEXPECT FILL 1/2 100x4\n";
+        assert_eq!(run_scenario(scenario), Err(ScenarioError {line: 3, message: "expected fill 1/2 100x4, got 1/2 100x5".to_string()}));
+        assert_eq!(run_scenario("BUY L id=1 100x15\nSELL L id=2 99x5").unwrap_err().message, "unexpected fill 1/2 100x5");
+        assert_eq!(run_scenario("BUY L id=1 100x15\nEXPECT BOOK BUY 1:100x10").unwrap_err().message, "expected Buy book [1:100x10], got [1:100x15]");
+        assert_eq!(run_scenario("BUY ICE id=1 100x15").unwrap_err().message, "expected peak=<number>");
+        assert_eq!(run_scenario("CANCEL id=4").unwrap_err().line, 1);
+    }
+}
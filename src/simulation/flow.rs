@@ -0,0 +1,154 @@
+use crate::matching_engine::order::OrderSide;
+use crate::matching_engine::parse::{DeserializedCommand, DeserializedOrder, InboundMessage, OrderCore};
+
+// xorshift64*, enough for reproducible streams without a dependency.
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    pub(crate) fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+
+    // Uniform in [0, 1).
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn normal(&mut self) -> f64 {
+        let (first, second) = (1.0 - self.unit(), self.unit());
+        (-2.0 * first.ln()).sqrt() * (2.0 * std::f64::consts::PI * second).cos()
+    }
+}
+
+// Prices are normally distributed `mean_offset` away from `mid` on the
+// passive side, so a smaller offset or a wider deviation crosses more often.
+// `peak_size` is the largest iceberg peak, peaks are drawn from 1 up to it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlowConfig {
+    pub seed: u64,
+    pub messages_per_second: f64,
+    pub mid: u64,
+    pub mean_offset: f64,
+    pub price_deviation: f64,
+    pub min_quantity: u64,
+    pub max_quantity: u64,
+    pub iceberg_ratio: f64,
+    pub peak_size: u64,
+    pub cancel_ratio: f64
+}
+
+impl Default for FlowConfig {
+    fn default() -> Self {
+        FlowConfig {
+            seed: 1,
+            messages_per_second: 10_000.0,
+            mid: 10_000,
+            mean_offset: 2.0,
+            price_deviation: 5.0,
+            min_quantity: 1,
+            max_quantity: 100,
+            iceberg_ratio: 0.1,
+            peak_size: 10,
+            cancel_ratio: 0.2
+        }
+    }
+}
+
+// `timestamp` is in microseconds since the start of the flow.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlowEvent {
+    pub timestamp: u64,
+    pub message: InboundMessage
+}
+
+// An endless stream of limit orders, icebergs and cancels with Poisson
+// arrivals. Order ids count up from 1; cancels pick one of the orders sent
+// so far, which may already have traded.
+pub struct OrderFlow {
+    config: FlowConfig,
+    rng: Rng,
+    elapsed: f64,
+    next_id: u64,
+    sent: Vec<u64>
+}
+
+impl OrderFlow {
+    pub fn new(config: FlowConfig) -> Self {
+        OrderFlow {rng: Rng::new(config.seed), config, elapsed: 0.0, next_id: 1, sent: Vec::new()}
+    }
+
+    fn next_order(&mut self) -> DeserializedOrder {
+        let direction = if self.rng.below(2) == 0 { OrderSide::Buy } else { OrderSide::Sell };
+        let offset = (self.config.mean_offset + self.rng.normal() * self.config.price_deviation).round() as i64;
+        let price = match direction {
+            OrderSide::Buy => self.config.mid as i64 - offset,
+            OrderSide::Sell => self.config.mid as i64 + offset
+        }.max(1) as u64;
+        let quantity_range = self.config.max_quantity.saturating_sub(self.config.min_quantity) + 1;
+        let quantity = self.config.min_quantity + self.rng.below(quantity_range);
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.sent.push(id);
+        let order_core = OrderCore {direction, id, price, quantity, account: String::new()};
+        match self.rng.unit() < self.config.iceberg_ratio {
+            false => DeserializedOrder::Limit {order_core},
+            true => DeserializedOrder::Iceberg {order_core, peak: 1 + self.rng.below(self.config.peak_size.max(1))}
+        }
+    }
+}
+
+impl Iterator for OrderFlow {
+    type Item = FlowEvent;
+
+    fn next(&mut self) -> Option<FlowEvent> {
+        self.elapsed += -(1.0 - self.rng.unit()).ln() / self.config.messages_per_second * 1_000_000.0;
+        let message = match self.sent.is_empty() || self.rng.unit() >= self.config.cancel_ratio {
+            true => InboundMessage::Order(self.next_order()),
+            false => {
+                let index = self.rng.below(self.sent.len() as u64) as usize;
+                InboundMessage::Command(DeserializedCommand::Cancel {id: self.sent.swap_remove(index)})
+            }
+        };
+        Some(FlowEvent {timestamp: self.elapsed as u64, message})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn reproducible_flow() {
+        let config = FlowConfig {cancel_ratio: 0.25, iceberg_ratio: 0.5, ..FlowConfig::default()};
+        let events: Vec<FlowEvent> = OrderFlow::new(config.clone()).take(2_000).collect();
+        assert_eq!(events, OrderFlow::new(config).take(2_000).collect::<Vec<FlowEvent>>());
+
+        let cancels = events.iter().filter(|event| matches!(event.message, InboundMessage::Command(DeserializedCommand::Cancel {..}))).count();
+        assert!((400..600).contains(&cancels), "{} cancels", cancels);
+        assert!(events.windows(2).all(|pair| pair[0].timestamp <= pair[1].timestamp));
+        // 2000 messages at 10000 per second take about 200ms.
+        assert!((150_000..250_000).contains(&events.last().unwrap().timestamp));
+
+        let mut exchange = Exchange::new(ExchangeConfig::default());
+        let mut fills = 0;
+        for event in events {
+            let outputs = exchange.handle(event.message);
+            assert!(!outputs.iter().any(|output| matches!(output, ExchangeOutput::Error(_))));
+            fills += outputs.iter().filter(|output| matches!(output, ExchangeOutput::Fill(_))).count();
+        }
+        assert!(fills > 0);
+        assert!(exchange.orderbook().best_buy_price() < exchange.orderbook().best_sell_price());
+    }
+}
@@ -0,0 +1,22 @@
+use std::fs;
+use std::path::PathBuf;
+
+use crate::matching_engine::order::{Order, OrderKey, OrderSide};
+use crate::matching_engine::parse::InboundMessage;
+
+// Fixtures shared by the unit tests of several modules.
+
+pub fn message(json: &str) -> InboundMessage {
+    serde_json::from_str(json).unwrap()
+}
+
+pub fn limit(id: u64, order_side: OrderSide, price: u64, quantity: u64) -> Order {
+    Order {order_key: OrderKey {id, timestamp: 0, price, order_side}, quantity, iceberg: None}
+}
+
+// Unique to the test process, with anything an earlier run left there removed.
+pub fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("orderbook-{}-{}", std::process::id(), name));
+    let _ = fs::remove_file(&path);
+    path
+}